@@ -0,0 +1,107 @@
+//! Play the part of asterisk in a single session against a running `FastAGI` server.
+//!
+//! Every command the server sends is printed. It is answered by the first matching `--rule`,
+//! or else by the status typed on stdin.
+use std::path::PathBuf;
+
+use blazing_agi::{client::AGIClientSession, AGIStatusGeneric, AGIVariableDump};
+use blazing_agi_cli::{
+    rules::{parse_status, Rule, Rules},
+    variables,
+};
+use clap::Parser;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use url::Url;
+
+/// Open a session to a FastAGI server, pretending to be asterisk.
+///
+/// Each command the server sends is printed. Commands matching a rule are answered
+/// automatically, all others are answered with the status you type. Typing only the result
+/// (e.g. `1 (value)`) is short for `200 result=1 (value)`, an empty line for `200 result=0`.
+/// Closing stdin (Ctrl-D) hangs up.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// The url to request, e.g. agi://localhost:4573/script
+    url: Url,
+    /// Set a variable of the request, e.g. `callerid=+4930123456`
+    #[arg(short = 'v', long = "var", value_name = "NAME=VALUE")]
+    variables: Vec<String>,
+    /// Add a custom argument. The first one becomes agi_arg_1 and so on
+    #[arg(short = 'a', long = "arg", value_name = "VALUE")]
+    args: Vec<String>,
+    /// Answer commands starting with PATTERN automatically. `*` matches anything
+    #[arg(short = 'r', long = "rule", value_name = "PATTERN => STATUS")]
+    rules: Vec<Rule>,
+    /// Read rules from a file, one per line
+    #[arg(short = 'f', long = "rules-file", value_name = "FILE")]
+    rules_file: Option<PathBuf>,
+    /// Do not ask on stdin: Answer commands no rule matches with this status
+    #[arg(short = 'd', long = "default", value_name = "STATUS", value_parser = parse_status)]
+    default: Option<AGIStatusGeneric>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let mut request = AGIVariableDump::builder(cli.url.clone()).build();
+    for (idx, arg) in cli.args.iter().enumerate() {
+        let number = u8::try_from(idx + 1).map_err(|_| "At most 255 arguments are allowed")?;
+        request.custom_args.insert(number, arg.clone());
+    }
+    for assignment in &cli.variables {
+        variables::assign(&mut request, assignment)?;
+    }
+    let mut rules = Rules::default();
+    if let Some(path) = &cli.rules_file {
+        rules.extend_from_str(&std::fs::read_to_string(path)?)?;
+    };
+    for rule in cli.rules {
+        rules.push(rule);
+    }
+
+    let mut session = AGIClientSession::connect(request).await?;
+    println!("Connected to {}", cli.url);
+    let mut stdin = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(command) = session.next_command().await? {
+        println!("server > {command}");
+        let status = if let Some(status) = rules.respond_to(&command) {
+            status.clone()
+        } else if let Some(status) = &cli.default {
+            status.clone()
+        } else {
+            match ask(&mut stdin).await? {
+                Some(status) => status,
+                None => {
+                    println!("Hanging up.");
+                    session.hangup().await?;
+                    return Ok(());
+                }
+            }
+        };
+        println!("client < {status}");
+        session.respond(&status).await?;
+    }
+    println!("The server closed the connection.");
+    Ok(())
+}
+
+/// Ask for a status on stdin until a valid one is entered.
+///
+/// Returns `None` when stdin is closed.
+async fn ask<R>(stdin: &mut tokio::io::Lines<R>) -> Result<Option<AGIStatusGeneric>, std::io::Error>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    loop {
+        eprint!("status> ");
+        let Some(line) = stdin.next_line().await? else {
+            return Ok(None);
+        };
+        match parse_status(&line) {
+            Ok(status) => return Ok(Some(status)),
+            Err(e) => eprintln!("{e}"),
+        };
+    }
+}
@@ -0,0 +1,180 @@
+//! Benchmark a `FastAGI` server with many concurrent simulated asterisk sessions.
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use blazing_agi::{
+    command::{Answer, GetFullVariable, SetVariable, Verbose},
+    connection::Connection,
+    handler::AGIHandler,
+    router::Router,
+    serve, AGIError, AGIRequest, AGIStatusGeneric, AGIVariableDump,
+};
+use blazing_agi_cli::{
+    load::{parse_rate, run_session, Report, SessionConfig},
+    rules::{parse_status, Rule, Rules},
+    variables,
+};
+use clap::Parser;
+use tokio::{net::TcpListener, sync::Semaphore, task::JoinSet};
+use url::Url;
+
+/// Open many simulated asterisk sessions against a FastAGI server and report throughput,
+/// command latency and errors per route.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// The urls to request. Sessions cycle through them in order
+    #[arg(required_unless_present = "self_test")]
+    urls: Vec<Url>,
+    /// Instead of an external server, benchmark a small router served with
+    /// `blazing_agi::serve::serve` on localhost
+    #[arg(long)]
+    self_test: bool,
+    /// Total number of sessions to run
+    #[arg(short = 'n', long, default_value_t = 1000)]
+    sessions: usize,
+    /// Maximum number of sessions running at the same time
+    #[arg(short = 'c', long, default_value_t = 100)]
+    concurrency: usize,
+    /// New sessions started per second. 0 starts them as fast as concurrency allows
+    #[arg(long, value_parser = parse_rate, default_value = "0")]
+    rate: f64,
+    /// Set a variable of each request, e.g. `callerid=+4930123456`
+    #[arg(short = 'v', long = "var", value_name = "NAME=VALUE")]
+    variables: Vec<String>,
+    /// Answer commands starting with PATTERN with STATUS. `*` matches anything
+    #[arg(short = 'r', long = "rule", value_name = "PATTERN => STATUS")]
+    rules: Vec<Rule>,
+    /// Read rules from a file, one per line
+    #[arg(short = 'f', long = "rules-file", value_name = "FILE")]
+    rules_file: Option<PathBuf>,
+    /// Answer commands no rule matches with this status
+    #[arg(short = 'd', long = "default", value_name = "STATUS", value_parser = parse_status, default_value = "200 result=0")]
+    default: AGIStatusGeneric,
+    /// Milliseconds to wait before answering each command
+    #[arg(long, default_value_t = 0)]
+    latency_ms: u64,
+    /// Seconds to wait for each command before counting the session as failed
+    #[arg(long, default_value_t = 10)]
+    timeout_secs: u64,
+}
+
+/// The handler benchmarked by `--self-test`: A few commands like a small IVR would send.
+#[derive(Debug)]
+struct SelfTestHandler {}
+#[async_trait::async_trait]
+impl AGIHandler for SelfTestHandler {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        connection.send_command(Answer::new()).await?;
+        connection
+            .send_command(GetFullVariable::new("${CALLERID(num)}".to_owned()))
+            .await?;
+        connection
+            .send_command(SetVariable::new(
+                "BLAZING_AGI_LOAD".to_owned(),
+                request.variables.uniqueid.clone(),
+            ))
+            .await?;
+        connection
+            .send_command(Verbose::new("load test".to_owned()))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Serve the self-test router on localhost and return the urls to request.
+async fn spawn_self_test_server() -> Result<Vec<Url>, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let router = Router::new()
+        .route("/ivr", SelfTestHandler {})
+        .route("/other", SelfTestHandler {});
+    tokio::spawn(serve::serve(listener, router));
+    Ok(vec![
+        format!("agi://127.0.0.1:{port}/ivr").parse()?,
+        format!("agi://127.0.0.1:{port}/other").parse()?,
+        // not routed: measures the fallback
+        format!("agi://127.0.0.1:{port}/missing").parse()?,
+    ])
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let urls = if cli.self_test {
+        spawn_self_test_server().await?
+    } else {
+        cli.urls.clone()
+    };
+    let mut requests = vec![];
+    for url in urls {
+        let mut request = AGIVariableDump::builder(url).build();
+        for assignment in &cli.variables {
+            variables::assign(&mut request, assignment)?;
+        }
+        requests.push(request);
+    }
+    let mut rules = Rules::default();
+    if let Some(path) = &cli.rules_file {
+        rules.extend_from_str(&std::fs::read_to_string(path)?)?;
+    };
+    for rule in cli.rules {
+        rules.push(rule);
+    }
+    // the self-test handler expects a value for GET FULL VARIABLE and a 1 for SET VARIABLE
+    if cli.self_test {
+        rules.push("GET FULL VARIABLE => 200 result=1 (0123456789)".parse()?);
+        rules.push("SET VARIABLE => 200 result=1".parse()?);
+        rules.push("VERBOSE => 200 result=1".parse()?);
+    };
+    let config = Arc::new(SessionConfig {
+        rules,
+        default: cli.default,
+        latency: Duration::from_millis(cli.latency_ms),
+        timeout: Duration::from_secs(cli.timeout_secs),
+    });
+
+    let semaphore = Arc::new(Semaphore::new(cli.concurrency.max(1)));
+    let mut interval =
+        (cli.rate > 0.0).then(|| tokio::time::interval(Duration::from_secs_f64(1.0 / cli.rate)));
+    let mut sessions = JoinSet::new();
+    let mut report = Report::default();
+    let start = Instant::now();
+    for idx in 0..cli.sessions {
+        if let Some(interval) = &mut interval {
+            interval.tick().await;
+        };
+        let permit = semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("The semaphore is never closed");
+        let mut request = requests[idx % requests.len()].clone();
+        request.uniqueid = format!("1700000000.{idx}");
+        let route = format!("/{}", request.network_script);
+        let config = config.clone();
+        sessions.spawn(async move {
+            let outcome = run_session(request, &config).await;
+            drop(permit);
+            (route, outcome)
+        });
+        // collect finished sessions as we go, so memory does not grow with the session count
+        while let Some(finished) = sessions.try_join_next() {
+            let (route, outcome) = finished?;
+            report.record(&route, outcome);
+        }
+    }
+    while let Some(finished) = sessions.join_next().await {
+        let (route, outcome) = finished?;
+        report.record(&route, outcome);
+    }
+    report.elapsed = start.elapsed();
+    print!("{report}");
+    Ok(())
+}
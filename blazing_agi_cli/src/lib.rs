@@ -0,0 +1,8 @@
+//! Shared code for the command line tools that play the part of asterisk against a `FastAGI`
+//! server.
+//!
+//! - `blazing-agi-cli` opens a single session and shows every command the server sends
+//! - `blazing-agi-load` runs many concurrent sessions and reports throughput and latency
+pub mod load;
+pub mod rules;
+pub mod variables;
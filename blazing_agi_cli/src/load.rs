@@ -0,0 +1,286 @@
+//! Run many simulated asterisk sessions against a server and collect statistics.
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use blazing_agi::{client::AGIClientSession, AGIStatusGeneric, AGIVariableDump};
+
+use crate::rules::Rules;
+
+/// How a single simulated session answers the commands it gets.
+#[derive(Debug)]
+pub struct SessionConfig {
+    /// Answer commands matching a rule with its status.
+    pub rules: Rules,
+    /// Answer all other commands with this status.
+    pub default: AGIStatusGeneric,
+    /// Wait this long before answering each command, like asterisk executing it would.
+    pub latency: Duration,
+    /// Give up when the server does not send the next command within this time.
+    pub timeout: Duration,
+}
+
+/// What happened in a single simulated session.
+#[derive(Debug, Default, PartialEq)]
+pub struct SessionOutcome {
+    /// For each command: How long the server took to send it after we sent the request or
+    /// answered the previous command.
+    pub command_latencies: Vec<Duration>,
+    /// Why the session failed, if it did.
+    pub error: Option<String>,
+}
+
+/// Run a single session, sending `request` and answering commands as configured.
+pub async fn run_session(request: AGIVariableDump, config: &SessionConfig) -> SessionOutcome {
+    let mut outcome = SessionOutcome::default();
+    let connect = tokio::time::timeout(config.timeout, AGIClientSession::connect(request)).await;
+    let mut session = match connect {
+        Ok(Ok(x)) => x,
+        Ok(Err(e)) => {
+            outcome.error = Some(format!("connect: {e}"));
+            return outcome;
+        }
+        Err(_) => {
+            outcome.error = Some("connect: timeout".to_owned());
+            return outcome;
+        }
+    };
+    loop {
+        let waiting_since = Instant::now();
+        let command = match tokio::time::timeout(config.timeout, session.next_command()).await {
+            Ok(Ok(Some(x))) => x,
+            // the server is done with this session
+            Ok(Ok(None)) => return outcome,
+            Ok(Err(e)) => {
+                outcome.error = Some(format!("read: {e}"));
+                return outcome;
+            }
+            Err(_) => {
+                outcome.error = Some("read: timeout".to_owned());
+                return outcome;
+            }
+        };
+        outcome.command_latencies.push(waiting_since.elapsed());
+        if !config.latency.is_zero() {
+            tokio::time::sleep(config.latency).await;
+        };
+        let status = config.rules.respond_to(&command).unwrap_or(&config.default);
+        if let Err(e) = session.respond(status).await {
+            outcome.error = Some(format!("write: {e}"));
+            return outcome;
+        };
+    }
+}
+
+/// Statistics for all sessions requesting the same route.
+#[derive(Debug, Default, PartialEq)]
+pub struct RouteStats {
+    /// Sessions that ended with the server closing the connection.
+    pub sessions_ok: usize,
+    /// Number of failed sessions per error.
+    pub errors: BTreeMap<String, usize>,
+    /// Latencies of all commands in all sessions.
+    pub command_latencies: Vec<Duration>,
+}
+impl RouteStats {
+    /// Add the outcome of a single session.
+    pub fn record(&mut self, outcome: SessionOutcome) {
+        match outcome.error {
+            None => self.sessions_ok += 1,
+            Some(e) => *self.errors.entry(e).or_default() += 1,
+        };
+        self.command_latencies.extend(outcome.command_latencies);
+    }
+
+    /// Number of sessions that failed.
+    pub fn sessions_failed(&self) -> usize {
+        self.errors.values().sum()
+    }
+}
+
+/// Statistics for an entire load test.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Statistics per requested route.
+    pub routes: BTreeMap<String, RouteStats>,
+    /// How long the whole test took.
+    pub elapsed: Duration,
+}
+impl Report {
+    /// Add the outcome of a single session requesting `route`.
+    pub fn record(&mut self, route: &str, outcome: SessionOutcome) {
+        self.routes
+            .entry(route.to_owned())
+            .or_default()
+            .record(outcome);
+    }
+}
+impl core::fmt::Display for Report {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let sessions = self
+            .routes
+            .values()
+            .map(|x| x.sessions_ok + x.sessions_failed())
+            .sum::<usize>();
+        let seconds = self.elapsed.as_secs_f64();
+        writeln!(f, "{sessions} sessions in {seconds:.2}s")?;
+        if seconds > 0.0 {
+            writeln!(f, "{:.1} sessions/s", sessions as f64 / seconds)?;
+        };
+        for (route, stats) in &self.routes {
+            writeln!(f)?;
+            writeln!(f, "{route}")?;
+            writeln!(
+                f,
+                "  sessions: {} ok, {} failed",
+                stats.sessions_ok,
+                stats.sessions_failed()
+            )?;
+            let mut latencies = stats.command_latencies.clone();
+            latencies.sort();
+            writeln!(f, "  commands: {}", latencies.len())?;
+            if !latencies.is_empty() {
+                writeln!(
+                    f,
+                    "  command latency: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                    percentile(&latencies, 50.0),
+                    percentile(&latencies, 90.0),
+                    percentile(&latencies, 99.0),
+                    latencies[latencies.len() - 1],
+                )?;
+            };
+            for (error, count) in &stats.errors {
+                writeln!(f, "  error: {count}x {error}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `p`-th percentile of the sorted `values`, using the nearest-rank method.
+///
+/// # Panics
+/// Panics if `values` is empty.
+pub fn percentile(values: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+/// Parse the number of sessions started per second. `0` means as fast as possible; other rates
+/// have to be positive and give a time between sessions between 1ns and the longest [`Duration`].
+pub fn parse_rate(input: &str) -> Result<f64, String> {
+    let rate = input
+        .parse::<f64>()
+        .map_err(|e| format!("{input} is not a number: {e}"))?;
+    if rate == 0.0 {
+        return Ok(rate);
+    };
+    match Duration::try_from_secs_f64(1.0 / rate) {
+        Ok(period) if rate.is_finite() && !period.is_zero() => Ok(rate),
+        _ => Err(format!(
+            "{input} is not a usable rate; it must be 0 or a positive number up to one billion"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use blazing_agi::{
+        command::Answer, connection::Connection, handler::AGIHandler, router::Router, serve,
+        AGIError, AGIRequest,
+    };
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct AnswerTwice {}
+    #[async_trait::async_trait]
+    impl AGIHandler for AnswerTwice {
+        async fn handle(
+            &self,
+            connection: &mut Connection,
+            _request: &AGIRequest,
+        ) -> Result<(), AGIError> {
+            connection.send_command(Answer::new()).await?;
+            connection.send_command(Answer::new()).await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn session_against_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve::serve(
+            listener,
+            Router::new().route("/answer", AnswerTwice {}),
+        ));
+        let request =
+            AGIVariableDump::builder(format!("agi://127.0.0.1:{port}/answer").parse().unwrap())
+                .build();
+        let config = SessionConfig {
+            rules: Rules::default(),
+            default: AGIStatusGeneric::Ok("0".to_owned(), None),
+            latency: Duration::ZERO,
+            timeout: Duration::from_secs(5),
+        };
+        let outcome = run_session(request, &config).await;
+        assert_eq!(outcome.error, None);
+        assert_eq!(outcome.command_latencies.len(), 2);
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(parse_rate("0"), Ok(0.0));
+        assert_eq!(parse_rate("2.5"), Ok(2.5));
+        assert_eq!(parse_rate("1e9"), Ok(1e9));
+        for invalid in ["-1", "inf", "NaN", "1e10", "1e-300", "fast"] {
+            assert!(parse_rate(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn percentiles() {
+        let values = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&values, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&values, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&values, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&values, 0.0), Duration::from_millis(1));
+        assert_eq!(
+            percentile(&[Duration::from_millis(7)], 90.0),
+            Duration::from_millis(7)
+        );
+    }
+
+    #[test]
+    fn errors_are_counted_per_route() {
+        let mut report = Report::default();
+        report.record(
+            "/a",
+            SessionOutcome {
+                command_latencies: vec![Duration::from_millis(1)],
+                error: None,
+            },
+        );
+        report.record(
+            "/a",
+            SessionOutcome {
+                command_latencies: vec![],
+                error: Some("read: timeout".to_owned()),
+            },
+        );
+        report.record(
+            "/b",
+            SessionOutcome {
+                command_latencies: vec![],
+                error: Some("read: timeout".to_owned()),
+            },
+        );
+        let a = &report.routes["/a"];
+        assert_eq!(a.sessions_ok, 1);
+        assert_eq!(a.sessions_failed(), 1);
+        assert_eq!(a.command_latencies.len(), 1);
+        assert_eq!(report.routes["/b"].errors["read: timeout"], 1);
+        assert!(report.to_string().starts_with("3 sessions in"));
+    }
+}
@@ -0,0 +1,211 @@
+//! Rules that answer commands automatically, e.g. `STREAM FILE => 200 result=0 endpos=1000`.
+use std::error::Error;
+use std::str::FromStr;
+
+use blazing_agi::{testing::glob_matches, AGIStatusGeneric};
+
+/// Contains all the ways in which a rule can be malformed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleParseError {
+    /// The rule does not contain `=>` between pattern and status.
+    NoArrow(String),
+    /// The pattern is empty.
+    EmptyPattern(String),
+    /// The status is not a valid AGI status.
+    InvalidStatus(String),
+    /// A line in a rules file is malformed.
+    InFile(usize, Box<RuleParseError>),
+}
+impl core::fmt::Display for RuleParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::NoArrow(x) => {
+                write!(f, "The rule `{x}` is not of the form `PATTERN => STATUS`")
+            }
+            Self::EmptyPattern(x) => {
+                write!(f, "The rule `{x}` has an empty pattern")
+            }
+            Self::InvalidStatus(x) => {
+                write!(f, "`{x}` is not a valid AGI status")
+            }
+            Self::InFile(line, x) => {
+                write!(f, "Line {line}: {x}")
+            }
+        }
+    }
+}
+impl Error for RuleParseError {}
+
+/// Parse `input` as a status, accepting a bare result (`0`) as shorthand for `200 result=0`.
+///
+/// An empty input is shorthand for `200 result=0`.
+pub fn parse_status(input: &str) -> Result<AGIStatusGeneric, RuleParseError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(AGIStatusGeneric::Ok("0".to_owned(), None));
+    };
+    if let Ok(status) = input.parse::<AGIStatusGeneric>() {
+        return Ok(status);
+    };
+    format!("200 result={input}")
+        .parse::<AGIStatusGeneric>()
+        .map_err(|_| RuleParseError::InvalidStatus(input.to_owned()))
+}
+
+/// Answer commands matching `pattern` with `status`.
+///
+/// `*` in the pattern matches any sequence of characters. The pattern only has to match the
+/// start of the command, so `STREAM FILE` matches every `STREAM FILE` command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pattern: String,
+    status: AGIStatusGeneric,
+}
+impl Rule {
+    /// Does this rule apply to `command`?
+    pub fn matches(&self, command: &str) -> bool {
+        // the pattern only has to match the start of the command
+        glob_matches(&format!("{}*", self.pattern), command)
+    }
+
+    /// The status this rule answers with.
+    pub fn status(&self) -> &AGIStatusGeneric {
+        &self.status
+    }
+}
+impl FromStr for Rule {
+    type Err = RuleParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, status) = s
+            .split_once("=>")
+            .or_else(|| s.split_once('→'))
+            .ok_or_else(|| RuleParseError::NoArrow(s.to_owned()))?;
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            return Err(RuleParseError::EmptyPattern(s.to_owned()));
+        };
+        Ok(Rule {
+            pattern: pattern.to_owned(),
+            status: parse_status(status)?,
+        })
+    }
+}
+
+/// An ordered list of [`Rule`]s. The first matching rule wins.
+#[derive(Debug, Default, PartialEq)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+impl Rules {
+    /// Add `rule` after all existing rules.
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// Add the rules in `content`, one per line. Empty lines and lines starting with `#` are
+    /// ignored.
+    pub fn extend_from_str(&mut self, content: &str) -> Result<(), RuleParseError> {
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            };
+            let rule = line
+                .parse::<Rule>()
+                .map_err(|e| RuleParseError::InFile(idx + 1, Box::new(e)))?;
+            self.push(rule);
+        }
+        Ok(())
+    }
+
+    /// The status the first rule matching `command` answers with, if any.
+    pub fn respond_to(&self, command: &str) -> Option<&AGIStatusGeneric> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(command))
+            .map(Rule::status)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rule() {
+        let rule = "STREAM FILE => 200 result=0 endpos=1000"
+            .parse::<Rule>()
+            .unwrap();
+        assert!(rule.matches("STREAM FILE \"welcome\" \"\""));
+        assert!(!rule.matches("ANSWER"));
+        assert_eq!(
+            rule.status(),
+            &AGIStatusGeneric::Ok("0".to_owned(), Some("endpos=1000".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parse_shorthand_status() {
+        assert_eq!(
+            parse_status("1 (value)"),
+            Ok(AGIStatusGeneric::Ok(
+                "1".to_owned(),
+                Some("(value)".to_owned())
+            ))
+        );
+        assert_eq!(parse_status("511"), Ok(AGIStatusGeneric::DeadChannel));
+        assert_eq!(
+            parse_status(""),
+            Ok(AGIStatusGeneric::Ok("0".to_owned(), None))
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_rule() {
+        assert_eq!(
+            "ANSWER 200 result=0".parse::<Rule>(),
+            Err(RuleParseError::NoArrow("ANSWER 200 result=0".to_owned()))
+        );
+        assert_eq!(
+            " => 200 result=0".parse::<Rule>(),
+            Err(RuleParseError::EmptyPattern(" => 200 result=0".to_owned()))
+        );
+    }
+
+    #[test]
+    fn first_rule_wins() {
+        let mut rules = Rules::default();
+        rules
+            .extend_from_str(
+                "# comments are ignored\n\
+                GET FULL VARIABLE \"${CALLERID(num)}\" => 200 result=1 (123)\n\
+                \n\
+                GET * VARIABLE => 200 result=0\n",
+            )
+            .unwrap();
+        assert_eq!(
+            rules.respond_to("GET FULL VARIABLE \"${CALLERID(num)}\""),
+            Some(&AGIStatusGeneric::Ok(
+                "1".to_owned(),
+                Some("(123)".to_owned())
+            ))
+        );
+        assert_eq!(
+            rules.respond_to("GET FULL VARIABLE \"${X}\""),
+            Some(&AGIStatusGeneric::Ok("0".to_owned(), None))
+        );
+        assert_eq!(rules.respond_to("ANSWER"), None);
+    }
+
+    #[test]
+    fn incorrect_line_in_file() {
+        let mut rules = Rules::default();
+        assert_eq!(
+            rules.extend_from_str("ANSWER => 200 result=0\nNOOP\n"),
+            Err(RuleParseError::InFile(
+                2,
+                Box::new(RuleParseError::NoArrow("NOOP".to_owned()))
+            ))
+        );
+    }
+}
@@ -0,0 +1,140 @@
+//! Set the variables of the initial request from the command line, e.g. `callerid=123`.
+use std::error::Error;
+
+use blazing_agi::AGIVariableDump;
+
+/// Contains all the ways in which setting a variable can fail.
+#[derive(Debug, PartialEq)]
+pub enum VariableError {
+    /// The assignment is not of the form `name=value`.
+    NoEquals(String),
+    /// There is no variable with this name.
+    UnknownVariable(String),
+    /// The value cannot be parsed for this variable.
+    InvalidValue(String, String),
+}
+impl core::fmt::Display for VariableError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::NoEquals(x) => {
+                write!(f, "`{x}` is not of the form `NAME=VALUE`")
+            }
+            Self::UnknownVariable(x) => {
+                write!(f, "There is no AGI variable called {x}")
+            }
+            Self::InvalidValue(name, value) => {
+                write!(f, "`{value}` is not a valid value for {name}")
+            }
+        }
+    }
+}
+impl Error for VariableError {}
+
+/// Apply an assignment `name=value` to `dump`.
+///
+/// `name` is the name asterisk uses, with or without the `agi_` prefix, e.g. `callerid` or
+/// `agi_callerid`. Use `arg_N` to set a custom argument.
+pub fn assign(dump: &mut AGIVariableDump, assignment: &str) -> Result<(), VariableError> {
+    let (name, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| VariableError::NoEquals(assignment.to_owned()))?;
+    set(dump, name.trim(), value)
+}
+
+/// Set the variable `name` in `dump` to `value`.
+pub fn set(dump: &mut AGIVariableDump, name: &str, value: &str) -> Result<(), VariableError> {
+    let short_name = name.strip_prefix("agi_").unwrap_or(name);
+    let invalid = || VariableError::InvalidValue(name.to_owned(), value.to_owned());
+    let field = match short_name {
+        "network_script" => &mut dump.network_script,
+        "request" => {
+            dump.request = value.parse().map_err(|_| invalid())?;
+            return Ok(());
+        }
+        "channel" => &mut dump.channel,
+        "language" => &mut dump.language,
+        "type" => &mut dump.channel_type,
+        "uniqueid" => &mut dump.uniqueid,
+        "version" => &mut dump.version,
+        "callerid" => &mut dump.callerid,
+        "calleridname" => &mut dump.calleridname,
+        "callingpres" => &mut dump.callingpres,
+        "callingani2" => &mut dump.callingani2,
+        "callington" => &mut dump.callington,
+        "callingtns" => &mut dump.callingtns,
+        "dnid" => &mut dump.dnid,
+        "rdnis" => &mut dump.rdnis,
+        "context" => &mut dump.context,
+        "extension" => &mut dump.extension,
+        "priority" => {
+            dump.priority = value.parse().map_err(|_| invalid())?;
+            return Ok(());
+        }
+        "enhanced" => {
+            dump.enhanced = match value {
+                "1" | "1.0" | "true" => true,
+                "0" | "0.0" | "false" => false,
+                _ => return Err(invalid()),
+            };
+            return Ok(());
+        }
+        "accountcode" => &mut dump.accountcode,
+        "threadid" => {
+            dump.threadid = value.parse().map_err(|_| invalid())?;
+            return Ok(());
+        }
+        x => {
+            let number = x
+                .strip_prefix("arg_")
+                .ok_or_else(|| VariableError::UnknownVariable(name.to_owned()))?
+                .parse::<u8>()
+                .map_err(|_| VariableError::UnknownVariable(name.to_owned()))?;
+            dump.custom_args.insert(number, value.to_owned());
+            return Ok(());
+        }
+    };
+    *field = value.to_owned();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dump() -> AGIVariableDump {
+        AGIVariableDump::builder("agi://localhost/".parse().unwrap()).build()
+    }
+
+    #[test]
+    fn assign_variables() {
+        let mut dump = dump();
+        assign(&mut dump, "callerid=+4930123").unwrap();
+        assign(&mut dump, "agi_context=from-pstn").unwrap();
+        assign(&mut dump, "priority=3").unwrap();
+        assign(&mut dump, "arg_2=x=y").unwrap();
+        assert_eq!(dump.callerid, "+4930123");
+        assert_eq!(dump.context, "from-pstn");
+        assert_eq!(dump.priority, 3);
+        assert_eq!(dump.custom_args.get(&2), Some(&"x=y".to_owned()));
+    }
+
+    #[test]
+    fn assign_incorrect() {
+        let mut dump = dump();
+        assert_eq!(
+            assign(&mut dump, "callerid"),
+            Err(VariableError::NoEquals("callerid".to_owned()))
+        );
+        assert_eq!(
+            assign(&mut dump, "foo=bar"),
+            Err(VariableError::UnknownVariable("foo".to_owned()))
+        );
+        assert_eq!(
+            assign(&mut dump, "priority=high"),
+            Err(VariableError::InvalidValue(
+                "priority".to_owned(),
+                "high".to_owned()
+            ))
+        );
+    }
+}
@@ -4,7 +4,29 @@
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::quote;
-use syn::{parse_macro_input, Expr, ExprTuple, Ident, ItemFn};
+use syn::{
+    parse::Parse, parse::ParseStream, parse_macro_input, Expr, ExprTuple, Ident, ItemFn, Token,
+    Type,
+};
+
+/// The arguments of `create_handler`: optionally `state = Type`.
+struct HandlerArgs {
+    state: Option<Type>,
+}
+impl Parse for HandlerArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.is_empty() {
+            return Ok(Self { state: None });
+        };
+        let key: Ident = input.parse()?;
+        if key != "state" {
+            return Err(syn::Error::new(key.span(), "expected `state = Type`"));
+        };
+        input.parse::<Token![=]>()?;
+        let state = input.parse()?;
+        Ok(Self { state: Some(state) })
+    }
+}
 
 /// Given an async fn, create an AGIHandler from it.
 ///
@@ -17,17 +39,33 @@ use syn::{parse_macro_input, Expr, ExprTuple, Ident, ItemFn};
 /// and type-checking or compilation may fail.
 /// If you do not use one of the arguments, you may change their name to `_`.
 ///
+/// With `#[create_handler(state = Config)]`, the body can use `state: Arc<Config>`, the state set
+/// with `Router::with_state`. If the router has no state of this type, the handler returns an
+/// error without running the body.
+///
 /// Note: What we really want is a transformation: `async fn(&mut Connection, &AGIRequest) -> AGIHandler`.
 /// But naming the types (specifically: lifetimes) there is very hard until RPIT captures lifetimes.
 /// I decided for this somewhat more hacky solution: simply copy-pasting the function body
 /// directly into a new impl block with this macro.
 #[proc_macro_attribute]
-pub fn create_handler(_: TokenStream, input: TokenStream) -> TokenStream {
+pub fn create_handler(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as HandlerArgs);
     let input = parse_macro_input!(input as ItemFn);
 
     let fn_name = input.sig.ident;
     let fn_block = input.block;
-    let struct_name = Ident::new(format!("Blazing_AGI_Handler_{fn_name}").as_str(), Span::call_site());
+    let struct_name = Ident::new(
+        format!("Blazing_AGI_Handler_{fn_name}").as_str(),
+        Span::call_site(),
+    );
+    let get_state = args.state.map(|state| {
+        quote! {
+            let state: ::std::sync::Arc<#state> =
+                <::blazing_agi::extract::State<#state> as ::blazing_agi::extract::FromAGIRequest>::from_request(request)
+                    .map_err(::blazing_agi::AGIError::from)?
+                    .0;
+        }
+    });
 
     let tokens = quote! {
         #[derive(Debug,Clone)]
@@ -35,6 +73,7 @@ pub fn create_handler(_: TokenStream, input: TokenStream) -> TokenStream {
         #[::async_trait::async_trait]
         impl ::blazing_agi::handler::AGIHandler for #struct_name {
             async fn handle(&self, connection: &mut ::blazing_agi::connection::Connection, request: &::blazing_agi::AGIRequest) -> Result<(), ::blazing_agi::AGIError> {
+                #get_state
                 #fn_block
             }
         }
@@ -67,5 +106,6 @@ pub fn layer_before(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as Expr);
     quote! {
         ::blazing_agi::layer::AndThenLayerBefore::new(#input)
-    }.into()
+    }
+    .into()
 }
@@ -48,6 +48,8 @@ fn create_nonce() -> String {
     return hex::encode(raw_bytes);
 }
 
+/// A minimal digest authentication, to show how to write a layer.
+/// For production use, enable the `auth` feature and use `blazing_agi::auth::DigestLayer`.
 #[derive(Clone, Debug)]
 struct SHA1DigestOverAGI {
     secret: String,
@@ -127,12 +129,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         and_then!((SHA1DigestOverAGI::new("top_secret"), foo)),
     );
     // But this is even nicer if you use a layer:
-    // Here, every route added !before! the layer will have the digest running first.
+    // Here, every route added !before! the route_layer will have the digest running first.
     let _router_equivalent = Router::new()
         .route("/protected/foo", foo)
-        .layer(layer_before!(SHA1DigestOverAGI::new("top_secret")))
+        .route_layer(layer_before!(SHA1DigestOverAGI::new("top_secret")))
         // this route will NOT have the SHA1 Digest running
         .route("/not_protected/foo", foo);
+    // To protect every route, including the fallback, use the middleware stack instead.
+    let _router_all_protected = Router::new()
+        .layer(layer_before!(SHA1DigestOverAGI::new("top_secret")))
+        .layer_fallback(true)
+        .route("/protected/foo", foo);
 
     let listener = TcpListener::bind("0.0.0.0:4573").await?;
     serve(listener, router).await?;
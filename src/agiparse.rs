@@ -116,15 +116,15 @@ impl Error for AGIParseError {}
 /// response we expected due to the sent command.
 /// The response will be further parsed down to an [`AGIResponse`](crate::command::AGIResponse)
 /// once we know to which Request this response is an answer.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AGIStatusGeneric {
     /// 200
     Ok(String, Option<String>),
-    // 510
+    /// 510
     Invalid,
-    // 511
+    /// 511
     DeadChannel,
-    // 520
+    /// 520
     EndUsage,
 }
 impl core::fmt::Display for AGIStatusGeneric {
@@ -139,13 +139,16 @@ impl core::fmt::Display for AGIStatusGeneric {
                 }
             },
             Self::Invalid => {
-                write!(f, "510")
+                write!(f, "510 Invalid or unknown command")
             }
             Self::DeadChannel => {
-                write!(f, "511")
+                write!(
+                    f,
+                    "511 Command Not Permitted on a dead channel or intercept routine"
+                )
             }
             Self::EndUsage => {
-                write!(f, "520")
+                write!(f, "520 End of proper usage.")
             }
         }
     }
@@ -155,12 +158,20 @@ impl FromStr for AGIStatusGeneric {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // line format is
         // 200 result=some_result [some_operational_data]
-        let mut splitline = s.trim_end().split(' ');
+        // or, for errors, the code followed by a human readable description:
+        // 510 Invalid or unknown command
+        let mut splitline = s.trim_end().splitn(3, ' ');
         let code = splitline
             .next()
             .ok_or(AGIParseError::NoStatusCode(s.to_owned()))?
             .parse::<u16>()
             .map_err(|_| AGIParseError::StatusCodeUnparsable(s.to_owned()))?;
+        match code {
+            510 => return Ok(AGIStatusGeneric::Invalid),
+            511 => return Ok(AGIStatusGeneric::DeadChannel),
+            520 => return Ok(AGIStatusGeneric::EndUsage),
+            _ => {}
+        };
         let result_part = splitline
             .next()
             .ok_or(AGIParseError::NoResult(s.to_owned()))?;
@@ -171,9 +182,6 @@ impl FromStr for AGIStatusGeneric {
         let operational_data = splitline.next().map(|x| x.to_owned());
         match code {
             200 => Ok(AGIStatusGeneric::Ok(result, operational_data)),
-            510 => Ok(AGIStatusGeneric::Invalid),
-            511 => Ok(AGIStatusGeneric::DeadChannel),
-            520 => Ok(AGIStatusGeneric::EndUsage),
             x => Err(AGIParseError::StatusDoesNotExist(x)),
         }
     }
@@ -186,6 +194,29 @@ pub enum AGIRequestType {
     File(PathBuf),
     FastAGI(Url),
 }
+impl AGIRequestType {
+    /// The host of a `FastAGI` request url in lowercase, if it has one.
+    pub(crate) fn host(&self) -> Option<String> {
+        match self {
+            Self::FastAGI(x) => x.host_str().map(str::to_ascii_lowercase),
+            Self::File(_) => None,
+        }
+    }
+
+    /// The decoded query parameters of a `FastAGI` request url, with all values of each name.
+    pub(crate) fn query(&self) -> HashMap<String, Vec<String>> {
+        let mut query = HashMap::<String, Vec<String>>::new();
+        if let Self::FastAGI(x) = self {
+            for (name, value) in x.query_pairs() {
+                query
+                    .entry(name.into_owned())
+                    .or_default()
+                    .push(value.into_owned());
+            }
+        };
+        query
+    }
+}
 impl FromStr for AGIRequestType {
     type Err = AGIParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -202,7 +233,7 @@ impl core::fmt::Display for AGIRequestType {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             Self::File(x) => {
-                write!(f, "{x:?}")
+                write!(f, "{}", x.display())
             }
             Self::FastAGI(x) => {
                 write!(f, "{x}")
@@ -225,7 +256,7 @@ fn enhanced_status(input: &str) -> Result<bool, AGIParseError> {
 /// The `AGIVariableDump` (i.e. an AGI request). This is the second packet asterisk sends, after an
 /// agi_network: yes has been sent to initiate the session.
 /// The variables are in 1-1 map to the variables asterisk sends.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct AGIVariableDump {
     pub network_script: String,
     pub request: AGIRequestType,
@@ -252,13 +283,14 @@ pub struct AGIVariableDump {
     /// (n)=>value )
     pub custom_args: HashMap<u8, String>,
 }
+/// Write the dump exactly like asterisk sends it, including the empty line that terminates it.
 impl core::fmt::Display for AGIVariableDump {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         writeln!(f, "agi_network_script: {}", self.network_script)?;
         writeln!(f, "agi_request: {}", self.request)?;
         writeln!(f, "agi_channel: {}", self.channel)?;
         writeln!(f, "agi_language: {}", self.language)?;
-        writeln!(f, "agi_channel_type: {}", self.channel_type)?;
+        writeln!(f, "agi_type: {}", self.channel_type)?;
         writeln!(f, "agi_uniqueid: {}", self.uniqueid)?;
         writeln!(f, "agi_version: {}", self.version)?;
         writeln!(f, "agi_callerid: {}", self.callerid)?;
@@ -272,20 +304,150 @@ impl core::fmt::Display for AGIVariableDump {
         writeln!(f, "agi_context: {}", self.context)?;
         writeln!(f, "agi_extension: {}", self.extension)?;
         writeln!(f, "agi_priority: {}", self.priority)?;
-        writeln!(f, "agi_enhanced: {}", self.enhanced)?;
+        writeln!(
+            f,
+            "agi_enhanced: {}",
+            if self.enhanced { "1.0" } else { "0.0" }
+        )?;
         writeln!(f, "agi_accountcode: {}", self.accountcode)?;
         writeln!(f, "agi_threadid: {}", self.threadid)?;
-        for idx in 0..self.custom_args.len() {
-            writeln!(
-                f,
-                "agi_arg_{}: {}",
-                idx,
-                self.custom_args
-                    .get(&(idx as u8))
-                    .expect("custom_args should contain consecutive u8s as key")
-            )?;
+        let mut args = self.custom_args.iter().collect::<Vec<_>>();
+        args.sort();
+        for (number, value) in args {
+            writeln!(f, "agi_arg_{number}: {value}")?;
         }
-        Ok(())
+        writeln!(f)
+    }
+}
+impl AGIVariableDump {
+    /// Start building an [`AGIVariableDump`] for a `FastAGI` request to `url`.
+    ///
+    /// This is useful whenever we need to play the client side (asterisk), e.g. in tests.
+    /// All variables not explicitly set get a plausible default value.
+    /// ```
+    /// use blazing_agi::AGIVariableDump;
+    /// let dump = AGIVariableDump::builder("agi://localhost/script".parse().unwrap())
+    ///     .callerid("+4930123456")
+    ///     .extension("100")
+    ///     .arg(1, "first")
+    ///     .build();
+    /// assert_eq!(dump.extension, "100");
+    /// assert_eq!(dump.custom_args.get(&1), Some(&"first".to_owned()));
+    /// ```
+    pub fn builder(url: Url) -> AGIVariableDumpBuilder {
+        AGIVariableDumpBuilder::new(url)
+    }
+}
+
+/// Build an [`AGIVariableDump`]. Create this with [`AGIVariableDump::builder`].
+#[derive(Debug, Clone)]
+pub struct AGIVariableDumpBuilder {
+    dump: AGIVariableDump,
+}
+/// Generate a setter for each variable that is a simple String.
+macro_rules! string_setters {
+    ($($name:ident => $doc:literal),* $(,)?) => {
+        $(
+            #[doc = concat!("Set `", $doc, "`.")]
+            #[must_use]
+            pub fn $name<S: Into<String>>(mut self, value: S) -> Self {
+                self.dump.$name = value.into();
+                self
+            }
+        )*
+    };
+}
+impl AGIVariableDumpBuilder {
+    fn new(url: Url) -> Self {
+        let network_script = url.path().trim_start_matches('/').to_owned();
+        Self {
+            dump: AGIVariableDump {
+                network_script,
+                request: AGIRequestType::FastAGI(url),
+                channel: "PJSIP/blazing_agi-00000001".to_owned(),
+                language: "en".to_owned(),
+                channel_type: "PJSIP".to_owned(),
+                uniqueid: "1700000000.1".to_owned(),
+                version: "20.0.0".to_owned(),
+                callerid: "unknown".to_owned(),
+                calleridname: "unknown".to_owned(),
+                callingpres: "0".to_owned(),
+                callingani2: "0".to_owned(),
+                callington: "0".to_owned(),
+                callingtns: "0".to_owned(),
+                dnid: "unknown".to_owned(),
+                rdnis: "unknown".to_owned(),
+                context: "default".to_owned(),
+                extension: "s".to_owned(),
+                priority: 1,
+                enhanced: false,
+                accountcode: String::new(),
+                threadid: 1,
+                custom_args: HashMap::new(),
+            },
+        }
+    }
+
+    string_setters!(
+        network_script => "agi_network_script",
+        channel => "agi_channel",
+        language => "agi_language",
+        channel_type => "agi_type",
+        uniqueid => "agi_uniqueid",
+        version => "agi_version",
+        callerid => "agi_callerid",
+        calleridname => "agi_calleridname",
+        callingpres => "agi_callingpres",
+        callingani2 => "agi_callingani2",
+        callington => "agi_callington",
+        callingtns => "agi_callingtns",
+        dnid => "agi_dnid",
+        rdnis => "agi_rdnis",
+        context => "agi_context",
+        extension => "agi_extension",
+        accountcode => "agi_accountcode",
+    );
+
+    /// Set `agi_request`.
+    #[must_use]
+    pub fn request(mut self, request: AGIRequestType) -> Self {
+        self.dump.request = request;
+        self
+    }
+
+    /// Set `agi_priority`.
+    #[must_use]
+    pub fn priority(mut self, priority: u16) -> Self {
+        self.dump.priority = priority;
+        self
+    }
+
+    /// Set `agi_enhanced`.
+    #[must_use]
+    pub fn enhanced(mut self, enhanced: bool) -> Self {
+        self.dump.enhanced = enhanced;
+        self
+    }
+
+    /// Set `agi_threadid`.
+    #[must_use]
+    pub fn threadid(mut self, threadid: u64) -> Self {
+        self.dump.threadid = threadid;
+        self
+    }
+
+    /// Set the custom argument `agi_arg_{number}`.
+    ///
+    /// Asterisk numbers custom arguments starting from 1.
+    #[must_use]
+    pub fn arg<S: Into<String>>(mut self, number: u8, value: S) -> Self {
+        self.dump.custom_args.insert(number, value.into());
+        self
+    }
+
+    /// Finish building.
+    pub fn build(self) -> AGIVariableDump {
+        self.dump
     }
 }
 impl FromStr for AGIVariableDump {
@@ -468,6 +630,14 @@ impl FromStr for AGIVariableDump {
     }
 }
 
+/// Is `line` a status without a result, i.e. one of the error codes asterisk sends with a human
+/// readable description only?
+pub(crate) fn is_error_status(line: &str) -> bool {
+    ["510 ", "511 ", "520 "]
+        .iter()
+        .any(|code| line.starts_with(code))
+}
+
 /// All AGI Message that we may encounter.
 /// The packet send by asterisk should always be parsable as [`AGIMessage`].
 #[derive(Debug, PartialEq, Eq)]
@@ -484,7 +654,7 @@ impl FromStr for AGIMessage {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.starts_with("agi_network: yes") {
             Ok(AGIMessage::NetworkStart)
-        } else if s.contains(" result=") {
+        } else if s.contains(" result=") || is_error_status(s) {
             Ok(AGIMessage::Status(
                 s.split('\n')
                     .next()
@@ -703,6 +873,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn agi_status_op_data_with_spaces() {
+        let line = "200 result=1 (hello world)\n";
+        assert_eq!(
+            line.parse::<AGIStatusGeneric>(),
+            Ok(AGIStatusGeneric::Ok(
+                "1".to_owned(),
+                Some("(hello world)".to_owned())
+            ))
+        );
+    }
+
+    #[test]
+    fn agi_status_invalid_without_result() {
+        let line = "510 Invalid or unknown command\n";
+        assert_eq!(
+            line.parse::<AGIStatusGeneric>(),
+            Ok(AGIStatusGeneric::Invalid)
+        );
+        assert_eq!(
+            line.parse::<AGIMessage>(),
+            Ok(AGIMessage::Status(AGIStatusGeneric::Invalid))
+        );
+    }
+
+    #[test]
+    fn agi_status_end_usage_without_result() {
+        let line = "520 Invalid command syntax.  Proper usage not available.\n";
+        assert_eq!(
+            line.parse::<AGIStatusGeneric>(),
+            Ok(AGIStatusGeneric::EndUsage)
+        );
+    }
+
+    #[test]
+    fn agi_status_unknown_code_without_result() {
+        let line = "300 Something else\n";
+        assert_eq!(
+            line.parse::<AGIStatusGeneric>(),
+            Err(AGIParseError::ResultUnparsable(line.to_owned()))
+        );
+        assert!(!is_error_status(line));
+    }
+
     #[test]
     fn agi_status_unparsable_code() {
         let line = "2f00 result=1 \n";
@@ -804,4 +1018,48 @@ mod tests {
         let message = "agi_network: yes";
         assert_eq!(message.parse::<AGIMessage>(), Ok(AGIMessage::NetworkStart));
     }
+
+    #[test]
+    fn request_query_and_host() {
+        let request =
+            AGIRequestType::from_str("agi://Host.local/script?lang=de&x=a%20b&x=c&flag").unwrap();
+        assert_eq!(request.host(), Some("host.local".to_owned()));
+        let query = request.query();
+        assert_eq!(query["lang"], vec!["de"]);
+        assert_eq!(query["x"], vec!["a b", "c"]);
+        assert_eq!(query["flag"], vec![""]);
+        let file = AGIRequestType::from_str("/var/lib/asterisk/agi-bin/x?y=z").unwrap();
+        assert_eq!(file.host(), None);
+        assert!(file.query().is_empty());
+    }
+
+    #[test]
+    fn agi_variable_dump_display_roundtrip() {
+        let dump = AGIVariableDump::builder(Url::parse("agi://localhost/some/script").unwrap())
+            .callerid("123")
+            .enhanced(true)
+            .arg(2, "second")
+            .arg(1, "first")
+            .build();
+        let wire = dump.to_string();
+        assert!(wire.contains("agi_type: PJSIP\n"));
+        assert!(wire.contains("agi_enhanced: 1.0\n"));
+        assert!(wire.ends_with("agi_arg_1: first\nagi_arg_2: second\n\n"));
+        assert_eq!(wire.parse::<AGIVariableDump>(), Ok(dump));
+    }
+
+    #[test]
+    fn agi_status_display_roundtrip() {
+        for status in [
+            AGIStatusGeneric::Ok("1".to_owned(), Some("(some value)".to_owned())),
+            AGIStatusGeneric::Invalid,
+            AGIStatusGeneric::DeadChannel,
+            AGIStatusGeneric::EndUsage,
+        ] {
+            assert_eq!(
+                format!("{status}\n").parse::<AGIMessage>(),
+                Ok(AGIMessage::Status(status))
+            );
+        }
+    }
 }
@@ -0,0 +1,726 @@
+//! Authenticate asterisk before handling a request.
+//!
+//! This module is only available with the `auth` feature.
+//!
+//! `FastAGI` has no authentication of its own. This module has two layers for it:
+//! - [`DigestLayer`] asks asterisk for a digest of a shared secret and a fresh nonce, at the
+//!   cost of one round-trip per session.
+//! - [`SignedUrlLayer`] checks an HMAC signature the dialplan put into the request, so sessions
+//!   start without an extra round-trip.
+//!
+//! For a [`DigestLayer`], we send a fresh nonce for each request and ask asterisk to evaluate
+//! `${SHA1(${BLAZING_AGI_DIGEST_SECRET}:<nonce>)}` in the calling channel. Only if the digest is
+//! correct does the layered handler run.
+//!
+//! In the asterisk dialplan, set the secret before calling the `FastAGI` server:
+//! ```text
+//! same => n,Set(BLAZING_AGI_DIGEST_SECRET=top_secret)
+//! same => n,AGI(agi://localhost/protected)
+//! ```
+//! and protect the routes with the layer:
+//! ```
+//! use blazing_agi::{auth::DigestLayer, router::Router};
+//!
+//! let router = Router::new()
+//!     .layer(DigestLayer::new("top_secret"))
+//!     .layer_fallback(true);
+//! ```
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Digest;
+
+use crate::{
+    command::{AGIResponse, GetFullVariable, Verbose},
+    handler::AGIHandler,
+    layer::Layer,
+    AGIError, AGIRequest, Connection,
+};
+
+/// The hash function asterisk uses to compute the digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DigestAlgorithm {
+    /// SHA-1, with the `SHA1` dialplan function.
+    #[default]
+    Sha1,
+    /// SHA-256, with the `SHA256` dialplan function. Not every asterisk version has this
+    /// function; check with `core show function SHA256`.
+    Sha256,
+}
+impl DigestAlgorithm {
+    /// The name of the dialplan function computing this hash.
+    fn function(self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+        }
+    }
+
+    /// The digest of `data`.
+    fn digest(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            Self::Sha256 => sha2::Sha256::digest(data).to_vec(),
+        }
+    }
+
+    /// The HMAC of `data` with `key`.
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha1 => {
+                let mut mac = Hmac::<sha1::Sha1>::new_from_slice(key)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            Self::Sha256 => {
+                let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key)
+                    .expect("HMAC accepts keys of any length");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+}
+
+/// The nonces handed out recently. A nonce is never handed out twice while it is remembered.
+#[derive(Debug, Default)]
+struct NonceCache {
+    issued: HashMap<String, Instant>,
+}
+impl NonceCache {
+    /// Create a nonce that was not handed out within the last `lifetime`.
+    fn issue(&mut self, lifetime: Duration) -> String {
+        let now = Instant::now();
+        self.issued
+            .retain(|_, issued| now.duration_since(*issued) < lifetime);
+        loop {
+            let nonce = create_nonce();
+            if !self.issued.contains_key(&nonce) {
+                self.issued.insert(nonce.clone(), now);
+                return nonce;
+            };
+        }
+    }
+}
+
+/// Create a 20-byte nonce with 8 bytes of randomness, encoded as a hex string.
+fn create_nonce() -> String {
+    let mut raw_bytes = [0_u8; 20];
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Should be after the epoch");
+    // 12 bytes against reuse
+    raw_bytes[0..=7].clone_from_slice(&now.as_secs().to_le_bytes());
+    raw_bytes[8..=11].clone_from_slice(&now.subsec_nanos().to_le_bytes());
+    // 8 bytes against predictability
+    rand::thread_rng().fill(&mut raw_bytes[12..=19]);
+    hex::encode(raw_bytes)
+}
+
+/// Check that `secret` can be used by a [`DigestLayer`].
+fn digest_secret(secret: String) -> String {
+    assert!(
+        !secret.is_empty(),
+        "The secret of a DigestLayer must not be empty"
+    );
+    secret
+}
+
+/// Compare two digests in time independent of where they differ.
+fn digests_equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// A [`Layer`] running the layered handler only if asterisk proves it knows a shared secret.
+///
+/// See the [module documentation](self) for how this works. Every failure to authenticate is
+/// returned as [`AGIError::ClientSideError`].
+///
+/// Clones of a layer share the set of recently used nonces.
+#[derive(Clone)]
+pub struct DigestLayer {
+    algorithm: DigestAlgorithm,
+    secrets: Vec<String>,
+    variable: String,
+    nonce_lifetime: Duration,
+    nonces: Arc<Mutex<NonceCache>>,
+}
+impl DigestLayer {
+    /// Create a layer accepting `secret`, with SHA-1 and the channel variable
+    /// `BLAZING_AGI_DIGEST_SECRET`.
+    ///
+    /// # Panics
+    /// Panics if `secret` is empty. Asterisk computes the digest of an unset variable like that
+    /// of an empty one, so an empty secret would let in every channel without the variable.
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Self {
+            algorithm: DigestAlgorithm::default(),
+            secrets: vec![digest_secret(secret.into())],
+            variable: "BLAZING_AGI_DIGEST_SECRET".to_owned(),
+            nonce_lifetime: Duration::from_secs(300),
+            nonces: Arc::default(),
+        }
+    }
+
+    /// Also accept `secret`, e.g. while rotating secrets.
+    ///
+    /// # Panics
+    /// Panics if `secret` is empty (see [`new`](Self::new)).
+    #[must_use]
+    pub fn secret<S: Into<String>>(mut self, secret: S) -> Self {
+        self.secrets.push(digest_secret(secret.into()));
+        self
+    }
+
+    /// Use `algorithm` to compute the digest.
+    #[must_use]
+    pub fn algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Read the secret from the channel variable `variable` instead of
+    /// `BLAZING_AGI_DIGEST_SECRET`.
+    #[must_use]
+    pub fn variable<S: Into<String>>(mut self, variable: S) -> Self {
+        self.variable = variable.into();
+        self
+    }
+
+    /// Remember used nonces for `lifetime` (default: 5 minutes).
+    #[must_use]
+    pub fn nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.nonce_lifetime = lifetime;
+        self
+    }
+
+    /// Check the digest asterisk sent for `nonce`.
+    fn accepts(&self, nonce: &str, digest: &[u8]) -> bool {
+        // check all secrets, so the time taken does not tell which one matched
+        self.secrets.iter().fold(false, |found, secret| {
+            let expected = self
+                .algorithm
+                .digest(format!("{secret}:{nonce}").as_bytes());
+            digests_equal(&expected, digest) | found
+        })
+    }
+
+    /// Let asterisk prove that it knows a secret.
+    async fn authenticate(&self, connection: &mut Connection) -> Result<(), AGIError> {
+        let nonce = self
+            .nonces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .issue(self.nonce_lifetime);
+        let response = connection
+            .send_command(GetFullVariable::new(format!(
+                "${{{}(${{{}}}:{nonce})}}",
+                self.algorithm.function(),
+                self.variable
+            )))
+            .await?;
+        // asterisk computes the digest even if the variable is unset, so there is always a value
+        let value = match response {
+            AGIResponse::Ok(inner) => inner.value.unwrap_or_default(),
+            m => return Err(AGIError::Not200(m.into())),
+        };
+        let Ok(digest) = hex::decode(&value) else {
+            return Err(AGIError::ClientSideError(format!(
+                "The digest {value} is not hex-encoded"
+            )));
+        };
+        if self.accepts(&nonce, &digest) {
+            Ok(())
+        } else {
+            connection
+                .send_command(Verbose::new("Unauthenticated: Wrong Digest.".to_owned()))
+                .await?;
+            Err(AGIError::ClientSideError("Wrong digest".to_owned()))
+        }
+    }
+}
+impl core::fmt::Debug for DigestLayer {
+    // never show the secrets
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("DigestLayer")
+            .field("algorithm", &self.algorithm)
+            .field("variable", &self.variable)
+            .field("nonce_lifetime", &self.nonce_lifetime)
+            .finish_non_exhaustive()
+    }
+}
+impl Layer for DigestLayer {
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(DigestHandler {
+            layer: self.clone(),
+            inner: Box::new(handler),
+        })
+    }
+}
+
+/// The handler created by a [`DigestLayer`].
+#[derive(Debug)]
+struct DigestHandler {
+    layer: DigestLayer,
+    inner: Box<dyn AGIHandler>,
+}
+#[async_trait::async_trait]
+impl AGIHandler for DigestHandler {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        self.layer.authenticate(connection).await?;
+        self.inner.handle(connection, request).await
+    }
+}
+
+/// Where in the request a [`SignedUrlLayer`] finds a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Param {
+    /// The query parameter with this name, like `sig` in `agi://host/route?sig=...`.
+    Query(String),
+    /// The custom argument `agi_arg_n` with this `n`.
+    Arg(u8),
+}
+impl Param {
+    /// The value of this parameter in `request`.
+    fn value<'a>(&self, request: &'a AGIRequest) -> Option<&'a str> {
+        match self {
+            Self::Query(name) => request.query_param(name),
+            Self::Arg(n) => request.variables.custom_args.get(n).map(String::as_str),
+        }
+    }
+}
+impl core::fmt::Display for Param {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Query(name) => write!(f, "query parameter {name}"),
+            Self::Arg(n) => write!(f, "agi_arg_{n}"),
+        }
+    }
+}
+
+/// A [`Layer`] running the layered handler only if the request carries a valid, unexpired
+/// signature.
+///
+/// The dialplan signs `<path>:<uniqueid>:<expires>` with HMAC and a shared secret, where `path`
+/// is the path of the request url, `uniqueid` the `UNIQUEID` of the channel and `expires` the
+/// unix timestamp (in seconds) after which the signature is no longer valid. It passes the
+/// hex-encoded signature and the timestamp along with the request, by default as the query
+/// parameters `signature` and `expires`:
+/// ```text
+/// same => n,Set(EXPIRES=$[${EPOCH} + 30])
+/// same => n,Set(SIGNATURE=${HMAC_SHA256(top_secret,/protected:${UNIQUEID}:${EXPIRES})})
+/// same => n,AGI(agi://localhost/protected?expires=${EXPIRES}&signature=${SIGNATURE})
+/// ```
+/// Asterisk has no HMAC function built in; `HMAC_SHA256` stands for whatever computes it in your
+/// setup (e.g. `func_odbc` or a `SHELL` call).
+///
+/// Every failure to authenticate is returned as [`AGIError::ClientSideError`].
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use blazing_agi::{auth::{Param, SignedUrlLayer}, router::Router};
+///
+/// let router = Router::new().layer(
+///     SignedUrlLayer::new("top_secret")
+///         .signature(Param::Arg(1))
+///         .max_validity(Duration::from_secs(60)),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct SignedUrlLayer {
+    algorithm: DigestAlgorithm,
+    secrets: Vec<String>,
+    signature: Param,
+    expires: Param,
+    max_validity: Duration,
+}
+impl SignedUrlLayer {
+    /// Create a layer accepting signatures with `secret`, using HMAC-SHA256 and the query
+    /// parameters `signature` and `expires`.
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Self {
+            algorithm: DigestAlgorithm::Sha256,
+            secrets: vec![secret.into()],
+            signature: Param::Query("signature".to_owned()),
+            expires: Param::Query("expires".to_owned()),
+            max_validity: Duration::from_secs(300),
+        }
+    }
+
+    /// Also accept signatures with `secret`, e.g. while rotating secrets.
+    #[must_use]
+    pub fn secret<S: Into<String>>(mut self, secret: S) -> Self {
+        self.secrets.push(secret.into());
+        self
+    }
+
+    /// Use HMAC with `algorithm`.
+    #[must_use]
+    pub fn algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Read the signature from `param`.
+    #[must_use]
+    pub fn signature(mut self, param: Param) -> Self {
+        self.signature = param;
+        self
+    }
+
+    /// Read the expiry timestamp from `param`.
+    #[must_use]
+    pub fn expires(mut self, param: Param) -> Self {
+        self.expires = param;
+        self
+    }
+
+    /// Reject signatures expiring more then `validity` in the future (default: 5 minutes), so a
+    /// leaked signature cannot be used for long.
+    #[must_use]
+    pub fn max_validity(mut self, validity: Duration) -> Self {
+        self.max_validity = validity;
+        self
+    }
+
+    /// Check the signature of `request`.
+    fn authenticate(&self, request: &AGIRequest) -> Result<(), AGIError> {
+        let missing = |param: &Param| AGIError::ClientSideError(format!("The {param} is missing"));
+        let signature = self
+            .signature
+            .value(request)
+            .ok_or_else(|| missing(&self.signature))?;
+        let expires = self
+            .expires
+            .value(request)
+            .ok_or_else(|| missing(&self.expires))?;
+        let Ok(expires_at) = expires.parse::<u64>() else {
+            return Err(AGIError::ClientSideError(format!(
+                "The expiry {expires} is not a unix timestamp"
+            )));
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("Should be after the epoch")
+            .as_secs();
+        if expires_at < now {
+            return Err(AGIError::ClientSideError(
+                "The signature has expired".to_owned(),
+            ));
+        };
+        if expires_at - now > self.max_validity.as_secs() {
+            return Err(AGIError::ClientSideError(
+                "The signature is valid for too long".to_owned(),
+            ));
+        };
+        let Ok(signature) = hex::decode(signature) else {
+            return Err(AGIError::ClientSideError(format!(
+                "The signature {signature} is not hex-encoded"
+            )));
+        };
+        let path = match &request.variables.request {
+            crate::AGIRequestType::FastAGI(url) => url.path(),
+            crate::AGIRequestType::File(_) => "",
+        };
+        let message = format!("{path}:{}:{expires}", request.variables.uniqueid);
+        // check all secrets, so the time taken does not tell which one matched
+        let valid = self.secrets.iter().fold(false, |found, secret| {
+            let expected = self.algorithm.hmac(secret.as_bytes(), message.as_bytes());
+            digests_equal(&expected, &signature) | found
+        });
+        if valid {
+            Ok(())
+        } else {
+            Err(AGIError::ClientSideError("Wrong signature".to_owned()))
+        }
+    }
+}
+impl core::fmt::Debug for SignedUrlLayer {
+    // never show the secrets
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("SignedUrlLayer")
+            .field("algorithm", &self.algorithm)
+            .field("signature", &self.signature)
+            .field("expires", &self.expires)
+            .field("max_validity", &self.max_validity)
+            .finish_non_exhaustive()
+    }
+}
+impl Layer for SignedUrlLayer {
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(SignedUrlHandler {
+            layer: self.clone(),
+            inner: Box::new(handler),
+        })
+    }
+}
+
+/// The handler created by a [`SignedUrlLayer`].
+#[derive(Debug)]
+struct SignedUrlHandler {
+    layer: SignedUrlLayer,
+    inner: Box<dyn AGIHandler>,
+}
+#[async_trait::async_trait]
+impl AGIHandler for SignedUrlHandler {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        self.layer.authenticate(request)?;
+        self.inner.handle(connection, request).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use url::Url;
+
+    use super::*;
+    use crate::{client::AGIClientSession, router::Router, AGIStatusGeneric, AGIVariableDump};
+
+    #[derive(Debug)]
+    struct Protected;
+    #[async_trait::async_trait]
+    impl AGIHandler for Protected {
+        async fn handle(&self, conn: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            conn.send_command(Verbose::new("protected".to_owned()))
+                .await?;
+            Ok(())
+        }
+    }
+
+    /// Play asterisk with `secret` set in the channel variable `variable`, computing digests
+    /// with `algorithm`. Returns the commands sent by the router after the digest.
+    async fn commands_with(
+        router: Router,
+        algorithm: DigestAlgorithm,
+        variable: &str,
+        secret: &str,
+    ) -> Vec<String> {
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { router.handle(server_side).await });
+        let request = AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build();
+        let session = AGIClientSession::start(client_side, &request)
+            .await
+            .unwrap();
+        let prefix = format!(
+            "GET FULL VARIABLE \"${{{}(${{{variable}}}:",
+            algorithm.function()
+        );
+        let mut commands = vec![];
+        session
+            .run(|command| {
+                let response = match command.strip_prefix(&prefix) {
+                    Some(rest) => {
+                        let nonce = rest.trim_end_matches(")}\"");
+                        let digest = algorithm.digest(format!("{secret}:{nonce}").as_bytes());
+                        AGIStatusGeneric::Ok(
+                            "1".to_owned(),
+                            Some(format!("({})", hex::encode(digest))),
+                        )
+                    }
+                    None => {
+                        commands.push(command);
+                        AGIStatusGeneric::Ok("1".to_owned(), None)
+                    }
+                };
+                async { response }
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+        commands
+    }
+
+    #[tokio::test]
+    async fn correct_digests_pass() {
+        let router = Router::new()
+            .route("/", Protected)
+            .layer(DigestLayer::new("top_secret"));
+        assert_eq!(
+            commands_with(
+                router,
+                DigestAlgorithm::Sha1,
+                "BLAZING_AGI_DIGEST_SECRET",
+                "top_secret"
+            )
+            .await,
+            vec!["VERBOSE \"protected\""]
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_digests_are_rejected() {
+        let router = Router::new()
+            .route("/", Protected)
+            .layer(DigestLayer::new("top_secret"));
+        assert_eq!(
+            commands_with(
+                router,
+                DigestAlgorithm::Sha1,
+                "BLAZING_AGI_DIGEST_SECRET",
+                "guessed"
+            )
+            .await,
+            vec!["VERBOSE \"Unauthenticated: Wrong Digest.\""]
+        );
+    }
+
+    #[tokio::test]
+    async fn unset_variables_are_rejected() {
+        // asterisk expands ${SHA1(${UNSET}:nonce)} to the digest of ":nonce"
+        let router = Router::new()
+            .route("/", Protected)
+            .layer(DigestLayer::new("top_secret"));
+        assert_eq!(
+            commands_with(
+                router,
+                DigestAlgorithm::Sha1,
+                "BLAZING_AGI_DIGEST_SECRET",
+                ""
+            )
+            .await,
+            vec!["VERBOSE \"Unauthenticated: Wrong Digest.\""]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The secret of a DigestLayer must not be empty")]
+    fn empty_secrets_panic() {
+        let _ = DigestLayer::new("top_secret").secret("");
+    }
+
+    #[tokio::test]
+    async fn rotated_secrets_algorithm_and_variable_are_configurable() {
+        let layer = DigestLayer::new("old")
+            .secret("new")
+            .algorithm(DigestAlgorithm::Sha256)
+            .variable("AGI_KEY");
+        for secret in ["old", "new"] {
+            let router = Router::new().route("/", Protected).layer(layer.clone());
+            assert_eq!(
+                commands_with(router, DigestAlgorithm::Sha256, "AGI_KEY", secret).await,
+                vec!["VERBOSE \"protected\""]
+            );
+        }
+    }
+
+    #[test]
+    fn nonces_are_not_reused() {
+        let mut cache = NonceCache::default();
+        let first = cache.issue(Duration::from_secs(60));
+        let second = cache.issue(Duration::from_secs(60));
+        assert_ne!(first, second);
+        assert_eq!(cache.issued.len(), 2);
+        // expired nonces are forgotten
+        cache.issue(Duration::ZERO);
+        assert_eq!(cache.issued.len(), 1);
+    }
+
+    #[test]
+    fn debug_hides_secrets() {
+        assert!(!format!("{:?}", DigestLayer::new("top_secret")).contains("top_secret"));
+    }
+
+    /// A request for `url` from the channel with uniqueid `1234.5`.
+    fn signed_request(url: &str, args: &[(u8, &str)]) -> AGIRequest {
+        let mut variables = AGIVariableDump::builder(Url::parse(url).unwrap())
+            .uniqueid("1234.5")
+            .build();
+        for (n, value) in args {
+            variables.custom_args.insert(*n, (*value).to_owned());
+        }
+        AGIRequest {
+            query: variables.request.query(),
+            variables,
+            captures: std::collections::HashMap::new(),
+            wildcards: None,
+            host: None,
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        }
+    }
+
+    fn sign(secret: &str, message: &str) -> String {
+        hex::encode(DigestAlgorithm::Sha256.hmac(secret.as_bytes(), message.as_bytes()))
+    }
+
+    fn in_secs(secs: u64) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + secs
+    }
+
+    #[test]
+    fn valid_signatures_pass() {
+        let layer = SignedUrlLayer::new("old").secret("new");
+        let expires = in_secs(30);
+        for secret in ["old", "new"] {
+            let signature = sign(secret, &format!("/protected:1234.5:{expires}"));
+            let request = signed_request(
+                &format!("agi://host/protected?expires={expires}&signature={signature}"),
+                &[],
+            );
+            assert!(layer.authenticate(&request).is_ok());
+        }
+    }
+
+    #[test]
+    fn signatures_in_args() {
+        let layer = SignedUrlLayer::new("secret")
+            .signature(Param::Arg(1))
+            .expires(Param::Arg(2));
+        let expires = in_secs(30).to_string();
+        let signature = sign("secret", &format!("/protected:1234.5:{expires}"));
+        let request = signed_request("agi://host/protected", &[(1, &signature), (2, &expires)]);
+        assert!(layer.authenticate(&request).is_ok());
+    }
+
+    #[test]
+    fn invalid_signatures_are_rejected() {
+        let layer = SignedUrlLayer::new("secret");
+        let rejected = |url: String| {
+            matches!(
+                layer.authenticate(&signed_request(&url, &[])),
+                Err(AGIError::ClientSideError(_))
+            )
+        };
+        let expires = in_secs(30);
+        // signed for another route
+        let signature = sign("secret", &format!("/other:1234.5:{expires}"));
+        assert!(rejected(format!(
+            "agi://host/protected?expires={expires}&signature={signature}"
+        )));
+        // expired
+        let expires = in_secs(0) - 10;
+        let signature = sign("secret", &format!("/protected:1234.5:{expires}"));
+        assert!(rejected(format!(
+            "agi://host/protected?expires={expires}&signature={signature}"
+        )));
+        // valid for too long
+        let expires = in_secs(3600);
+        let signature = sign("secret", &format!("/protected:1234.5:{expires}"));
+        assert!(rejected(format!(
+            "agi://host/protected?expires={expires}&signature={signature}"
+        )));
+        // missing
+        assert!(rejected("agi://host/protected".to_owned()));
+    }
+}
@@ -0,0 +1,241 @@
+//! Speak the client side (asterisk) of `FastAGI`.
+//!
+//! A client connects to a `FastAGI` server, sends the initial request and then receives the
+//! commands the server sends, answering each one with a status. This is what asterisk does, but
+//! it is just as useful for proxies, bridges from other softswitches or load tests.
+//! ```no_run
+//! use blazing_agi::{client::AGIClientSession, AGIStatusGeneric, AGIVariableDump};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let request = AGIVariableDump::builder("agi://localhost:4573/greet".parse()?)
+//!     .callerid("+4930123456")
+//!     .build();
+//! let mut session = AGIClientSession::connect(request).await?;
+//! while let Some(command) = session.next_command().await? {
+//!     println!("server sent {command}");
+//!     session
+//!         .respond(&AGIStatusGeneric::Ok("0".to_owned(), None))
+//!         .await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::future::Future;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+
+use crate::connection::AGIStream;
+use crate::{AGIRequestType, AGIStatusGeneric, AGIVariableDump};
+
+/// The port `FastAGI` servers listen on, unless the url says otherwise.
+pub const DEFAULT_PORT: u16 = 4573;
+
+/// Contains all the ways in which the client side of a session can fail.
+#[derive(Debug)]
+pub enum AGIClientError {
+    /// The request is not a `FastAGI` request (`agi://`).
+    NotFastAGI(AGIRequestType),
+    /// The requested url does not contain a host to connect to.
+    NoHost(String),
+    /// Unable to connect to the server.
+    CannotConnect(tokio::io::Error),
+    /// Unable to read from or write to the server.
+    Io(tokio::io::Error),
+}
+impl core::fmt::Display for AGIClientError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::NotFastAGI(x) => {
+                write!(f, "The request {x} is not a FastAGI request")
+            }
+            Self::NoHost(x) => {
+                write!(f, "The url {x} does not contain a host")
+            }
+            Self::CannotConnect(x) => {
+                write!(f, "Unable to connect to the FastAGI server: {x}")
+            }
+            Self::Io(x) => {
+                write!(f, "Unable to talk to the FastAGI server: {x}")
+            }
+        }
+    }
+}
+impl std::error::Error for AGIClientError {}
+
+/// A single session with a `FastAGI` server, seen from the client (asterisk).
+///
+/// Create this with [`connect`](Self::connect), or with [`start`](Self::start) over any other
+/// stream.
+#[derive(Debug)]
+pub struct AGIClientSession {
+    reader: BufReader<ReadHalf<Box<dyn AGIStream>>>,
+    writer: WriteHalf<Box<dyn AGIStream>>,
+}
+impl AGIClientSession {
+    /// Connect to the server in the `agi_request` of `request` and send the request.
+    ///
+    /// # Errors
+    /// Returns an Error if `request` is not a `FastAGI` request or the server cannot be reached.
+    pub async fn connect(request: AGIVariableDump) -> Result<Self, AGIClientError> {
+        let url = match &request.request {
+            AGIRequestType::FastAGI(x) => x,
+            x => return Err(AGIClientError::NotFastAGI(x.clone())),
+        };
+        let host = url
+            .host_str()
+            .ok_or_else(|| AGIClientError::NoHost(url.to_string()))?;
+        let port = url.port().unwrap_or(DEFAULT_PORT);
+        let stream = TcpStream::connect((host, port))
+            .await
+            .map_err(AGIClientError::CannotConnect)?;
+        Self::start(stream, &request).await
+    }
+
+    /// Start a session over an existing `stream` by sending `request`.
+    ///
+    /// # Errors
+    /// Returns an Error if the request cannot be sent.
+    pub async fn start<S: AGIStream + 'static>(
+        stream: S,
+        request: &AGIVariableDump,
+    ) -> Result<Self, AGIClientError> {
+        let stream: Box<dyn AGIStream> = Box::new(stream);
+        let (read_half, write_half) = tokio::io::split(stream);
+        let mut session = Self {
+            reader: BufReader::new(read_half),
+            writer: write_half,
+        };
+        session.send("agi_network: yes\n").await?;
+        session.send(&request.to_string()).await?;
+        Ok(session)
+    }
+
+    /// Send `text` to the server as-is.
+    async fn send(&mut self, text: &str) -> Result<(), AGIClientError> {
+        self.writer
+            .write_all(text.as_bytes())
+            .await
+            .map_err(AGIClientError::Io)
+    }
+
+    /// Wait for the next command the server sends (without the trailing newline).
+    ///
+    /// Returns `Ok(None)` when the server closed the connection.
+    ///
+    /// # Errors
+    /// Returns an Error if the stream cannot be read.
+    pub async fn next_command(&mut self) -> Result<Option<String>, AGIClientError> {
+        let mut line = String::new();
+        let bytes_read = self
+            .reader
+            .read_line(&mut line)
+            .await
+            .map_err(AGIClientError::Io)?;
+        if bytes_read == 0 {
+            return Ok(None);
+        };
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+    }
+
+    /// Respond to the last command with `status`.
+    ///
+    /// # Errors
+    /// Returns an Error if the status cannot be sent.
+    pub async fn respond(&mut self, status: &AGIStatusGeneric) -> Result<(), AGIClientError> {
+        self.send(&format!("{status}\n")).await
+    }
+
+    /// Answer every command with the status `handler` computes for it, until the server closes
+    /// the connection.
+    ///
+    /// # Errors
+    /// Returns an Error if the stream cannot be read or written.
+    pub async fn run<F, Fut>(mut self, mut handler: F) -> Result<(), AGIClientError>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = AGIStatusGeneric>,
+    {
+        while let Some(command) = self.next_command().await? {
+            let status = handler(command).await;
+            self.respond(&status).await?;
+        }
+        Ok(())
+    }
+
+    /// Hang up: Close our side of the connection.
+    ///
+    /// # Errors
+    /// Returns an Error if the stream cannot be shut down cleanly.
+    pub async fn hangup(mut self) -> Result<(), AGIClientError> {
+        self.writer.shutdown().await.map_err(AGIClientError::Io)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+
+    use crate::command::{Answer, Verbose};
+    use crate::connection::Connection;
+    use crate::handler::AGIHandler;
+    use crate::router::Router;
+    use crate::{AGIError, AGIRequest};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct AnswerAndGreet {}
+    #[async_trait::async_trait]
+    impl AGIHandler for AnswerAndGreet {
+        async fn handle(
+            &self,
+            connection: &mut Connection,
+            request: &AGIRequest,
+        ) -> Result<(), AGIError> {
+            connection.send_command(Answer::new()).await?;
+            connection
+                .send_command(Verbose::new(format!(
+                    "Hello {}",
+                    request.variables.callerid
+                )))
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn session_against_router() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(crate::serve::serve(
+            listener,
+            Router::new().route("/greet", AnswerAndGreet {}),
+        ));
+        let request =
+            AGIVariableDump::builder(format!("agi://127.0.0.1:{port}/greet").parse().unwrap())
+                .callerid("Obi-Wan")
+                .build();
+        let session = AGIClientSession::connect(request).await.unwrap();
+        let mut commands = vec![];
+        session
+            .run(|command| {
+                let result = if command == "ANSWER" { "0" } else { "1" };
+                commands.push(command);
+                async move { AGIStatusGeneric::Ok(result.to_owned(), None) }
+            })
+            .await
+            .unwrap();
+        assert_eq!(commands, vec!["ANSWER", "VERBOSE \"Hello Obi-Wan\""]);
+    }
+
+    #[tokio::test]
+    async fn file_requests_are_rejected() {
+        let mut request = AGIVariableDump::builder("agi://localhost/".parse().unwrap()).build();
+        request.request = AGIRequestType::File("/var/lib/asterisk/agi-bin/x".into());
+        assert!(matches!(
+            AGIClientSession::connect(request).await,
+            Err(AGIClientError::NotFastAGI(_))
+        ));
+    }
+}
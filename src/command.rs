@@ -51,6 +51,26 @@ pub mod get_full_variable;
 pub use self::get_full_variable::GetFullVariable;
 pub mod set_variable;
 pub use self::set_variable::SetVariable;
+pub mod set_music;
+pub use self::set_music::SetMusic;
+pub mod get_variable;
+pub use self::get_variable::GetVariable;
+pub mod hangup;
+pub use self::hangup::Hangup;
+pub mod wait_for_digit;
+pub use self::wait_for_digit::WaitForDigit;
+pub mod get_data;
+pub use self::get_data::GetData;
+pub mod stream_file;
+pub use self::stream_file::StreamFile;
+pub mod database_get;
+pub use self::database_get::DatabaseGet;
+pub mod database_put;
+pub use self::database_put::DatabasePut;
+pub mod database_del;
+pub use self::database_del::DatabaseDel;
+pub mod database_deltree;
+pub use self::database_deltree::DatabaseDelTree;
 
 /// An Error that occured while converting an [`AGIStatusGeneric`](crate::agiparse::AGIStatusGeneric) to a specialized response.
 #[derive(Debug, PartialEq)]
@@ -119,7 +139,7 @@ pub trait AGICommand: core::fmt::Display + core::fmt::Debug + Send + Sync {
 }
 
 /// Characters a user can type when getting DTMF data
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Characters {
     Zero,
     One,
@@ -135,6 +155,65 @@ pub enum Characters {
     Pound,
 }
 
+impl Characters {
+    /// Get the character from the ascii code asterisk sends as result for DTMF input
+    pub(crate) fn from_ascii_code(code: i32) -> Option<Characters> {
+        u8::try_from(code)
+            .ok()
+            .and_then(|x| Characters::try_from(char::from(x)).ok())
+    }
+}
+impl TryFrom<char> for Characters {
+    type Error = char;
+    fn try_from(val: char) -> Result<Self, Self::Error> {
+        match val {
+            '0' => Ok(Characters::Zero),
+            '1' => Ok(Characters::One),
+            '2' => Ok(Characters::Two),
+            '3' => Ok(Characters::Three),
+            '4' => Ok(Characters::Four),
+            '5' => Ok(Characters::Five),
+            '6' => Ok(Characters::Six),
+            '7' => Ok(Characters::Seven),
+            '8' => Ok(Characters::Eight),
+            '9' => Ok(Characters::Nine),
+            '*' => Ok(Characters::Star),
+            '#' => Ok(Characters::Pound),
+            x => Err(x),
+        }
+    }
+}
+impl From<&Characters> for char {
+    fn from(val: &Characters) -> Self {
+        match val {
+            Characters::Zero => '0',
+            Characters::One => '1',
+            Characters::Two => '2',
+            Characters::Three => '3',
+            Characters::Four => '4',
+            Characters::Five => '5',
+            Characters::Six => '6',
+            Characters::Seven => '7',
+            Characters::Eight => '8',
+            Characters::Nine => '9',
+            Characters::Star => '*',
+            Characters::Pound => '#',
+        }
+    }
+}
+impl From<Characters> for char {
+    fn from(val: Characters) -> Self {
+        char::from(&val)
+    }
+}
+
+/// Strip the parentheses asterisk puts around values in the operational data of a status.
+pub(crate) fn strip_parens(x: &str) -> &str {
+    x.strip_prefix('(')
+        .and_then(|x| x.strip_suffix(')'))
+        .unwrap_or(x)
+}
+
 /// Digits a user can type
 #[derive(Debug, PartialEq)]
 pub enum Digit {
@@ -0,0 +1,100 @@
+//! Defines the `DATABASE DEL` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/database_del/)
+use super::*;
+
+/// The Database Del command.
+///
+/// Delete a single key from the asterisk database (astdb).
+/// ```
+/// use blazing_agi::command::DatabaseDel;
+/// let cmd = DatabaseDel::new("cidname".to_owned(), "0123456789".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "DATABASE DEL \"cidname\" \"0123456789\"\n")
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`DatabaseDelResponse`].
+#[derive(Debug)]
+pub struct DatabaseDel {
+    family: String,
+    key: String,
+}
+impl DatabaseDel {
+    /// Create [`DatabaseDel`]. When sent, this will delete `family/key`.
+    pub fn new(family: String, key: String) -> Self {
+        Self { family, key }
+    }
+}
+impl core::fmt::Display for DatabaseDel {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "DATABASE DEL \"{}\" \"{}\"", self.family, self.key)
+    }
+}
+impl AGICommand for DatabaseDel {
+    type Response = DatabaseDelResponse;
+}
+
+/// The responses we can get when sending [`DatabaseDel`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub enum DatabaseDelResponse {
+    /// The key was deleted.
+    Success,
+    /// The key could not be deleted, e.g. because it did not exist.
+    Failure,
+}
+impl InnerAGIResponse for DatabaseDelResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`DatabaseDelResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`DatabaseDel`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for DatabaseDelResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match result {
+            "1" => Ok(DatabaseDelResponse::Success),
+            "0" => Ok(DatabaseDelResponse::Failure),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "DATABASE DEL",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let cmd = DatabaseDel::new("fam".to_owned(), "key".to_owned());
+        assert_eq!(cmd.to_string(), "DATABASE DEL \"fam\" \"key\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            DatabaseDelResponse::try_from(("1", None)).unwrap(),
+            DatabaseDelResponse::Success
+        );
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert_eq!(
+            DatabaseDelResponse::try_from(("0", None)).unwrap(),
+            DatabaseDelResponse::Failure
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            DatabaseDelResponse::try_from(("-1", None)),
+            Err(AGIStatusParseError {
+                result: "-1".to_owned(),
+                op_data: None,
+                response_to_command: "DATABASE DEL"
+            })
+        );
+    }
+}
@@ -0,0 +1,108 @@
+//! Defines the `DATABASE DELTREE` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/database_deltree/)
+use super::*;
+
+/// The Database Deltree command.
+///
+/// Delete an entire family, or a keytree within a family, from the asterisk database (astdb).
+/// ```
+/// use blazing_agi::command::DatabaseDelTree;
+/// let cmd = DatabaseDelTree::new("cidname".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "DATABASE DELTREE \"cidname\"\n");
+/// let cmd = DatabaseDelTree::new("cidname".to_owned()).with_keytree("0123".to_owned());
+/// assert_eq!(cmd.to_string(), "DATABASE DELTREE \"cidname\" \"0123\"\n");
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`DatabaseDelTreeResponse`].
+#[derive(Debug)]
+pub struct DatabaseDelTree {
+    family: String,
+    keytree: Option<String>,
+}
+impl DatabaseDelTree {
+    /// Create [`DatabaseDelTree`]. When sent, this will delete all keys in `family`.
+    pub fn new(family: String) -> Self {
+        Self {
+            family,
+            keytree: None,
+        }
+    }
+
+    /// Only delete the keys below `keytree` in the family.
+    pub fn with_keytree(self, keytree: String) -> Self {
+        Self {
+            keytree: Some(keytree),
+            ..self
+        }
+    }
+}
+impl core::fmt::Display for DatabaseDelTree {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match &self.keytree {
+            Some(keytree) => writeln!(f, "DATABASE DELTREE \"{}\" \"{keytree}\"", self.family),
+            None => writeln!(f, "DATABASE DELTREE \"{}\"", self.family),
+        }
+    }
+}
+impl AGICommand for DatabaseDelTree {
+    type Response = DatabaseDelTreeResponse;
+}
+
+/// The responses we can get when sending [`DatabaseDelTree`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub enum DatabaseDelTreeResponse {
+    /// The keys were deleted.
+    Success,
+    /// The keys could not be deleted, e.g. because there were none.
+    Failure,
+}
+impl InnerAGIResponse for DatabaseDelTreeResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`DatabaseDelTreeResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`DatabaseDelTree`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for DatabaseDelTreeResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match result {
+            "1" => Ok(DatabaseDelTreeResponse::Success),
+            "0" => Ok(DatabaseDelTreeResponse::Failure),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "DATABASE DELTREE",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let cmd = DatabaseDelTree::new("fam".to_owned());
+        assert_eq!(cmd.to_string(), "DATABASE DELTREE \"fam\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            DatabaseDelTreeResponse::try_from(("1", None)).unwrap(),
+            DatabaseDelTreeResponse::Success
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            DatabaseDelTreeResponse::try_from(("-1", None)),
+            Err(AGIStatusParseError {
+                result: "-1".to_owned(),
+                op_data: None,
+                response_to_command: "DATABASE DELTREE"
+            })
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Defines the `DATABASE GET` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/database_get/)
+use super::*;
+
+/// The Database Get command.
+///
+/// Get a value from the asterisk database (astdb).
+/// ```
+/// use blazing_agi::command::DatabaseGet;
+/// let cmd = DatabaseGet::new("cidname".to_owned(), "0123456789".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "DATABASE GET \"cidname\" \"0123456789\"\n")
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`DatabaseGetResponse`].
+#[derive(Debug)]
+pub struct DatabaseGet {
+    family: String,
+    key: String,
+}
+impl DatabaseGet {
+    /// Create [`DatabaseGet`]. When sent, this will get the value of `family/key`.
+    pub fn new(family: String, key: String) -> Self {
+        Self { family, key }
+    }
+}
+impl core::fmt::Display for DatabaseGet {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "DATABASE GET \"{}\" \"{}\"", self.family, self.key)
+    }
+}
+impl AGICommand for DatabaseGet {
+    type Response = DatabaseGetResponse;
+}
+
+/// The responses we can get when sending [`DatabaseGet`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub struct DatabaseGetResponse {
+    /// The value stored under the key, or `None` if there is none.
+    pub value: Option<String>,
+}
+impl InnerAGIResponse for DatabaseGetResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`DatabaseGetResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`DatabaseGet`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for DatabaseGetResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match (result.parse::<i32>(), op_data) {
+            (Ok(1), Some(x)) => Ok(DatabaseGetResponse {
+                value: Some(strip_parens(x).to_owned()),
+            }),
+            (Ok(0), _) => Ok(DatabaseGetResponse { value: None }),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "DATABASE GET",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let cmd = DatabaseGet::new("fam".to_owned(), "key".to_owned());
+        assert_eq!(cmd.to_string(), "DATABASE GET \"fam\" \"key\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            DatabaseGetResponse::try_from(("1", Some("(some value)"))).unwrap(),
+            DatabaseGetResponse {
+                value: Some("some value".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn parse_not_found() {
+        assert_eq!(
+            DatabaseGetResponse::try_from(("0", None)).unwrap(),
+            DatabaseGetResponse { value: None }
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            DatabaseGetResponse::try_from(("2", None)),
+            Err(AGIStatusParseError {
+                result: "2".to_owned(),
+                op_data: None,
+                response_to_command: "DATABASE GET"
+            })
+        );
+    }
+}
@@ -0,0 +1,105 @@
+//! Defines the `DATABASE PUT` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/database_put/)
+use super::*;
+
+/// The Database Put command.
+///
+/// Store a value in the asterisk database (astdb).
+/// ```
+/// use blazing_agi::command::DatabasePut;
+/// let cmd = DatabasePut::new("cidname".to_owned(), "0123456789".to_owned(), "Alice".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "DATABASE PUT \"cidname\" \"0123456789\" \"Alice\"\n")
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`DatabasePutResponse`].
+#[derive(Debug)]
+pub struct DatabasePut {
+    family: String,
+    key: String,
+    value: String,
+}
+impl DatabasePut {
+    /// Create [`DatabasePut`]. When sent, this will set `family/key` to `value`.
+    pub fn new(family: String, key: String, value: String) -> Self {
+        Self { family, key, value }
+    }
+}
+impl core::fmt::Display for DatabasePut {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(
+            f,
+            "DATABASE PUT \"{}\" \"{}\" \"{}\"",
+            self.family, self.key, self.value
+        )
+    }
+}
+impl AGICommand for DatabasePut {
+    type Response = DatabasePutResponse;
+}
+
+/// The responses we can get when sending [`DatabasePut`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub enum DatabasePutResponse {
+    /// The value was stored.
+    Success,
+    /// The value could not be stored.
+    Failure,
+}
+impl InnerAGIResponse for DatabasePutResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`DatabasePutResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`DatabasePut`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for DatabasePutResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match result {
+            "1" => Ok(DatabasePutResponse::Success),
+            "0" => Ok(DatabasePutResponse::Failure),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "DATABASE PUT",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let cmd = DatabasePut::new("fam".to_owned(), "key".to_owned(), "val".to_owned());
+        assert_eq!(cmd.to_string(), "DATABASE PUT \"fam\" \"key\" \"val\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            DatabasePutResponse::try_from(("1", None)).unwrap(),
+            DatabasePutResponse::Success
+        );
+    }
+
+    #[test]
+    fn parse_failure() {
+        assert_eq!(
+            DatabasePutResponse::try_from(("0", None)).unwrap(),
+            DatabasePutResponse::Failure
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            DatabasePutResponse::try_from(("-1", None)),
+            Err(AGIStatusParseError {
+                result: "-1".to_owned(),
+                op_data: None,
+                response_to_command: "DATABASE PUT"
+            })
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Defines the `GET DATA` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/get_data/)
+use std::time::Duration;
+
+use super::*;
+
+/// The Get Data command.
+///
+/// Play a file and collect DTMF digits from the caller, until `#` is pressed, the maximum
+/// number of digits is reached or the timeout expires.
+/// ```
+/// use std::time::Duration;
+/// use blazing_agi::command::GetData;
+/// let cmd = GetData::new("enter-pin".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "GET DATA \"enter-pin\"\n");
+/// let cmd = GetData::new("enter-pin".to_owned())
+///     .with_timeout(Duration::from_secs(5))
+///     .with_max_digits(4);
+/// assert_eq!(cmd.to_string(), "GET DATA \"enter-pin\" 5000 4\n");
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`GetDataResponse`].
+#[derive(Debug)]
+pub struct GetData {
+    file: String,
+    timeout: Option<Duration>,
+    max_digits: Option<u16>,
+}
+impl GetData {
+    /// Create [`GetData`]. When sent, this will play `file` and collect digits.
+    pub fn new(file: String) -> Self {
+        Self {
+            file,
+            timeout: None,
+            max_digits: None,
+        }
+    }
+
+    /// Stop waiting for further digits after `timeout`.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Stop collecting digits once `max_digits` were pressed.
+    pub fn with_max_digits(self, max_digits: u16) -> Self {
+        Self {
+            max_digits: Some(max_digits),
+            ..self
+        }
+    }
+}
+impl core::fmt::Display for GetData {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "GET DATA \"{}\"", self.file)?;
+        // asterisk uses its default timeout when 0 is given
+        let timeout_ms = self.timeout.map_or(0, |x| x.as_millis());
+        match (self.timeout, self.max_digits) {
+            (_, Some(max_digits)) => writeln!(f, " {timeout_ms} {max_digits}"),
+            (Some(_), None) => writeln!(f, " {timeout_ms}"),
+            (None, None) => writeln!(f),
+        }
+    }
+}
+impl AGICommand for GetData {
+    type Response = GetDataResponse;
+}
+
+/// The responses we can get when sending [`GetData`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub enum GetDataResponse {
+    /// The caller entered `digits` (without the terminating `#`).
+    /// `timeout` is true if the input ended because the timeout expired.
+    Digits { digits: String, timeout: bool },
+    /// Collecting digits failed, e.g. because the caller hung up.
+    Failure,
+}
+impl InnerAGIResponse for GetDataResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`GetDataResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`GetData`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for GetDataResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        if result == "-1" {
+            return Ok(GetDataResponse::Failure);
+        };
+        if !result.chars().all(|c| Characters::try_from(c).is_ok()) {
+            return Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "GET DATA",
+            });
+        };
+        Ok(GetDataResponse::Digits {
+            digits: result.to_owned(),
+            timeout: op_data == Some("(timeout)"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_only_timeout() {
+        let cmd = GetData::new("beep".to_owned()).with_timeout(Duration::from_millis(2500));
+        assert_eq!(cmd.to_string(), "GET DATA \"beep\" 2500\n");
+    }
+
+    #[test]
+    fn run_only_max_digits() {
+        let cmd = GetData::new("beep".to_owned()).with_max_digits(3);
+        assert_eq!(cmd.to_string(), "GET DATA \"beep\" 0 3\n");
+    }
+
+    #[test]
+    fn parse_digits() {
+        assert_eq!(
+            GetDataResponse::try_from(("1234", None)).unwrap(),
+            GetDataResponse::Digits {
+                digits: "1234".to_owned(),
+                timeout: false
+            }
+        );
+    }
+
+    #[test]
+    fn parse_timeout() {
+        assert_eq!(
+            GetDataResponse::try_from(("12", Some("(timeout)"))).unwrap(),
+            GetDataResponse::Digits {
+                digits: "12".to_owned(),
+                timeout: true
+            }
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            GetDataResponse::try_from(("12a", None)),
+            Err(AGIStatusParseError {
+                result: "12a".to_owned(),
+                op_data: None,
+                response_to_command: "GET DATA"
+            })
+        );
+    }
+}
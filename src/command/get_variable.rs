@@ -0,0 +1,104 @@
+//! Defines the `GET VARIABLE` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/get_variable/)
+//!
+//! Note that [`GetFullVariable`](super::GetFullVariable) is strictly more powerful, because it
+//! can evaluate arbitrary expressions.
+use super::*;
+
+/// The Get Variable command.
+///
+/// Get the value of a channel variable.
+/// ```
+/// use blazing_agi::command::GetVariable;
+/// let cmd = GetVariable::new("TheVariable".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "GET VARIABLE \"TheVariable\"\n")
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`GetVariableResponse`].
+#[derive(Debug)]
+pub struct GetVariable {
+    var_name: String,
+}
+impl GetVariable {
+    /// Create [`GetVariable`]. When sent, this will get the value of `var_name`.
+    pub fn new(var_name: String) -> Self {
+        Self { var_name }
+    }
+}
+impl core::fmt::Display for GetVariable {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "GET VARIABLE \"{}\"", self.var_name)
+    }
+}
+impl AGICommand for GetVariable {
+    type Response = GetVariableResponse;
+}
+
+/// The responses we can get when sending [`GetVariable`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub struct GetVariableResponse {
+    /// The value of the variable, or `None` if it is not set.
+    pub value: Option<String>,
+}
+impl InnerAGIResponse for GetVariableResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`GetVariableResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`GetVariable`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for GetVariableResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match (result.parse::<i32>(), op_data) {
+            (Ok(1), Some(x)) => Ok(GetVariableResponse {
+                value: Some(strip_parens(x).to_owned()),
+            }),
+            (Ok(0), _) => Ok(GetVariableResponse { value: None }),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "GET VARIABLE",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let cmd = GetVariable::new("TEST_VAR_NAME".to_owned());
+        assert_eq!(cmd.to_string(), "GET VARIABLE \"TEST_VAR_NAME\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            GetVariableResponse::try_from(("1", Some("(the value)"))).unwrap(),
+            GetVariableResponse {
+                value: Some("the value".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn parse_not_set() {
+        assert_eq!(
+            GetVariableResponse::try_from(("0", None)).unwrap(),
+            GetVariableResponse { value: None }
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            GetVariableResponse::try_from(("1", None)),
+            Err(AGIStatusParseError {
+                result: "1".to_owned(),
+                op_data: None,
+                response_to_command: "GET VARIABLE"
+            })
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Defines the `HANGUP` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/hangup/)
+use super::*;
+
+/// The Hangup command.
+///
+/// Hang up the current channel, or another channel if one is given.
+/// ```
+/// use blazing_agi::command::Hangup;
+/// let cmd = Hangup::new();
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "HANGUP\n");
+/// let cmd = Hangup::new().with_channel("PJSIP/other-00000002".to_owned());
+/// assert_eq!(cmd.to_string(), "HANGUP \"PJSIP/other-00000002\"\n");
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`HangupResponse`].
+#[derive(Debug, Default)]
+pub struct Hangup {
+    channel: Option<String>,
+}
+impl Hangup {
+    /// Create the Hangup command for the current channel.
+    pub fn new() -> Self {
+        Self { channel: None }
+    }
+
+    /// Hang up `channel` instead of the current channel.
+    pub fn with_channel(self, channel: String) -> Self {
+        Self {
+            channel: Some(channel),
+        }
+    }
+}
+impl core::fmt::Display for Hangup {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match &self.channel {
+            Some(x) => {
+                writeln!(f, "HANGUP \"{x}\"")
+            }
+            None => {
+                writeln!(f, "HANGUP")
+            }
+        }
+    }
+}
+impl AGICommand for Hangup {
+    type Response = HangupResponse;
+}
+
+/// The responses we can get when sending [`Hangup`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub enum HangupResponse {
+    /// The channel was hung up.
+    Success,
+    /// The channel does not exist.
+    Failure,
+}
+impl InnerAGIResponse for HangupResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`HangupResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`Hangup`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for HangupResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match result.parse::<i32>() {
+            Ok(1) => Ok(HangupResponse::Success),
+            Ok(-1) => Ok(HangupResponse::Failure),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "HANGUP",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        assert_eq!(Hangup::new().to_string(), "HANGUP\n");
+    }
+
+    #[test]
+    fn run_with_channel() {
+        let cmd = Hangup::new().with_channel("SIP/foo".to_owned());
+        assert_eq!(cmd.to_string(), "HANGUP \"SIP/foo\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            HangupResponse::try_from(("1", None)).unwrap(),
+            HangupResponse::Success
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            HangupResponse::try_from(("0", None)),
+            Err(AGIStatusParseError {
+                result: "0".to_owned(),
+                op_data: None,
+                response_to_command: "HANGUP"
+            })
+        );
+    }
+}
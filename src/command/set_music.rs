@@ -0,0 +1,115 @@
+//! Defines the `SET MUSIC` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/set_music/)
+use super::*;
+
+/// The Set Music command.
+///
+/// Turn music on hold on or off for the caller.
+/// ```
+/// use blazing_agi::command::SetMusic;
+/// let cmd = SetMusic::on();
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "SET MUSIC on\n");
+/// let cmd = SetMusic::on().with_class("jazz".to_owned());
+/// assert_eq!(cmd.to_string(), "SET MUSIC on \"jazz\"\n");
+/// let cmd = SetMusic::off();
+/// assert_eq!(cmd.to_string(), "SET MUSIC off\n");
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`SetMusicResponse`].
+#[derive(Debug)]
+pub struct SetMusic {
+    enabled: bool,
+    class: Option<String>,
+}
+impl SetMusic {
+    /// Create [`SetMusic`]. When sent, this will start music on hold of the default class.
+    pub fn on() -> Self {
+        Self {
+            enabled: true,
+            class: None,
+        }
+    }
+
+    /// Create [`SetMusic`]. When sent, this will stop music on hold.
+    pub fn off() -> Self {
+        Self {
+            enabled: false,
+            class: None,
+        }
+    }
+
+    /// Play music on hold of the class `class` instead of the default class.
+    pub fn with_class(self, class: String) -> Self {
+        Self {
+            class: Some(class),
+            ..self
+        }
+    }
+}
+impl core::fmt::Display for SetMusic {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let state = if self.enabled { "on" } else { "off" };
+        match &self.class {
+            Some(class) => writeln!(f, "SET MUSIC {state} \"{class}\""),
+            None => writeln!(f, "SET MUSIC {state}"),
+        }
+    }
+}
+impl AGICommand for SetMusic {
+    type Response = SetMusicResponse;
+}
+
+/// The responses we can get when sending [`SetMusic`] that returned 200.
+/// There is only one acceptable response: `200 result=0`, so this is the empty struct.
+#[derive(Debug, PartialEq)]
+pub struct SetMusicResponse {}
+impl InnerAGIResponse for SetMusicResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`SetMusicResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`SetMusic`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for SetMusicResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&str, Option<&str>)) -> Result<Self, Self::Error> {
+        let res_parsed = result.parse::<u16>();
+        match res_parsed {
+            Ok(0) => Ok(SetMusicResponse {}),
+            _ => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "SET MUSIC",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_with_class() {
+        let cmd = SetMusic::on().with_class("default".to_owned());
+        assert_eq!(cmd.to_string(), "SET MUSIC on \"default\"\n");
+    }
+
+    #[test]
+    fn parse_success() {
+        assert_eq!(
+            SetMusicResponse::try_from(("0", None)).unwrap(),
+            SetMusicResponse {}
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            SetMusicResponse::try_from(("1", None)),
+            Err(AGIStatusParseError {
+                result: "1".to_owned(),
+                op_data: None,
+                response_to_command: "SET MUSIC"
+            })
+        );
+    }
+}
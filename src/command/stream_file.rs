@@ -0,0 +1,164 @@
+//! Defines the `STREAM FILE` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/stream_file/)
+use super::*;
+
+/// The Stream File command.
+///
+/// Play a file to the caller. Playback can be interrupted by pressing one of the escape digits.
+/// ```
+/// use blazing_agi::command::{Characters, StreamFile};
+/// let cmd = StreamFile::new("welcome".to_owned());
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "STREAM FILE \"welcome\" \"\"\n");
+/// let cmd = StreamFile::new("menu".to_owned())
+///     .with_escape_digits(vec![Characters::One, Characters::Two])
+///     .with_offset(8000);
+/// assert_eq!(cmd.to_string(), "STREAM FILE \"menu\" \"12\" 8000\n");
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`StreamFileResponse`].
+#[derive(Debug)]
+pub struct StreamFile {
+    file: String,
+    escape_digits: Vec<Characters>,
+    offset: Option<u64>,
+}
+impl StreamFile {
+    /// Create [`StreamFile`]. When sent, this will play `file` (without extension).
+    pub fn new(file: String) -> Self {
+        Self {
+            file,
+            escape_digits: vec![],
+            offset: None,
+        }
+    }
+
+    /// Allow the caller to interrupt playback by pressing any of `escape_digits`.
+    pub fn with_escape_digits(self, escape_digits: Vec<Characters>) -> Self {
+        Self {
+            escape_digits,
+            ..self
+        }
+    }
+
+    /// Start playback at `offset` samples.
+    pub fn with_offset(self, offset: u64) -> Self {
+        Self {
+            offset: Some(offset),
+            ..self
+        }
+    }
+}
+impl core::fmt::Display for StreamFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let escape_digits = self
+            .escape_digits
+            .iter()
+            .map(char::from)
+            .collect::<String>();
+        match self.offset {
+            Some(offset) => {
+                writeln!(
+                    f,
+                    "STREAM FILE \"{}\" \"{escape_digits}\" {offset}",
+                    self.file
+                )
+            }
+            None => {
+                writeln!(f, "STREAM FILE \"{}\" \"{escape_digits}\"", self.file)
+            }
+        }
+    }
+}
+impl AGICommand for StreamFile {
+    type Response = StreamFileResponse;
+}
+
+/// The responses we can get when sending [`StreamFile`] that returned 200.
+///
+/// `endpos` is the sample offset at which playback stopped, if asterisk sent it.
+#[derive(Debug, PartialEq)]
+pub enum StreamFileResponse {
+    /// The file was played completely.
+    Finished { endpos: Option<u64> },
+    /// The caller pressed `digit` and interrupted playback.
+    Interrupted {
+        digit: Characters,
+        endpos: Option<u64>,
+    },
+    /// Playback failed, e.g. because the file does not exist.
+    Failure { endpos: Option<u64> },
+}
+impl InnerAGIResponse for StreamFileResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`StreamFileResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`StreamFile`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for StreamFileResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        let err = || AGIStatusParseError {
+            result: result.to_owned(),
+            op_data: op_data.map(|x| x.to_owned()),
+            response_to_command: "STREAM FILE",
+        };
+        let endpos = match op_data {
+            None => None,
+            Some(x) => Some(
+                x.strip_prefix("endpos=")
+                    .and_then(|pos| pos.parse::<u64>().ok())
+                    .ok_or_else(err)?,
+            ),
+        };
+        match result.parse::<i32>() {
+            Ok(0) => Ok(StreamFileResponse::Finished { endpos }),
+            Ok(-1) => Ok(StreamFileResponse::Failure { endpos }),
+            Ok(x) => Characters::from_ascii_code(x)
+                .map(|digit| StreamFileResponse::Interrupted { digit, endpos })
+                .ok_or_else(err),
+            Err(_) => Err(err()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run_with_escape_digits() {
+        let cmd = StreamFile::new("menu".to_owned())
+            .with_escape_digits(vec![Characters::Star, Characters::Pound]);
+        assert_eq!(cmd.to_string(), "STREAM FILE \"menu\" \"*#\"\n");
+    }
+
+    #[test]
+    fn parse_finished() {
+        assert_eq!(
+            StreamFileResponse::try_from(("0", Some("endpos=1000"))).unwrap(),
+            StreamFileResponse::Finished { endpos: Some(1000) }
+        );
+    }
+
+    #[test]
+    fn parse_interrupted() {
+        assert_eq!(
+            StreamFileResponse::try_from(("49", Some("endpos=20"))).unwrap(),
+            StreamFileResponse::Interrupted {
+                digit: Characters::One,
+                endpos: Some(20)
+            }
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_op_data() {
+        assert_eq!(
+            StreamFileResponse::try_from(("0", Some("endpos=foo"))),
+            Err(AGIStatusParseError {
+                result: "0".to_owned(),
+                op_data: Some("endpos=foo".to_owned()),
+                response_to_command: "STREAM FILE"
+            })
+        );
+    }
+}
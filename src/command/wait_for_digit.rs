@@ -0,0 +1,125 @@
+//! Defines the `WAIT FOR DIGIT` AGI command.
+//! See also the [official documentation](https://docs.asterisk.org/Asterisk_22_Documentation/API_Documentation/AGI_Commands/wait_for_digit/)
+use std::time::Duration;
+
+use super::*;
+
+/// The Wait For Digit command.
+///
+/// Wait until the caller presses a single DTMF digit.
+/// ```
+/// use std::time::Duration;
+/// use blazing_agi::command::WaitForDigit;
+/// let cmd = WaitForDigit::new(Duration::from_secs(5));
+/// // Will send:
+/// assert_eq!(cmd.to_string(), "WAIT FOR DIGIT 5000\n");
+/// assert_eq!(WaitForDigit::forever().to_string(), "WAIT FOR DIGIT -1\n");
+/// ```
+///
+/// The associated [`InnerAGIResponse`] from [`send_command`](crate::connection::Connection::send_command) is
+/// [`WaitForDigitResponse`].
+#[derive(Debug)]
+pub struct WaitForDigit {
+    /// Timeout in milliseconds, -1 for no timeout
+    timeout_ms: i64,
+}
+impl WaitForDigit {
+    /// Wait at most `timeout` for a digit.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout_ms: i64::try_from(timeout.as_millis()).unwrap_or(i64::MAX),
+        }
+    }
+
+    /// Wait until a digit is pressed, however long that takes.
+    pub fn forever() -> Self {
+        Self { timeout_ms: -1 }
+    }
+}
+impl core::fmt::Display for WaitForDigit {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        writeln!(f, "WAIT FOR DIGIT {}", self.timeout_ms)
+    }
+}
+impl AGICommand for WaitForDigit {
+    type Response = WaitForDigitResponse;
+}
+
+/// The responses we can get when sending [`WaitForDigit`] that returned 200.
+#[derive(Debug, PartialEq)]
+pub enum WaitForDigitResponse {
+    /// The caller pressed this digit.
+    Digit(Characters),
+    /// No digit was pressed before the timeout.
+    Timeout,
+    /// Waiting failed, e.g. because the caller hung up.
+    Failure,
+}
+impl InnerAGIResponse for WaitForDigitResponse {}
+/// Convert from a tuple `(result, operational_data)` to [`WaitForDigitResponse`]. This is used
+/// internally when parsing AGI responses to sending a [`WaitForDigit`] command.
+impl<'a> TryFrom<(&'a str, Option<&'a str>)> for WaitForDigitResponse {
+    type Error = AGIStatusParseError;
+    fn try_from((result, op_data): (&'a str, Option<&'a str>)) -> Result<Self, Self::Error> {
+        match result.parse::<i32>() {
+            Ok(0) => Ok(WaitForDigitResponse::Timeout),
+            Ok(-1) => Ok(WaitForDigitResponse::Failure),
+            Ok(x) => Characters::from_ascii_code(x)
+                .map(WaitForDigitResponse::Digit)
+                .ok_or(AGIStatusParseError {
+                    result: result.to_owned(),
+                    op_data: op_data.map(|x| x.to_owned()),
+                    response_to_command: "WAIT FOR DIGIT",
+                }),
+            Err(_) => Err(AGIStatusParseError {
+                result: result.to_owned(),
+                op_data: op_data.map(|x| x.to_owned()),
+                response_to_command: "WAIT FOR DIGIT",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn run() {
+        let cmd = WaitForDigit::new(Duration::from_millis(1500));
+        assert_eq!(cmd.to_string(), "WAIT FOR DIGIT 1500\n");
+    }
+
+    #[test]
+    fn run_forever() {
+        assert_eq!(WaitForDigit::forever().to_string(), "WAIT FOR DIGIT -1\n");
+    }
+
+    #[test]
+    fn parse_digit() {
+        assert_eq!(
+            WaitForDigitResponse::try_from(("35", None)).unwrap(),
+            WaitForDigitResponse::Digit(Characters::Pound)
+        );
+    }
+
+    #[test]
+    fn parse_timeout() {
+        assert_eq!(
+            WaitForDigitResponse::try_from(("0", None)).unwrap(),
+            WaitForDigitResponse::Timeout
+        );
+    }
+
+    #[test]
+    fn parse_incorrect_result() {
+        assert_eq!(
+            WaitForDigitResponse::try_from(("65", None)),
+            Err(AGIStatusParseError {
+                result: "65".to_owned(),
+                op_data: None,
+                response_to_command: "WAIT FOR DIGIT"
+            })
+        );
+    }
+}
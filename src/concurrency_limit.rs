@@ -0,0 +1,312 @@
+//! Limit how many sessions a handler runs at once.
+//!
+//! A [`ConcurrencyLimitLayer`] runs at most a fixed number of sessions of the layered handler at
+//! the same time. Further sessions wait in a queue for a bounded time, optionally with music on
+//! hold, and run an overflow handler if no slot frees up in time.
+//! ```
+//! use std::time::Duration;
+//!
+//! use blazing_agi::{concurrency_limit::ConcurrencyLimitLayer, router::Router};
+//! # use blazing_agi_macros::create_handler;
+//! # #[create_handler]
+//! # async fn backend(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+//! #     Ok(())
+//! # }
+//! # #[create_handler]
+//! # async fn busy(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+//! #     Ok(())
+//! # }
+//! # #[create_handler]
+//! # async fn other(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+//! #     Ok(())
+//! # }
+//!
+//! let router = Router::new()
+//!     .group(|group| {
+//!         group.route("/backend", backend).route_layer(
+//!             ConcurrencyLimitLayer::new(10)
+//!                 .wait(Duration::from_secs(30))
+//!                 .music_on_hold()
+//!                 .overflow(busy),
+//!         )
+//!     })
+//!     .route("/other", other);
+//! ```
+//!
+//! Clones of a layer share their slots, so all routes a layer is added to share the same limit.
+//! Create a separate layer for each route to limit them separately.
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    command::SetMusic, handler::AGIHandler, layer::Layer, AGIError, AGIRequest, Connection,
+};
+
+/// A [`Layer`] limiting how many sessions of the layered handler run at once.
+///
+/// See the [module documentation](self) for how this works.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimitLayer {
+    slots: Arc<Semaphore>,
+    wait: Duration,
+    music: bool,
+    music_class: Option<String>,
+    overflow: Option<Arc<dyn AGIHandler>>,
+}
+impl ConcurrencyLimitLayer {
+    /// Create a layer running at most `max` sessions at once. Sessions exceeding the limit
+    /// are not queued and fail with [`AGIError::ClientSideError`].
+    ///
+    /// # Panics
+    /// Panics if `max` is zero.
+    pub fn new(max: usize) -> Self {
+        assert!(max > 0, "A concurrency limit has to allow some sessions.");
+        Self {
+            slots: Arc::new(Semaphore::new(max)),
+            wait: Duration::ZERO,
+            music: false,
+            music_class: None,
+            overflow: None,
+        }
+    }
+
+    /// Let sessions exceeding the limit wait for up to `wait` for a free slot.
+    #[must_use]
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Play music on hold (of the default class) to waiting callers.
+    #[must_use]
+    pub fn music_on_hold(mut self) -> Self {
+        self.music = true;
+        self
+    }
+
+    /// Play music on hold of the class `class` to waiting callers.
+    #[must_use]
+    pub fn music_class<S: Into<String>>(mut self, class: S) -> Self {
+        self.music = true;
+        self.music_class = Some(class.into());
+        self
+    }
+
+    /// Run `handler` for sessions that did not get a slot in time, instead of failing with
+    /// [`AGIError::ClientSideError`].
+    #[must_use]
+    pub fn overflow<H: AGIHandler + 'static>(mut self, handler: H) -> Self {
+        self.overflow = Some(Arc::new(handler));
+        self
+    }
+
+    /// Wait for a free slot, with music on hold if configured.
+    async fn acquire(
+        &self,
+        connection: &mut Connection,
+    ) -> Result<Option<OwnedSemaphorePermit>, AGIError> {
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        };
+        if self.wait.is_zero() {
+            return Ok(None);
+        };
+        if self.music {
+            let music = match &self.music_class {
+                Some(class) => SetMusic::on().with_class(class.clone()),
+                None => SetMusic::on(),
+            };
+            connection.send_command(music).await?;
+        };
+        let permit = tokio::time::timeout(self.wait, self.slots.clone().acquire_owned())
+            .await
+            .ok()
+            .and_then(Result::ok);
+        if self.music {
+            connection.send_command(SetMusic::off()).await?;
+        };
+        Ok(permit)
+    }
+}
+impl Layer for ConcurrencyLimitLayer {
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(ConcurrencyLimitHandler {
+            layer: self.clone(),
+            inner: Box::new(handler),
+        })
+    }
+}
+
+/// The handler created by a [`ConcurrencyLimitLayer`].
+#[derive(Debug)]
+struct ConcurrencyLimitHandler {
+    layer: ConcurrencyLimitLayer,
+    inner: Box<dyn AGIHandler>,
+}
+#[async_trait::async_trait]
+impl AGIHandler for ConcurrencyLimitHandler {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        if let Some(_permit) = self.layer.acquire(connection).await? {
+            return self.inner.handle(connection, request).await;
+        };
+        match &self.layer.overflow {
+            Some(overflow) => overflow.handle(connection, request).await,
+            None => Err(AGIError::ClientSideError(
+                "The concurrency limit was exceeded".to_owned(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::sync::Notify;
+    use url::Url;
+
+    use super::*;
+    use crate::{command::Verbose, router::Router, AGIStatusGeneric, AGIVariableDump};
+
+    /// Blocks until released, holding its slot.
+    #[derive(Debug, Default)]
+    struct Blocking {
+        started: Notify,
+        release: Notify,
+    }
+    #[async_trait::async_trait]
+    impl AGIHandler for Arc<Blocking> {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            self.started.notify_one();
+            self.release.notified().await;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct Count(AtomicUsize);
+    #[async_trait::async_trait]
+    impl AGIHandler for Arc<Count> {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Greet;
+    #[async_trait::async_trait]
+    impl AGIHandler for Greet {
+        async fn handle(&self, conn: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            conn.send_command(Verbose::new("hello".to_owned())).await?;
+            Ok(())
+        }
+    }
+
+    fn request() -> AGIRequest {
+        AGIRequest {
+            variables: AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+            captures: std::collections::HashMap::new(),
+            wildcards: None,
+            host: None,
+            query: std::collections::HashMap::new(),
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        }
+    }
+
+    /// Run `handler` on a connection nobody listens on.
+    async fn run(handler: &dyn AGIHandler) -> Result<(), AGIError> {
+        let (_, server_side) = tokio::io::duplex(64);
+        handler
+            .handle(&mut Connection::new(server_side), &request())
+            .await
+    }
+
+    /// Occupy a slot of `layer` until `blocking` is released.
+    async fn occupy(
+        layer: &ConcurrencyLimitLayer,
+        blocking: &Arc<Blocking>,
+    ) -> tokio::task::JoinHandle<Result<(), AGIError>> {
+        let handler = layer.layer(blocking.clone());
+        let task = tokio::spawn(async move { run(handler.as_ref()).await });
+        blocking.started.notified().await;
+        task
+    }
+
+    #[tokio::test]
+    async fn sessions_over_the_limit_overflow() {
+        let blocking = Arc::new(Blocking::default());
+        let layer = ConcurrencyLimitLayer::new(1);
+        let first = occupy(&layer, &blocking).await;
+        assert!(matches!(
+            run(layer.layer(Greet).as_ref()).await,
+            Err(AGIError::ClientSideError(_))
+        ));
+
+        let overflow = Arc::new(Count::default());
+        let layer = layer
+            .wait(Duration::from_millis(10))
+            .overflow(overflow.clone());
+        let count = Arc::new(Count::default());
+        assert!(run(layer.layer(count.clone()).as_ref()).await.is_ok());
+        assert_eq!(count.0.load(Ordering::Relaxed), 0);
+        assert_eq!(overflow.0.load(Ordering::Relaxed), 1);
+
+        blocking.release.notify_one();
+        first.await.unwrap().unwrap();
+        assert!(run(layer.layer(count.clone()).as_ref()).await.is_ok());
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn queued_sessions_hear_music() {
+        let blocking = Arc::new(Blocking::default());
+        let layer = ConcurrencyLimitLayer::new(1)
+            .wait(Duration::from_secs(10))
+            .music_class("queue");
+        let first = occupy(&layer, &blocking).await;
+        let router = Router::new().route("/", Greet).route_layer(layer);
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { router.handle(server_side).await });
+        let session = crate::client::AGIClientSession::start(
+            client_side,
+            &AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+        )
+        .await
+        .unwrap();
+        let mut commands = vec![];
+        session
+            .run(|command| {
+                if command.starts_with("SET MUSIC on") {
+                    blocking.release.notify_one();
+                };
+                let result = if command.starts_with("SET MUSIC") {
+                    "0"
+                } else {
+                    "1"
+                };
+                commands.push(command);
+                async move { AGIStatusGeneric::Ok(result.to_owned(), None) }
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+        first.await.unwrap().unwrap();
+        assert_eq!(
+            commands,
+            vec![
+                "SET MUSIC on \"queue\"",
+                "SET MUSIC off",
+                "VERBOSE \"hello\""
+            ]
+        );
+    }
+}
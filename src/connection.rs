@@ -1,8 +1,8 @@
 //! This module handles the literal network connection and sends/receives packets.
 use std::collections::VecDeque;
+use std::sync::Arc;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 #[cfg(feature = "tracing")]
 use tracing::{trace, Level};
 
@@ -10,6 +10,7 @@ use crate::*;
 
 use self::agiparse::{AGIMessage, AGIParseError, AGIStatusGeneric};
 use crate::command::{AGICommand, AGIResponse};
+use crate::transcript::{Recorder, TranscriptSink};
 
 /// The buffers required while waiting for a full message to have arrived for parsing
 #[derive(Debug)]
@@ -121,6 +122,13 @@ impl AGIMessageBuffer {
     }
 }
 
+/// Anything a [`Connection`] can run over.
+///
+/// This is implemented for every type that is a bidirectional async byte stream, most notably
+/// [`TcpStream`](tokio::net::TcpStream) and the in-memory [`DuplexStream`](tokio::io::DuplexStream).
+pub trait AGIStream: AsyncRead + AsyncWrite + Send + Unpin + core::fmt::Debug {}
+impl<T> AGIStream for T where T: AsyncRead + AsyncWrite + Send + Unpin + core::fmt::Debug {}
+
 /// `Connection` handles a single AGI stream (a connection originating from a client).
 /// [`command`]s are sent with [`connection::Connection::send_command`](self::Connection::send_command)
 #[derive(Debug)]
@@ -130,17 +138,29 @@ pub struct Connection {
     /// Buffer when more then one message is contained in a single TCP read
     queued_messages: VecDeque<AGIMessage>,
     /// The underlying stream
-    stream: TcpStream,
+    stream: Box<dyn AGIStream>,
+    /// Where to record the transcript of this connection to, if anywhere
+    recorder: Option<Recorder>,
 }
 impl Connection {
-    pub(crate) fn new(stream: TcpStream) -> Connection {
+    pub(crate) fn new<S: AGIStream + 'static>(stream: S) -> Connection {
         Connection {
             message_buf: AGIMessageBuffer::new(),
             queued_messages: VecDeque::<AGIMessage>::with_capacity(2),
-            stream,
+            stream: Box::new(stream),
+            recorder: None,
         }
     }
 
+    /// Record every message on this connection to `sink` from now on.
+    ///
+    /// Usually you will want to set this for all connections with
+    /// [`Router::record`](crate::router::Router::record) instead, which will also record the
+    /// initial request.
+    pub fn record_to(&mut self, sink: Arc<dyn TranscriptSink>) {
+        self.recorder = Some(Recorder::new(sink));
+    }
+
     /// Send an AGI Command over this connection.
     ///
     /// Return an Error when sending fails or we do not get a Status message as a response.
@@ -157,9 +177,12 @@ impl Connection {
         H: AGICommand,
     {
         let string_to_send = command.to_string();
+        if let Some(recorder) = &self.recorder {
+            recorder.outbound(&string_to_send);
+        };
         // send the command over the stream
         self.stream
-            .write(string_to_send.as_bytes())
+            .write_all(string_to_send.as_bytes())
             .await
             .map_err(AGIError::CannotSendCommand)?;
         // make sure that we get an AGIStatus as a result
@@ -196,7 +219,7 @@ impl Connection {
         }
     }
 
-    /// Read from [`TcpStream`] a single time and handle the result
+    /// Read from the underlying stream a single time and handle the result
     async fn read_single_call(&mut self) -> Result<Vec<AGIMessage>, AGIParseError> {
         let mut ephemeral_buf = [0_u8; 2048];
         let bytes_read = self
@@ -205,14 +228,34 @@ impl Connection {
             .await
             .map_err(|_| AGIParseError::ReadError)?;
         if bytes_read == 0 {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.flush_inbound();
+            };
             return Err(AGIParseError::NoBytes);
         };
-        let as_utf8 = core::str::from_utf8(&ephemeral_buf).map_err(|_| AGIParseError::NotUtf8)?;
+        let Ok(as_utf8) = core::str::from_utf8(&ephemeral_buf[..bytes_read]) else {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.inbound(&String::from_utf8_lossy(&ephemeral_buf[..bytes_read]));
+                recorder.flush_inbound();
+            };
+            return Err(AGIParseError::NotUtf8);
+        };
         let first_zero_index = as_utf8.find('\0').unwrap_or(as_utf8.len());
         #[cfg(feature = "tracing")]
         trace!("new bytes read from network in a single call: {as_utf8}");
-        self.message_buf
-            .handle_single_call_buffer(&as_utf8[0..first_zero_index])
+        // record before parsing, so that malformed data ends up in the transcript
+        if let Some(recorder) = &mut self.recorder {
+            recorder.inbound(&as_utf8[0..first_zero_index]);
+        };
+        let parsed = self
+            .message_buf
+            .handle_single_call_buffer(&as_utf8[0..first_zero_index]);
+        if parsed.is_err() {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.flush_inbound();
+            };
+        };
+        parsed
     }
 
     /// Read the next message and parse it as an [`AGIMessage`]
@@ -252,7 +295,9 @@ fn line_type(line: &str) -> LineType {
         LineType::Empty
     } else if line == "agi_network: yes\n" {
         LineType::NetworkStart
-    } else if line.len() >= 3 && line[3..].starts_with(" result=") {
+    } else if (line.len() >= 3 && line[3..].starts_with(" result="))
+        || (line.ends_with('\n') && agiparse::is_error_status(line))
+    {
         LineType::Status
     } else {
         LineType::Unknown
@@ -527,6 +572,27 @@ mod test {
         assert_eq!(message_buf.this_message, "".to_owned());
     }
 
+    #[test]
+    fn status_without_result() {
+        let mut message_buf = AGIMessageBuffer::new();
+        assert_eq!(
+            message_buf.handle_single_call_buffer("511 Command Not Permitted"),
+            Ok(vec![])
+        );
+        assert_eq!(
+            message_buf.handle_single_call_buffer(" on a dead channel or intercept routine\n"),
+            Ok(vec![AGIMessage::Status(AGIStatusGeneric::DeadChannel)])
+        );
+        assert_eq!(message_buf.this_message, "".to_owned());
+    }
+
+    #[test]
+    fn error_status_line_type() {
+        assert_eq!(line_type("520 Invalid command syntax.\n"), LineType::Status);
+        assert_eq!(line_type("520 Invalid command syntax."), LineType::Unknown);
+        assert_eq!(line_type("300 Something else\n"), LineType::Unknown);
+    }
+
     #[test]
     fn parse_answer_response() {
         let response_body = AGIMessage::Status(AGIStatusGeneric::Ok(
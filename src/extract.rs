@@ -0,0 +1,429 @@
+//! Extract typed values from an [`AGIRequest`], so handlers can be plain async functions.
+//!
+//! Any async function taking a `&mut Connection` followed by up to eight extractors (types
+//! implementing [`FromAGIRequest`]) and returning `Result<(), AGIError>` can be passed to
+//! [`Router::route`](crate::router::Router::route):
+//! ```
+//! use blazing_agi::{
+//!     command::Verbose,
+//!     connection::Connection,
+//!     extract::{Args, CallerId, Captures},
+//!     router::Router,
+//!     AGIError,
+//! };
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct Mailbox {
+//!     user: String,
+//!     box_id: u32,
+//! }
+//!
+//! async fn voicemail(
+//!     connection: &mut Connection,
+//!     Captures(mailbox): Captures<Mailbox>,
+//!     CallerId(caller): CallerId,
+//!     Args((greeting,)): Args<(String,)>,
+//! ) -> Result<(), AGIError> {
+//!     connection
+//!         .send_command(Verbose::new(format!(
+//!             "{caller} calls box {} of {} with greeting {greeting}",
+//!             mailbox.box_id, mailbox.user
+//!         )))
+//!         .await?;
+//!     Ok(())
+//! }
+//!
+//! let router = Router::new().route("/voicemail/:user/:box_id<u32>", voicemail);
+//! ```
+//! When an extractor fails, the handler is not run. Instead it returns the [`AGIError`] the
+//! [`ExtractionError`] converts into. Use [`handler`] with
+//! [`on_rejection`](ExtractorHandler::on_rejection) to choose a different error.
+use std::any::Any;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use serde::de::{
+    value::{MapDeserializer, SeqDeserializer},
+    DeserializeOwned,
+};
+
+use crate::{
+    handler::{AGIHandler, IntoAGIHandler},
+    AGIError, AGIRequest, Connection,
+};
+
+mod de;
+use self::de::ValueDeserializer;
+
+/// Contains all the ways in which extracting a value from a request can fail.
+#[derive(Debug)]
+pub enum ExtractionError {
+    /// The captures of the request cannot be deserialized into the expected type.
+    InvalidCaptures(String),
+    /// The custom arguments (`agi_arg_n`) cannot be deserialized into the expected type.
+    InvalidArgs(String),
+    /// The router has no state of the expected type (param 1).
+    MissingState(&'static str),
+    /// A custom extractor failed.
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+impl core::fmt::Display for ExtractionError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::InvalidCaptures(x) => {
+                write!(f, "The captures of the request are invalid: {x}")
+            }
+            Self::InvalidArgs(x) => {
+                write!(f, "The arguments of the request are invalid: {x}")
+            }
+            Self::MissingState(x) => {
+                write!(f, "The router has no state of type {x}")
+            }
+            Self::Other(x) => {
+                write!(f, "Unable to extract a value from the request: {x}")
+            }
+        }
+    }
+}
+impl std::error::Error for ExtractionError {}
+/// Invalid captures or arguments are the clients fault ([`AGIError::ClientSideError`]), all
+/// other errors are [`AGIError::InnerError`]s.
+impl From<ExtractionError> for AGIError {
+    fn from(value: ExtractionError) -> Self {
+        match value {
+            ExtractionError::InvalidCaptures(_) | ExtractionError::InvalidArgs(_) => {
+                AGIError::ClientSideError(value.to_string())
+            }
+            x => AGIError::InnerError(Box::new(x)),
+        }
+    }
+}
+
+/// A value that can be extracted from a request, to be used as an argument of a handler
+/// function.
+pub trait FromAGIRequest: Sized {
+    /// Extract the value from `request`.
+    ///
+    /// # Errors
+    /// Returns an Error if the request does not contain a suitable value.
+    fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError>;
+}
+
+/// All captures of the route, deserialized into `T` (usually a struct with a field per
+/// capture, or a `HashMap`).
+///
+/// Values are parsed into the type of the field, so a capture `:id<u32>` can be deserialized
+/// into a `u32` field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures<T>(pub T);
+impl<T: DeserializeOwned> FromAGIRequest for Captures<T> {
+    fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError> {
+        let captures = request
+            .captures
+            .iter()
+            .map(|(name, value)| (name.as_str(), ValueDeserializer(value)));
+        T::deserialize(MapDeserializer::new(captures))
+            .map(Self)
+            .map_err(|e| ExtractionError::InvalidCaptures(e.to_string()))
+    }
+}
+
+/// The custom arguments (`agi_arg_1`, `agi_arg_2`, ...) in order, deserialized into `T`
+/// (usually a tuple or a `Vec`).
+///
+/// A tuple needs exactly as many arguments as it has fields. Requests with a gap in the
+/// arguments (e.g. `agi_arg_3` without `agi_arg_2`) are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Args<T>(pub T);
+impl<T: DeserializeOwned> FromAGIRequest for Args<T> {
+    fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError> {
+        let mut args = request.variables.custom_args.iter().collect::<Vec<_>>();
+        args.sort_by_key(|(number, _)| **number);
+        if let Some((position, (number, _))) = args
+            .iter()
+            .enumerate()
+            .find(|(position, (number, _))| usize::from(**number) != position + 1)
+        {
+            return Err(ExtractionError::InvalidArgs(format!(
+                "agi_arg_{number} was sent, but agi_arg_{} was not",
+                position + 1
+            )));
+        };
+        let values = args.into_iter().map(|(_, value)| ValueDeserializer(value));
+        T::deserialize(SeqDeserializer::new(values))
+            .map(Self)
+            .map_err(|e| ExtractionError::InvalidArgs(e.to_string()))
+    }
+}
+
+/// The caller id (`agi_callerid`) of the request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallerId(pub String);
+impl FromAGIRequest for CallerId {
+    fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError> {
+        Ok(Self(request.variables.callerid.clone()))
+    }
+}
+
+/// The language of the channel (`agi_language`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Language(pub String);
+impl FromAGIRequest for Language {
+    fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError> {
+        Ok(Self(request.variables.language.clone()))
+    }
+}
+
+/// The state set with [`Router::with_state`](crate::router::Router::with_state).
+#[derive(Debug)]
+pub struct State<S>(pub Arc<S>);
+impl<S> Clone for State<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<S> core::ops::Deref for State<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0
+    }
+}
+impl<S: Any + Send + Sync> FromAGIRequest for State<S> {
+    fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError> {
+        request
+            .state_as::<S>()
+            .map(Self)
+            .ok_or(ExtractionError::MissingState(std::any::type_name::<S>()))
+    }
+}
+
+/// A handler function taking a `&mut Connection` and the extractors `Args`.
+///
+/// This is implemented for all suitable async functions; there is no need to implement it
+/// yourself.
+pub trait ExtractorFn<'a, Args>: Send + Sync + 'static {
+    /// The future returned by the function.
+    type Future: Future<Output = Result<(), AGIError>> + Send + 'a;
+
+    /// Call the function.
+    fn call(&self, connection: &'a mut Connection, args: Args) -> Self::Future;
+}
+
+/// Implement [`FromAGIRequest`] for a tuple of extractors and [`ExtractorFn`] for functions
+/// taking them.
+macro_rules! impl_extractors {
+    ($($ty:ident $arg:ident),*) => {
+        #[allow(unused_variables)]
+        impl<$($ty: FromAGIRequest,)*> FromAGIRequest for ($($ty,)*) {
+            fn from_request(request: &AGIRequest) -> Result<Self, ExtractionError> {
+                Ok(($($ty::from_request(request)?,)*))
+            }
+        }
+
+        impl<'a, F, Fut, $($ty: FromAGIRequest,)*> ExtractorFn<'a, ($($ty,)*)> for F
+        where
+            F: Fn(&'a mut Connection, $($ty),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Result<(), AGIError>> + Send + 'a,
+        {
+            type Future = Fut;
+
+            fn call(&self, connection: &'a mut Connection, ($($arg,)*): ($($ty,)*)) -> Fut {
+                self(connection, $($arg),*)
+            }
+        }
+    };
+}
+impl_extractors!();
+impl_extractors!(T1 t1);
+impl_extractors!(T1 t1, T2 t2);
+impl_extractors!(T1 t1, T2 t2, T3 t3);
+impl_extractors!(T1 t1, T2 t2, T3 t3, T4 t4);
+impl_extractors!(T1 t1, T2 t2, T3 t3, T4 t4, T5 t5);
+impl_extractors!(T1 t1, T2 t2, T3 t3, T4 t4, T5 t5, T6 t6);
+impl_extractors!(T1 t1, T2 t2, T3 t3, T4 t4, T5 t5, T6 t6, T7 t7);
+impl_extractors!(T1 t1, T2 t2, T3 t3, T4 t4, T5 t5, T6 t6, T7 t7, T8 t8);
+
+/// Turns a value into an error returned when an extractor fails.
+type Rejection = Box<dyn Fn(ExtractionError) -> AGIError + Send + Sync>;
+
+/// An [`AGIHandler`] running a handler function after extracting its arguments.
+///
+/// Create this with [`handler`].
+pub struct ExtractorHandler<F, Args> {
+    function: F,
+    on_rejection: Rejection,
+    _args: PhantomData<fn() -> Args>,
+}
+impl<F, Args> ExtractorHandler<F, Args> {
+    /// Return the error `f` creates when an extractor fails, instead of the default conversion
+    /// from [`ExtractionError`].
+    #[must_use]
+    pub fn on_rejection<R>(mut self, f: R) -> Self
+    where
+        R: Fn(ExtractionError) -> AGIError + Send + Sync + 'static,
+    {
+        self.on_rejection = Box::new(f);
+        self
+    }
+}
+impl<F, Args> core::fmt::Debug for ExtractorHandler<F, Args> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "ExtractorHandler({})", std::any::type_name::<F>())
+    }
+}
+#[async_trait::async_trait]
+impl<F, Args> AGIHandler for ExtractorHandler<F, Args>
+where
+    F: for<'a> ExtractorFn<'a, Args>,
+    Args: FromAGIRequest + 'static,
+{
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        let args = Args::from_request(request).map_err(&self.on_rejection)?;
+        self.function.call(connection, args).await
+    }
+}
+
+/// Turn the handler function `function` into an [`AGIHandler`].
+///
+/// [`Router::route`](crate::router::Router::route) does this for you; use this function when
+/// you want to configure the handler, e.g. with
+/// [`on_rejection`](ExtractorHandler::on_rejection).
+pub fn handler<F, Args>(function: F) -> ExtractorHandler<F, Args>
+where
+    F: for<'a> ExtractorFn<'a, Args>,
+    Args: FromAGIRequest + 'static,
+{
+    ExtractorHandler {
+        function,
+        on_rejection: Box::new(AGIError::from),
+        _args: PhantomData,
+    }
+}
+
+/// Marks the [`IntoAGIHandler`] implementation for handler functions.
+#[doc(hidden)]
+pub struct FunctionMarker<Args>(PhantomData<fn() -> Args>);
+impl<F, Args> IntoAGIHandler<FunctionMarker<Args>> for F
+where
+    F: for<'a> ExtractorFn<'a, Args>,
+    Args: FromAGIRequest + 'static,
+{
+    fn into_handler(self) -> Box<dyn AGIHandler> {
+        Box::new(handler(self))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::AGIVariableDump;
+
+    fn request(url: &str) -> AGIRequest {
+        let variables = AGIVariableDump::builder(url.parse().unwrap())
+            .callerid("+4930123456")
+            .build();
+        AGIRequest {
+            host: variables.request.host(),
+            query: variables.request.query(),
+            variables,
+            captures: HashMap::new(),
+            wildcards: None,
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        }
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Mailbox {
+        user: String,
+        box_id: u32,
+        greeting: Option<String>,
+    }
+
+    #[test]
+    fn captures_are_parsed() {
+        let mut request = request("agi://host/");
+        request.captures.insert("user".to_owned(), "bob".to_owned());
+        request.captures.insert("box_id".to_owned(), "7".to_owned());
+        assert_eq!(
+            Captures::<Mailbox>::from_request(&request).unwrap().0,
+            Mailbox {
+                user: "bob".to_owned(),
+                box_id: 7,
+                greeting: None,
+            }
+        );
+        request.captures.insert("box_id".to_owned(), "x".to_owned());
+        let error = Captures::<Mailbox>::from_request(&request).unwrap_err();
+        assert!(matches!(error, ExtractionError::InvalidCaptures(_)));
+        assert!(matches!(
+            AGIError::from(error),
+            AGIError::ClientSideError(_)
+        ));
+    }
+
+    #[test]
+    fn args_in_order() {
+        let mut request = request("agi://host/");
+        request.variables.custom_args.insert(2, "true".to_owned());
+        request.variables.custom_args.insert(1, "12".to_owned());
+        assert_eq!(
+            Args::<(u8, bool)>::from_request(&request).unwrap().0,
+            (12, true)
+        );
+        assert_eq!(
+            Args::<Vec<String>>::from_request(&request).unwrap().0,
+            vec!["12", "true"]
+        );
+        assert!(matches!(
+            Args::<(u8,)>::from_request(&request),
+            Err(ExtractionError::InvalidArgs(_))
+        ));
+    }
+
+    #[test]
+    fn args_with_gaps_are_rejected() {
+        let mut request = request("agi://host/");
+        request.variables.custom_args.insert(1, "12".to_owned());
+        request.variables.custom_args.insert(3, "true".to_owned());
+        let error = Args::<(u8, bool)>::from_request(&request).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The arguments of the request are invalid: agi_arg_3 was sent, but agi_arg_2 was not"
+        );
+        assert!(matches!(
+            Args::<Vec<String>>::from_request(&request),
+            Err(ExtractionError::InvalidArgs(_))
+        ));
+    }
+
+    #[test]
+    fn variables_and_state() {
+        let mut request = request("agi://host/");
+        request.variables.language = "de".to_owned();
+        let (CallerId(caller), Language(language)) =
+            <(CallerId, Language)>::from_request(&request).unwrap();
+        assert_eq!(caller, "+4930123456");
+        assert_eq!(language, "de");
+        assert!(matches!(
+            State::<u32>::from_request(&request),
+            Err(ExtractionError::MissingState("u32"))
+        ));
+        request.state = Some(Arc::new(42_u32));
+        assert_eq!(*State::<u32>::from_request(&request).unwrap(), 42);
+        assert!(State::<String>::from_request(&request).is_err());
+    }
+}
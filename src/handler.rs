@@ -5,6 +5,8 @@ extern crate tracing;
 #[cfg(feature = "tracing")]
 use tracing::Level;
 
+use std::{future::Future, pin::Pin, sync::Arc};
+
 use crate::{command::verbose::Verbose, AGIError, AGIRequest, Connection};
 
 /// The main trait that handles an AGI request.
@@ -23,6 +25,21 @@ pub trait AGIHandler: Send + Sync + core::fmt::Debug {
     ) -> Result<(), AGIError>;
 }
 
+/// Anything that can be turned into an [`AGIHandler`]: Handlers themselves, async functions
+/// taking the connection and the request (see [`RequestFn`]) and handler functions taking
+/// extractors (see [`extract`](crate::extract)).
+///
+/// `M` only distinguishes the different implementations; you never need to name it.
+pub trait IntoAGIHandler<M> {
+    /// Turn `self` into a handler.
+    fn into_handler(self) -> Box<dyn AGIHandler>;
+}
+impl<H: AGIHandler + 'static> IntoAGIHandler<()> for H {
+    fn into_handler(self) -> Box<dyn AGIHandler> {
+        Box::new(self)
+    }
+}
+
 #[async_trait::async_trait]
 impl AGIHandler for Box<dyn AGIHandler> {
     async fn handle(&self, conn: &mut Connection, req: &AGIRequest) -> Result<(), AGIError> {
@@ -37,6 +54,13 @@ impl AGIHandler for &Box<dyn AGIHandler> {
     }
 }
 
+#[async_trait::async_trait]
+impl AGIHandler for Arc<dyn AGIHandler> {
+    async fn handle(&self, conn: &mut Connection, req: &AGIRequest) -> Result<(), AGIError> {
+        (**self).handle(conn, req).await
+    }
+}
+
 #[async_trait::async_trait]
 impl AGIHandler for &dyn AGIHandler {
     async fn handle(&self, conn: &mut Connection, req: &AGIRequest) -> Result<(), AGIError> {
@@ -70,6 +94,134 @@ impl AGIHandler for AndThenHandler {
     }
 }
 
+/// The future returned by closures passed to [`handler_fn`].
+pub type BoxHandlerFuture<'a> = Pin<Box<dyn Future<Output = Result<(), AGIError>> + Send + 'a>>;
+
+/// A handler function taking a `&mut Connection` and the `&AGIRequest`.
+///
+/// This is implemented for all suitable async functions and async closures; there is no need to
+/// implement it yourself. Such functions can be passed to
+/// [`Router::route`](crate::router::Router::route) directly:
+/// ```
+/// use blazing_agi::{
+///     command::Verbose, connection::Connection, router::Router, AGIError, AGIRequest,
+/// };
+///
+/// async fn greet(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+///     connection
+///         .send_command(Verbose::new(format!("Hello {}", request.variables.callerid)))
+///         .await?;
+///     Ok(())
+/// }
+///
+/// let router = Router::new()
+///     .route("/greet", greet)
+///     .route(
+///         "/bye",
+///         async |connection: &mut Connection, _request: &AGIRequest| {
+///             connection.send_command(Verbose::new("Bye".to_owned())).await?;
+///             Ok(())
+///         },
+///     );
+/// ```
+/// Closures need the types of their arguments, and have to be async closures (`async |..| {}`,
+/// Rust 1.85 or later): a closure returning an `async` block cannot return a future borrowing its
+/// arguments. On older compilers, use [`handler_fn`] instead.
+pub trait RequestFn<'a>: Send + Sync + 'static {
+    /// The future returned by the function.
+    type Future: Future<Output = Result<(), AGIError>> + Send + 'a;
+
+    /// Call the function.
+    fn call(&self, connection: &'a mut Connection, request: &'a AGIRequest) -> Self::Future;
+}
+impl<'a, F, Fut> RequestFn<'a> for F
+where
+    F: Fn(&'a mut Connection, &'a AGIRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), AGIError>> + Send + 'a,
+{
+    type Future = Fut;
+
+    fn call(&self, connection: &'a mut Connection, request: &'a AGIRequest) -> Fut {
+        self(connection, request)
+    }
+}
+
+/// Marks the [`IntoAGIHandler`] implementation for [`RequestFn`]s.
+#[doc(hidden)]
+pub struct RequestFnMarker;
+impl<F> IntoAGIHandler<RequestFnMarker> for F
+where
+    F: for<'a> RequestFn<'a>,
+{
+    fn into_handler(self) -> Box<dyn AGIHandler> {
+        Box::new(FnHandler { function: self })
+    }
+}
+
+/// An [`AGIHandler`] calling a function with the connection and request.
+///
+/// Create this with [`handler_fn`], or by passing a [`RequestFn`] to the router.
+pub struct FnHandler<F> {
+    function: F,
+}
+impl<F> core::fmt::Debug for FnHandler<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "FnHandler({})", std::any::type_name::<F>())
+    }
+}
+#[async_trait::async_trait]
+impl<F> AGIHandler for FnHandler<F>
+where
+    F: for<'a> RequestFn<'a>,
+{
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        self.function.call(connection, request).await
+    }
+}
+
+/// Turn a closure into an [`AGIHandler`].
+///
+/// The closure has to return a boxed future, because the future borrows the connection and
+/// request. Closures may capture their environment, e.g. a cloned `Arc`:
+/// ```
+/// use std::sync::{
+///     atomic::{AtomicUsize, Ordering},
+///     Arc,
+/// };
+///
+/// use blazing_agi::{command::Verbose, handler::handler_fn, router::Router};
+///
+/// let calls = Arc::new(AtomicUsize::new(0));
+/// let counter = calls.clone();
+/// let router = Router::new().route(
+///     "/count",
+///     handler_fn(move |connection, _request| {
+///         let calls = counter.fetch_add(1, Ordering::Relaxed) + 1;
+///         Box::pin(async move {
+///             connection
+///                 .send_command(Verbose::new(format!("call number {calls}")))
+///                 .await?;
+///             Ok(())
+///         })
+///     }),
+/// );
+/// ```
+/// Unlike an async closure passed as a [`RequestFn`], this works on any supported compiler and
+/// does not need the types of the arguments.
+pub fn handler_fn<F>(function: F) -> FnHandler<F>
+where
+    F: for<'a> Fn(&'a mut Connection, &'a AGIRequest) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    FnHandler { function }
+}
+
 /// A trivial AGI response, simply acknowledging that a route does not exist.
 #[derive(Debug)]
 pub(crate) struct FallbackHandler {}
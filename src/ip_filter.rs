@@ -0,0 +1,304 @@
+//! Restrict which network addresses may open sessions.
+//!
+//! An [`IpFilter`] is a list of allowed and denied networks in CIDR notation. Use it with
+//! [`Router::ip_filter`](crate::router::Router::ip_filter) to drop connections from other
+//! addresses before anything is read from them, or with [`IpFilterLayer`] to reject requests on
+//! some routes only.
+//! ```
+//! use blazing_agi::{ip_filter::IpFilter, router::Router};
+//!
+//! let router = Router::new().ip_filter(
+//!     IpFilter::new()
+//!         .allow("10.0.10.0/24")
+//!         .allow("2001:db8::/32")
+//!         .deny("10.0.10.99"),
+//! );
+//! ```
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{handler::AGIHandler, layer::Layer, AGIError, AGIRequest, Connection};
+
+/// Errors when parsing a [`Cidr`].
+#[derive(Debug, PartialEq)]
+pub enum CidrParseError {
+    /// The address is not a valid IPv4 or IPv6 address.
+    InvalidAddress(String),
+    /// The prefix length is not a number or too long for the address.
+    InvalidPrefix(String),
+}
+impl core::fmt::Display for CidrParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::InvalidAddress(x) => write!(f, "{x} is not a valid IP address"),
+            Self::InvalidPrefix(x) => write!(f, "{x} is not a valid prefix length"),
+        }
+    }
+}
+impl std::error::Error for CidrParseError {}
+
+/// A network in CIDR notation, like `10.0.0.0/8` or `2001:db8::/32`.
+///
+/// An address without a prefix length (like `10.0.0.1`) is the network of only this address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8,
+}
+impl Cidr {
+    /// Whether `address` is in this network.
+    ///
+    /// IPv4 addresses mapped into IPv6 (like `::ffff:10.0.0.1`) are treated as IPv4 addresses.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(&net.octets(), &addr.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+impl core::str::FromStr for Cidr {
+    type Err = CidrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address = address
+            .parse::<IpAddr>()
+            .map_err(|_| CidrParseError::InvalidAddress(address.to_owned()))?
+            .to_canonical();
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(x) => match x.parse::<u8>() {
+                Ok(prefix) if prefix <= max_prefix => prefix,
+                _ => return Err(CidrParseError::InvalidPrefix(x.to_owned())),
+            },
+            None => max_prefix,
+        };
+        Ok(Self { address, prefix })
+    }
+}
+impl core::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix)
+    }
+}
+
+/// Whether the first `prefix` bits of `a` and `b` are the same.
+fn prefix_matches(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let full_bytes = usize::from(prefix / 8);
+    let rest_bits = prefix % 8;
+    if a[..full_bytes] != b[..full_bytes] {
+        return false;
+    };
+    if rest_bits == 0 {
+        return true;
+    };
+    let mask = 0xff_u8 << (8 - rest_bits);
+    a[full_bytes] & mask == b[full_bytes] & mask
+}
+
+/// Lists of allowed and denied networks.
+///
+/// An address is allowed if it is in no denied network and, if any networks are allowed at all,
+/// in one of the allowed networks. Requests without a known address (e.g. from an in-memory
+/// stream) are only allowed if no networks are explicitly allowed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IpFilter {
+    allowed: Vec<Cidr>,
+    denied: Vec<Cidr>,
+}
+impl IpFilter {
+    /// Create a filter allowing every address.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the network `cidr`. Once a network is allowed, all others are denied.
+    ///
+    /// # Panics
+    /// Panics if `cidr` is not a valid network (see [`Cidr`]).
+    #[must_use]
+    pub fn allow(mut self, cidr: &str) -> Self {
+        self.allowed.push(parse_cidr(cidr));
+        self
+    }
+
+    /// Deny the network `cidr`, even if it is part of an allowed network.
+    ///
+    /// # Panics
+    /// Panics if `cidr` is not a valid network (see [`Cidr`]).
+    #[must_use]
+    pub fn deny(mut self, cidr: &str) -> Self {
+        self.denied.push(parse_cidr(cidr));
+        self
+    }
+
+    /// Whether `address` may open sessions.
+    pub fn allows(&self, address: Option<IpAddr>) -> bool {
+        match address {
+            Some(address) => {
+                !self.denied.iter().any(|x| x.contains(address))
+                    && (self.allowed.is_empty() || self.allowed.iter().any(|x| x.contains(address)))
+            }
+            None => self.allowed.is_empty(),
+        }
+    }
+
+    /// Whether the peer `address` may open sessions.
+    pub(crate) fn allows_peer(&self, address: Option<SocketAddr>) -> bool {
+        self.allows(address.map(|x| x.ip()))
+    }
+}
+
+/// Parse `cidr`, panicking with a readable message.
+fn parse_cidr(cidr: &str) -> Cidr {
+    match cidr.parse() {
+        Ok(x) => x,
+        Err(e) => panic!("Invalid network {cidr}: {e}"),
+    }
+}
+
+/// A [`Layer`] rejecting requests from addresses its [`IpFilter`] does not allow with
+/// [`AGIError::ClientSideError`].
+#[derive(Debug, Clone)]
+pub struct IpFilterLayer {
+    filter: IpFilter,
+}
+impl IpFilterLayer {
+    /// Create a layer filtering with `filter`.
+    pub fn new(filter: IpFilter) -> Self {
+        Self { filter }
+    }
+}
+impl Layer for IpFilterLayer {
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(IpFilterHandler {
+            filter: self.filter.clone(),
+            inner: Box::new(handler),
+        })
+    }
+}
+
+/// The handler created by an [`IpFilterLayer`].
+#[derive(Debug)]
+struct IpFilterHandler {
+    filter: IpFilter,
+    inner: Box<dyn AGIHandler>,
+}
+#[async_trait::async_trait]
+impl AGIHandler for IpFilterHandler {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        if !self.filter.allows_peer(request.peer_addr) {
+            return Err(AGIError::ClientSideError(format!(
+                "The address {:?} is not allowed",
+                request.peer_addr
+            )));
+        };
+        self.inner.handle(connection, request).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(x: &str) -> Option<IpAddr> {
+        Some(x.parse().unwrap())
+    }
+
+    #[test]
+    fn parse_cidrs() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<Cidr>().unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+        assert_eq!(
+            "10.0.0.0/33".parse::<Cidr>(),
+            Err(CidrParseError::InvalidPrefix("33".to_owned()))
+        );
+        assert_eq!(
+            "10.0.0/8".parse::<Cidr>(),
+            Err(CidrParseError::InvalidAddress("10.0.0".to_owned()))
+        );
+    }
+
+    #[test]
+    fn networks_contain_addresses() {
+        let net = "192.168.8.0/21".parse::<Cidr>().unwrap();
+        assert!(net.contains("192.168.15.255".parse().unwrap()));
+        assert!(!net.contains("192.168.16.0".parse().unwrap()));
+        assert!(net.contains("::ffff:192.168.9.1".parse().unwrap()));
+        assert!(!net.contains("::1".parse().unwrap()));
+        let everything = "0.0.0.0/0".parse::<Cidr>().unwrap();
+        assert!(everything.contains("1.2.3.4".parse().unwrap()));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = IpFilter::new().allow("10.0.0.0/8").deny("10.0.0.99");
+        assert!(filter.allows(ip("10.1.2.3")));
+        assert!(!filter.allows(ip("10.0.0.99")));
+        assert!(!filter.allows(ip("192.168.0.1")));
+        assert!(!filter.allows(None));
+        let filter = IpFilter::new().deny("10.0.0.0/8");
+        assert!(filter.allows(ip("192.168.0.1")));
+        assert!(filter.allows(None));
+        assert!(!filter.allows(ip("10.0.0.1")));
+    }
+
+    #[derive(Debug)]
+    struct Accept;
+    #[async_trait::async_trait]
+    impl AGIHandler for Accept {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn layer_rejects_unknown_peers() {
+        let handler = IpFilterLayer::new(IpFilter::new().allow("::1")).layer(Accept);
+        let mut request = AGIRequest {
+            variables: crate::AGIVariableDump::builder(url::Url::parse("agi://host/").unwrap())
+                .build(),
+            captures: std::collections::HashMap::new(),
+            wildcards: None,
+            host: None,
+            query: std::collections::HashMap::new(),
+            state: None,
+            peer_addr: Some("[::1]:5000".parse().unwrap()),
+            local_addr: None,
+            route: None,
+        };
+        let (_, server_side) = tokio::io::duplex(64);
+        let mut connection = Connection::new(server_side);
+        assert!(handler.handle(&mut connection, &request).await.is_ok());
+        request.peer_addr = Some("127.0.0.1:5000".parse().unwrap());
+        assert!(matches!(
+            handler.handle(&mut connection, &request).await,
+            Err(AGIError::ClientSideError(_))
+        ));
+        request.peer_addr = None;
+        assert!(matches!(
+            handler.handle(&mut connection, &request).await,
+            Err(AGIError::ClientSideError(_))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid network 10.0.0.0/99: 99 is not a valid prefix length")]
+    fn invalid_networks_panic() {
+        let _ = IpFilter::new().allow("10.0.0.0/99");
+    }
+}
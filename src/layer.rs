@@ -1,7 +1,10 @@
 //! Defines the Layer, a way to transform an [`AGIHandler`] into another one.
-use crate::handler::AndThenHandler;
+use std::sync::Arc;
+
+use crate::handler::{AndThenHandler, BoxHandlerFuture};
 
 use crate::handler::AGIHandler;
+use crate::{AGIError, AGIRequest, Connection};
 
 /// A layer (middleware) that transforms a handler into another handler
 pub trait Layer: Clone {
@@ -35,3 +38,303 @@ where
         Box::new(AndThenHandler::new(self.handler.clone(), Box::new(handler)))
     }
 }
+
+/// The handler wrapped by a [`from_fn`] layer.
+pub struct Next<'a> {
+    inner: &'a dyn AGIHandler,
+}
+impl<'a> Next<'a> {
+    /// Run the wrapped handler.
+    pub async fn run(
+        self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        self.inner.handle(connection, request).await
+    }
+}
+impl core::fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Next({:?})", self.inner)
+    }
+}
+
+/// A layer running a function around the handler it is applied to.
+///
+/// Create this with [`from_fn`], [`before`] or [`after`].
+pub struct FromFnLayer<F> {
+    function: Arc<F>,
+}
+impl<F> Clone for FromFnLayer<F> {
+    fn clone(&self) -> Self {
+        Self {
+            function: self.function.clone(),
+        }
+    }
+}
+impl<F> core::fmt::Debug for FromFnLayer<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "FromFnLayer({})", std::any::type_name::<F>())
+    }
+}
+impl<F> Layer for FromFnLayer<F>
+where
+    F: for<'a> Fn(&'a mut Connection, &'a AGIRequest, Next<'a>) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(FromFnHandler {
+            function: self.function.clone(),
+            inner: Box::new(handler),
+        })
+    }
+}
+
+/// The handler created by a [`FromFnLayer`].
+struct FromFnHandler<F> {
+    function: Arc<F>,
+    inner: Box<dyn AGIHandler>,
+}
+impl<F> core::fmt::Debug for FromFnHandler<F> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "FromFnHandler({:?})", self.inner)
+    }
+}
+#[async_trait::async_trait]
+impl<F> AGIHandler for FromFnHandler<F>
+where
+    F: for<'a> Fn(&'a mut Connection, &'a AGIRequest, Next<'a>) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        (self.function)(
+            connection,
+            request,
+            Next {
+                inner: &*self.inner,
+            },
+        )
+        .await
+    }
+}
+
+/// Create a layer running `function` around the handler it is applied to.
+///
+/// `function` gets the connection, the request and the wrapped handler as [`Next`]. It decides
+/// whether and when to run the handler and what to do with its result. Like with
+/// [`handler_fn`](crate::handler::handler_fn), it has to return a boxed future.
+///
+/// Example: log how long each handler took
+/// ```
+/// use blazing_agi::{layer::from_fn, router::Router};
+///
+/// let router = Router::new().layer(from_fn(|connection, request, next| {
+///     Box::pin(async move {
+///         let start = std::time::Instant::now();
+///         let result = next.run(connection, request).await;
+///         println!("{} took {:?}", request.variables.request, start.elapsed());
+///         result
+///     })
+/// }));
+/// ```
+pub fn from_fn<F>(function: F) -> FromFnLayer<F>
+where
+    F: for<'a> Fn(&'a mut Connection, &'a AGIRequest, Next<'a>) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    FromFnLayer {
+        function: Arc::new(function),
+    }
+}
+
+/// Create a layer running `function` before the handler it is applied to.
+///
+/// The handler only runs if `function` returns `Ok(())`.
+pub fn before<F>(
+    function: F,
+) -> FromFnLayer<
+    impl for<'a> Fn(&'a mut Connection, &'a AGIRequest, Next<'a>) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+>
+where
+    F: for<'a> Fn(&'a mut Connection, &'a AGIRequest) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    let function = Arc::new(function);
+    from_fn(move |connection, request, next| {
+        let function = function.clone();
+        Box::pin(async move {
+            function(connection, request).await?;
+            next.run(connection, request).await
+        })
+    })
+}
+
+/// Create a layer running `function` after the handler it is applied to.
+///
+/// `function` gets the result of the handler and returns the result of the layered handler, so
+/// it can observe, replace or map errors.
+///
+/// Example: tell asterisk whether the handler succeeded
+/// ```
+/// use blazing_agi::{command::SetVariable, layer::after, router::Router};
+///
+/// let router = Router::new().layer(after(|connection, _request, result| {
+///     Box::pin(async move {
+///         let value = if result.is_ok() { "SUCCESS" } else { "FAILURE" };
+///         connection
+///             .send_command(SetVariable::new("AGI_RESULT".to_owned(), value.to_owned()))
+///             .await?;
+///         result
+///     })
+/// }));
+/// ```
+pub fn after<F>(
+    function: F,
+) -> FromFnLayer<
+    impl for<'a> Fn(&'a mut Connection, &'a AGIRequest, Next<'a>) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+>
+where
+    F: for<'a> Fn(&'a mut Connection, &'a AGIRequest, Result<(), AGIError>) -> BoxHandlerFuture<'a>
+        + Send
+        + Sync
+        + 'static,
+{
+    let function = Arc::new(function);
+    from_fn(move |connection, request, next| {
+        let function = function.clone();
+        Box::pin(async move {
+            let result = next.run(connection, request).await;
+            function(connection, request, result).await
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::AGIVariableDump;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    /// Logs that it ran and fails if `fail` is set.
+    #[derive(Debug)]
+    struct Inner {
+        log: Log,
+        fail: bool,
+    }
+    #[async_trait::async_trait]
+    impl AGIHandler for Inner {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            self.log.lock().unwrap().push("handler");
+            if self.fail {
+                Err(AGIError::ClientSideError("inner".to_owned()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    async fn run(handler: Box<dyn AGIHandler>) -> Result<(), AGIError> {
+        let (_, server_side) = tokio::io::duplex(64);
+        let request = AGIRequest {
+            variables: AGIVariableDump::builder("agi://host/".parse().unwrap()).build(),
+            captures: HashMap::new(),
+            wildcards: None,
+            host: None,
+            query: HashMap::new(),
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        };
+        handler
+            .handle(&mut Connection::new(server_side), &request)
+            .await
+    }
+
+    #[tokio::test]
+    async fn hooks_run_in_order() {
+        let log = Log::default();
+        let (before_log, after_log, around_log) = (log.clone(), log.clone(), log.clone());
+        let handler = Inner {
+            log: log.clone(),
+            fail: false,
+        };
+        let handler = after(move |_, _, result| {
+            after_log.lock().unwrap().push("after");
+            Box::pin(async move { result })
+        })
+        .layer(handler);
+        let handler = before(move |_, _| {
+            before_log.lock().unwrap().push("before");
+            Box::pin(async { Ok(()) })
+        })
+        .layer(handler);
+        let handler = from_fn(move |connection, request, next| {
+            let log = around_log.clone();
+            Box::pin(async move {
+                log.lock().unwrap().push("around start");
+                let result = next.run(connection, request).await;
+                log.lock().unwrap().push("around end");
+                result
+            })
+        })
+        .layer(handler);
+        assert!(run(handler).await.is_ok());
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["around start", "before", "handler", "after", "around end"]
+        );
+    }
+
+    #[tokio::test]
+    async fn after_maps_errors() {
+        let log = Log::default();
+        let handler = after(|_, _, result| {
+            Box::pin(
+                async move { result.map_err(|e| AGIError::ClientSideError(format!("mapped {e}"))) },
+            )
+        })
+        .layer(Inner {
+            log: log.clone(),
+            fail: true,
+        });
+        assert!(
+            matches!(run(handler).await, Err(AGIError::ClientSideError(x)) if x.starts_with("mapped"))
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_before_skips_the_handler() {
+        let log = Log::default();
+        let handler =
+            before(|_, _| Box::pin(async { Err(AGIError::ClientSideError("denied".to_owned())) }))
+                .layer(Inner {
+                    log: log.clone(),
+                    fail: false,
+                });
+        assert!(run(handler).await.is_err());
+        assert!(log.lock().unwrap().is_empty());
+    }
+}
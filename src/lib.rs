@@ -49,23 +49,37 @@
 //! [`AGIError`], which tells the runtime that something went wrong - the stream is also closed.
 use std::collections::HashMap;
 
-use agiparse::{AGIMessage, AGIParseError, AGIStatusGeneric, AGIVariableDump};
+use agiparse::{AGIMessage, AGIParseError};
+pub use agiparse::{AGIRequestType, AGIStatusGeneric, AGIVariableDump, AGIVariableDumpBuilder};
 use connection::Connection;
 use handler::AGIHandler;
 
 mod agiparse;
+#[cfg(feature = "auth")]
+pub mod auth;
+pub mod client;
 pub mod command;
+pub mod concurrency_limit;
 pub mod connection;
+pub mod extract;
 pub mod handler;
+pub mod ip_filter;
 pub mod layer;
+pub mod proxy_protocol;
+pub mod rate_limit;
 pub mod router;
 pub mod serve;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod transcript;
 
 /// Contains all the ways in which serving a `FastAGI` Request can fail.
 #[derive(Debug)]
 pub enum AGIError {
     /// Handlers may use this to bubble up errors if they want.
-    InnerError(Box<dyn std::error::Error>),
+    InnerError(Box<dyn std::error::Error + Send + Sync>),
     /// A special case:
     /// This is raised when the client (asterisk) made a well-formed request
     /// with incorrect data (such as Unauth etc) - the handler asks the router to break
@@ -130,13 +144,79 @@ impl core::fmt::Display for AGIError {
 }
 impl std::error::Error for AGIError {}
 
+/// The state of a router, see [`Router::with_state`](crate::router::Router::with_state).
+pub type AGIState = std::sync::Arc<dyn std::any::Any + Send + Sync>;
+
 /// The Data sent with the request.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct AGIRequest {
     /// The individual variables that asterisk sent.
     pub variables: AGIVariableDump,
-    /// The pathsegments of the request uri that were captured in :capture segments.
+    /// The pathsegments of the request uri that were captured in :capture segments, with
+    /// percent-encoding removed.
     pub captures: HashMap<String, String>,
-    /// The pathsegments of the request uri that were captured in * segments.
+    /// The pathsegments of the request uri that were captured in * segments, with
+    /// percent-encoding removed.
     pub wildcards: Option<String>,
+    /// The host of the request uri in lowercase, e.g. `tenant-a.agi.local` for
+    /// `agi://Tenant-A.agi.local/script`.
+    pub host: Option<String>,
+    /// The query parameters of the request uri, e.g. `lang => [de]` for
+    /// `agi://host/script?lang=de`. A parameter given more then once has all its values, in
+    /// order.
+    pub query: HashMap<String, Vec<String>>,
+    /// The state of the router handling the request, if it has one.
+    /// Use [`State`](crate::extract::State) or [`AGIRequest::state_as`] to get it as its actual
+    /// type.
+    pub state: Option<AGIState>,
+    /// The address of the client (asterisk), if the request came in over the network.
+    pub peer_addr: Option<std::net::SocketAddr>,
+    /// The address the client connected to, if the request came in over the network.
+    pub local_addr: Option<std::net::SocketAddr>,
+    /// The pattern of the route the router dispatched the request to, e.g. `/user/:id` for
+    /// `agi://host/user/7`. For conditional routes, this is the prefix they were added for.
+    /// `None` for fallbacks and requests not dispatched by a router.
+    pub route: Option<String>,
+}
+/// Requests are equal if they have the same data and the very same state.
+impl PartialEq for AGIRequest {
+    fn eq(&self, other: &Self) -> bool {
+        self.variables == other.variables
+            && self.captures == other.captures
+            && self.wildcards == other.wildcards
+            && self.host == other.host
+            && self.query == other.query
+            && self.peer_addr == other.peer_addr
+            && self.local_addr == other.local_addr
+            && self.route == other.route
+            && match (&self.state, &other.state) {
+                (Some(a), Some(b)) => std::sync::Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+impl AGIRequest {
+    /// The value of the capture `name`, parsed as a `T`.
+    ///
+    /// Returns `None` if there is no such capture or its value cannot be parsed. Captures
+    /// constrained to a type in the route (like `:id<u32>`) can always be parsed as that type.
+    pub fn capture<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.captures.get(name).and_then(|x| x.parse().ok())
+    }
+
+    /// The state of the router handling the request, if it has one and it is an `S`.
+    pub fn state_as<S: Send + Sync + 'static>(&self) -> Option<std::sync::Arc<S>> {
+        self.state
+            .clone()
+            .and_then(|state| state.downcast::<S>().ok())
+    }
+
+    /// The first value of the query parameter `name`, if it was given.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .get(name)
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
 }
@@ -0,0 +1,482 @@
+//! Accept connections through a proxy speaking the
+//! [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt).
+//!
+//! Behind a load balancer like HAProxy, every connection comes from the proxy. With the PROXY
+//! protocol, the proxy sends the address of the original client before any other data. Enable it
+//! with [`Router::proxy_protocol`](crate::router::Router::proxy_protocol); the original
+//! addresses then become [`AGIRequest::peer_addr`](crate::AGIRequest::peer_addr) and
+//! [`AGIRequest::local_addr`](crate::AGIRequest::local_addr) and are checked by the
+//! [`ip_filter`](crate::router::Router::ip_filter).
+//! ```
+//! use blazing_agi::{proxy_protocol::ProxyProtocol, router::Router};
+//!
+//! let router = Router::new().proxy_protocol(ProxyProtocol::Required);
+//! ```
+//! Both version 1 (text) and version 2 (binary) headers are understood.
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+/// Whether connections start with a PROXY protocol header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProxyProtocol {
+    /// Connections never start with a header. This is the default.
+    #[default]
+    Disabled,
+    /// Connections may start with a header.
+    ///
+    /// Only use this if clients cannot connect without going through the proxy, since anyone can
+    /// send a header claiming any address.
+    Optional,
+    /// Connections must start with a header. Connections without one are dropped.
+    Required,
+}
+
+/// The addresses sent by the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ProxyHeader {
+    /// The address of the original client, or `None` if the proxy did not send one (e.g. for its
+    /// own health checks).
+    pub(crate) source: Option<SocketAddr>,
+    /// The address the original client connected to.
+    pub(crate) destination: Option<SocketAddr>,
+}
+
+/// Errors when reading a PROXY protocol header.
+#[derive(Debug, PartialEq)]
+pub(crate) enum ProxyProtocolError {
+    /// The connection did not start with a header, but one is required.
+    Missing,
+    /// The header is malformed.
+    Invalid(&'static str),
+    /// The connection closed or failed before the header was complete.
+    Closed,
+    /// The header was not complete in time.
+    TimedOut,
+}
+impl core::fmt::Display for ProxyProtocolError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "The connection did not start with a PROXY header"),
+            Self::Invalid(x) => write!(f, "The PROXY header is invalid: {x}"),
+            Self::Closed => write!(f, "The connection closed during the PROXY header"),
+            Self::TimedOut => write!(f, "The PROXY header did not arrive in time"),
+        }
+    }
+}
+impl std::error::Error for ProxyProtocolError {}
+
+/// The start of a version 1 header.
+const V1_SIGNATURE: &[u8] = b"PROXY ";
+/// The longest valid version 1 header, including the final CRLF.
+const V1_MAX_LENGTH: usize = 107;
+/// The start of a version 2 header.
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+/// The length of a version 2 header before the addresses.
+const V2_FIXED_LENGTH: usize = 16;
+
+/// The result of parsing the start of a connection.
+#[derive(Debug, PartialEq)]
+enum Parsed {
+    /// More data is needed to decide.
+    Incomplete,
+    /// The connection does not start with a header.
+    NotProxy,
+    /// A header of the given length in bytes.
+    Header(ProxyHeader, usize),
+}
+
+/// Whether `buf` starts with `signature`, or is the start of it.
+fn could_be(buf: &[u8], signature: &[u8]) -> bool {
+    let len = buf.len().min(signature.len());
+    buf[..len] == signature[..len]
+}
+
+/// Parse a header at the start of `buf`.
+fn parse(buf: &[u8]) -> Result<Parsed, ProxyProtocolError> {
+    if buf.starts_with(V1_SIGNATURE) {
+        parse_v1(buf)
+    } else if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if could_be(buf, V1_SIGNATURE) || could_be(buf, V2_SIGNATURE) {
+        Ok(Parsed::Incomplete)
+    } else {
+        Ok(Parsed::NotProxy)
+    }
+}
+
+/// Parse a version 1 header like `PROXY TCP4 192.0.2.1 192.0.2.2 56324 4573\r\n`.
+fn parse_v1(buf: &[u8]) -> Result<Parsed, ProxyProtocolError> {
+    let Some(end) = buf.windows(2).position(|x| x == b"\r\n") else {
+        return if buf.len() >= V1_MAX_LENGTH {
+            Err(ProxyProtocolError::Invalid("the header is too long"))
+        } else {
+            Ok(Parsed::Incomplete)
+        };
+    };
+    if end + 2 > V1_MAX_LENGTH {
+        return Err(ProxyProtocolError::Invalid("the header is too long"));
+    };
+    let line = core::str::from_utf8(&buf[V1_SIGNATURE.len()..end])
+        .map_err(|_| ProxyProtocolError::Invalid("the header is not ASCII"))?;
+    let mut parts = line.split(' ');
+    let header = match parts.next() {
+        Some("UNKNOWN") => ProxyHeader {
+            source: None,
+            destination: None,
+        },
+        Some(family @ ("TCP4" | "TCP6")) => {
+            let parts = parts.collect::<Vec<_>>();
+            let [source, destination, source_port, destination_port] = parts[..] else {
+                return Err(ProxyProtocolError::Invalid("wrong number of fields"));
+            };
+            let address = |ip: &str, port: &str| -> Result<SocketAddr, ProxyProtocolError> {
+                let ip = ip
+                    .parse::<IpAddr>()
+                    .map_err(|_| ProxyProtocolError::Invalid("invalid address"))?;
+                if ip.is_ipv4() != (family == "TCP4") {
+                    return Err(ProxyProtocolError::Invalid("address of the wrong family"));
+                };
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| ProxyProtocolError::Invalid("invalid port"))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            ProxyHeader {
+                source: Some(address(source, source_port)?),
+                destination: Some(address(destination, destination_port)?),
+            }
+        }
+        _ => return Err(ProxyProtocolError::Invalid("unknown protocol")),
+    };
+    Ok(Parsed::Header(header, end + 2))
+}
+
+/// Parse a binary version 2 header.
+fn parse_v2(buf: &[u8]) -> Result<Parsed, ProxyProtocolError> {
+    if buf.len() < V2_FIXED_LENGTH {
+        return Ok(Parsed::Incomplete);
+    };
+    let version_command = buf[12];
+    let family = buf[13];
+    let length = V2_FIXED_LENGTH + usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    if version_command >> 4 != 2 {
+        return Err(ProxyProtocolError::Invalid("unknown version"));
+    };
+    if buf.len() < length {
+        return Ok(Parsed::Incomplete);
+    };
+    let addresses = &buf[V2_FIXED_LENGTH..length];
+    let no_addresses = ProxyHeader {
+        source: None,
+        destination: None,
+    };
+    let header = match version_command & 0x0f {
+        // LOCAL: the proxy connected on its own behalf
+        0x0 => no_addresses,
+        // PROXY
+        0x1 => match family >> 4 {
+            // INET
+            0x1 => {
+                let Some(a) = addresses.get(..12) else {
+                    return Err(ProxyProtocolError::Invalid("the addresses are too short"));
+                };
+                ProxyHeader {
+                    source: Some(SocketAddr::new(
+                        Ipv4Addr::new(a[0], a[1], a[2], a[3]).into(),
+                        u16::from_be_bytes([a[8], a[9]]),
+                    )),
+                    destination: Some(SocketAddr::new(
+                        Ipv4Addr::new(a[4], a[5], a[6], a[7]).into(),
+                        u16::from_be_bytes([a[10], a[11]]),
+                    )),
+                }
+            }
+            // INET6
+            0x2 => {
+                let Some(a) = addresses.get(..36) else {
+                    return Err(ProxyProtocolError::Invalid("the addresses are too short"));
+                };
+                let ip = |x: &[u8]| -> IpAddr {
+                    let mut octets = [0_u8; 16];
+                    octets.copy_from_slice(x);
+                    Ipv6Addr::from(octets).into()
+                };
+                ProxyHeader {
+                    source: Some(SocketAddr::new(
+                        ip(&a[..16]),
+                        u16::from_be_bytes([a[32], a[33]]),
+                    )),
+                    destination: Some(SocketAddr::new(
+                        ip(&a[16..32]),
+                        u16::from_be_bytes([a[34], a[35]]),
+                    )),
+                }
+            }
+            // UNSPEC and UNIX addresses are ignored
+            _ => no_addresses,
+        },
+        _ => return Err(ProxyProtocolError::Invalid("unknown command")),
+    };
+    Ok(Parsed::Header(header, length))
+}
+
+/// Read the header from the start of `stream`, waiting at most `timeout` for it.
+///
+/// Returns the header (if there is one) and the bytes read after it, which belong to the `FastAGI`
+/// session.
+pub(crate) async fn read_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    mode: ProxyProtocol,
+    timeout: Duration,
+) -> Result<(Option<ProxyHeader>, Vec<u8>), ProxyProtocolError> {
+    tokio::time::timeout(timeout, read_header_untimed(stream, mode))
+        .await
+        .unwrap_or(Err(ProxyProtocolError::TimedOut))
+}
+
+/// Read the header from the start of `stream`, however long it takes.
+async fn read_header_untimed<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    mode: ProxyProtocol,
+) -> Result<(Option<ProxyHeader>, Vec<u8>), ProxyProtocolError> {
+    let mut buf = Vec::with_capacity(256);
+    loop {
+        match parse(&buf)? {
+            Parsed::Incomplete => {
+                let mut chunk = [0_u8; 512];
+                let read = stream
+                    .read(&mut chunk)
+                    .await
+                    .map_err(|_| ProxyProtocolError::Closed)?;
+                if read == 0 {
+                    return Err(ProxyProtocolError::Closed);
+                };
+                buf.extend_from_slice(&chunk[..read]);
+            }
+            Parsed::NotProxy => {
+                return match mode {
+                    ProxyProtocol::Required => Err(ProxyProtocolError::Missing),
+                    ProxyProtocol::Optional | ProxyProtocol::Disabled => Ok((None, buf)),
+                };
+            }
+            Parsed::Header(header, length) => {
+                buf.drain(..length);
+                return Ok((Some(header), buf));
+            }
+        }
+    }
+}
+
+/// A stream returning `prefix` before the data of `inner`.
+#[derive(Debug)]
+pub(crate) struct Rewind<S> {
+    prefix: Vec<u8>,
+    inner: S,
+}
+impl<S> Rewind<S> {
+    pub(crate) fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self { prefix, inner }
+    }
+}
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.prefix.is_empty() {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        };
+        let len = self.prefix.len().min(buf.remaining());
+        buf.put_slice(&self.prefix[..len]);
+        self.prefix.drain(..len);
+        Poll::Ready(Ok(()))
+    }
+}
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    fn header(source: &str, destination: &str) -> ProxyHeader {
+        ProxyHeader {
+            source: Some(source.parse().unwrap()),
+            destination: Some(destination.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn parse_v1_headers() {
+        let buf = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 4573\r\nagi_network: yes\n";
+        assert_eq!(
+            parse(buf),
+            Ok(Parsed::Header(
+                header("192.0.2.1:56324", "192.0.2.2:4573"),
+                43
+            ))
+        );
+        assert_eq!(
+            parse(b"PROXY TCP6 2001:db8::1 ::1 56324 4573\r\n"),
+            Ok(Parsed::Header(
+                header("[2001:db8::1]:56324", "[::1]:4573"),
+                39
+            ))
+        );
+        assert_eq!(
+            parse(b"PROXY UNKNOWN\r\n"),
+            Ok(Parsed::Header(
+                ProxyHeader {
+                    source: None,
+                    destination: None
+                },
+                15
+            ))
+        );
+        assert_eq!(
+            parse(b"PROXY TCP4 ::1 192.0.2.2 56324 4573\r\n"),
+            Err(ProxyProtocolError::Invalid("address of the wrong family"))
+        );
+        assert_eq!(
+            parse(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n"),
+            Err(ProxyProtocolError::Invalid("wrong number of fields"))
+        );
+        assert_eq!(
+            parse(&[b'P'; 200]),
+            Ok(Parsed::NotProxy),
+            "only the signature decides whether this is a header"
+        );
+        assert_eq!(
+            parse(&[b"PROXY ".as_slice(), &[b'x'; 200]].concat()),
+            Err(ProxyProtocolError::Invalid("the header is too long"))
+        );
+    }
+
+    #[test]
+    fn parse_v2_headers() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x11, 0, 15]);
+        buf.extend_from_slice(&[192, 0, 2, 1, 192, 0, 2, 2, 0xdc, 0x04, 0x11, 0xdd]);
+        // a TLV the parser skips
+        buf.extend_from_slice(&[0x04, 0, 0]);
+        assert_eq!(parse(&buf[..20]), Ok(Parsed::Incomplete));
+        assert_eq!(
+            parse(&buf),
+            Ok(Parsed::Header(
+                header("192.0.2.1:56324", "192.0.2.2:4573"),
+                31
+            ))
+        );
+
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x21, 0, 36]);
+        buf.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        buf.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        buf.extend_from_slice(&[0xdc, 0x04, 0x11, 0xdd]);
+        assert_eq!(
+            parse(&buf),
+            Ok(Parsed::Header(
+                header("[::1]:56324", "[2001:db8::2]:4573"),
+                52
+            ))
+        );
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(
+            parse(&local),
+            Ok(Parsed::Header(
+                ProxyHeader {
+                    source: None,
+                    destination: None
+                },
+                16
+            ))
+        );
+        local[12] = 0x10;
+        assert_eq!(
+            parse(&local),
+            Err(ProxyProtocolError::Invalid("unknown version"))
+        );
+    }
+
+    #[test]
+    fn parse_starts_of_connections() {
+        assert_eq!(parse(b""), Ok(Parsed::Incomplete));
+        assert_eq!(parse(b"PROX"), Ok(Parsed::Incomplete));
+        assert_eq!(parse(b"\r\n\r\n"), Ok(Parsed::Incomplete));
+        assert_eq!(parse(b"a"), Ok(Parsed::NotProxy));
+        assert_eq!(parse(b"agi_network: yes\n"), Ok(Parsed::NotProxy));
+    }
+
+    #[tokio::test]
+    async fn data_after_the_header_is_kept() {
+        let (mut client_side, mut server_side) = tokio::io::duplex(256);
+        client_side
+            .write_all(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 4573\r\nagi_network: yes\n")
+            .await
+            .unwrap();
+        let (parsed, rest) = read_header(
+            &mut server_side,
+            ProxyProtocol::Required,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(parsed, Some(header("192.0.2.1:56324", "192.0.2.2:4573")));
+        let mut stream = Rewind::new(rest, server_side);
+        let mut read = [0_u8; 17];
+        stream.read_exact(&mut read).await.unwrap();
+        assert_eq!(&read, b"agi_network: yes\n");
+    }
+
+    #[tokio::test]
+    async fn required_headers_must_be_sent() {
+        let (mut client_side, mut server_side) = tokio::io::duplex(256);
+        client_side.write_all(b"agi_network: yes\n").await.unwrap();
+        assert_eq!(
+            read_header(
+                &mut server_side,
+                ProxyProtocol::Required,
+                Duration::from_secs(5)
+            )
+            .await,
+            Err(ProxyProtocolError::Missing)
+        );
+        let (mut client_side, mut server_side) = tokio::io::duplex(256);
+        client_side.write_all(b"agi_network: yes\n").await.unwrap();
+        assert_eq!(
+            read_header(
+                &mut server_side,
+                ProxyProtocol::Optional,
+                Duration::from_secs(5)
+            )
+            .await,
+            Ok((None, b"agi_network: yes\n".to_vec()))
+        );
+    }
+}
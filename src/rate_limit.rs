@@ -0,0 +1,398 @@
+//! Limit how often handlers run.
+//!
+//! A [`RateLimitLayer`] keeps a token bucket for each key it computes from the [`AGIRequest`].
+//! Every request takes a token from its bucket, and the buckets refill at a fixed rate. When a
+//! bucket is empty, the layered handler does not run, and the layer instead does what its
+//! [`RateLimitAction`] says.
+//! ```
+//! use std::time::Duration;
+//!
+//! use blazing_agi::{rate_limit::{RateLimitAction, RateLimitLayer}, router::Router};
+//!
+//! // at most 5 calls per minute from each caller, with the caller told via a channel variable
+//! let router = Router::new().layer(
+//!     RateLimitLayer::new(5, Duration::from_secs(60))
+//!         .key(|request| request.variables.callerid.clone())
+//!         .on_exceeded(RateLimitAction::SetVariable(
+//!             "RATE_LIMITED".to_owned(),
+//!             "1".to_owned(),
+//!         )),
+//! );
+//! ```
+//!
+//! Clones of a layer share their buckets, so all routes a layer is added to share the same
+//! limits. To limit routes separately, key by the [`route`](AGIRequest::route) the request was
+//! dispatched to, or create a separate layer for each [`group`](crate::router::Router::group)
+//! of routes. Keying by the route rather than the path keeps `/user/1` and `/user/2` in the same
+//! bucket:
+//! ```
+//! # use std::time::Duration;
+//! # use blazing_agi::rate_limit::RateLimitLayer;
+//! let layer = RateLimitLayer::new(100, Duration::from_secs(1))
+//!     .key(|request| request.route.clone().unwrap_or_default());
+//! ```
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    command::SetVariable, handler::AGIHandler, layer::Layer, AGIError, AGIRequest, Connection,
+};
+
+/// Computes the key of the bucket a request takes its token from.
+type KeyFn = dyn Fn(&AGIRequest) -> String + Send + Sync;
+
+/// What a [`RateLimitLayer`] does instead of running the layered handler when the limit is
+/// exceeded.
+#[derive(Debug, Clone, Default)]
+pub enum RateLimitAction {
+    /// Return [`AGIError::ClientSideError`] right away. This is the default.
+    #[default]
+    Reject,
+    /// Run this handler instead, e.g. to play an announcement.
+    Handler(Arc<dyn AGIHandler>),
+    /// Set the channel variable (first) to the value (second) and return, so the dialplan can
+    /// decide what to do.
+    SetVariable(String, String),
+}
+
+/// A token bucket.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+impl Bucket {
+    /// Refill the bucket up to `now` and try to take a token.
+    fn take(&mut self, now: Instant, limit: &Limit) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket is full at `now`, so forgetting it changes nothing.
+    fn is_full(&self, now: Instant, limit: &Limit) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * limit.rate >= limit.burst
+    }
+}
+
+/// The size and refill rate of the buckets.
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    /// The number of tokens in a full bucket.
+    burst: f64,
+    /// Tokens added per second.
+    rate: f64,
+}
+
+/// The buckets of all keys seen recently.
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    /// Forget full buckets once there are this many.
+    cleanup_at: usize,
+}
+impl Buckets {
+    /// Try to take a token from the bucket for `key`.
+    fn take(&mut self, key: String, limit: &Limit) -> bool {
+        let now = Instant::now();
+        if self.buckets.len() >= self.cleanup_at {
+            self.buckets.retain(|_, bucket| !bucket.is_full(now, limit));
+            self.cleanup_at = (2 * self.buckets.len()).max(64);
+        };
+        self.buckets
+            .entry(key)
+            .or_insert(Bucket {
+                tokens: limit.burst,
+                updated: now,
+            })
+            .take(now, limit)
+    }
+}
+
+/// A [`Layer`] limiting how often the layered handler runs.
+///
+/// See the [module documentation](self) for how this works.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limit: Limit,
+    key: Arc<KeyFn>,
+    action: RateLimitAction,
+    buckets: Arc<Mutex<Buckets>>,
+}
+impl RateLimitLayer {
+    /// Create a layer allowing `requests` requests every `per`, shared by all requests.
+    ///
+    /// # Panics
+    /// Panics if `requests` or `per` is zero.
+    pub fn new(requests: u32, per: Duration) -> Self {
+        assert!(
+            requests > 0 && !per.is_zero(),
+            "A rate limit has to allow some requests."
+        );
+        Self {
+            limit: Limit {
+                burst: f64::from(requests),
+                rate: f64::from(requests) / per.as_secs_f64(),
+            },
+            key: Arc::new(|_| String::new()),
+            action: RateLimitAction::default(),
+            buckets: Arc::default(),
+        }
+    }
+
+    /// Allow up to `burst` requests at once (default: the `requests` passed to
+    /// [`new`](Self::new)), while still refilling at the same rate.
+    ///
+    /// # Panics
+    /// Panics if `burst` is zero.
+    #[must_use]
+    pub fn burst(mut self, burst: u32) -> Self {
+        assert!(burst > 0, "A rate limit has to allow some requests.");
+        self.limit.burst = f64::from(burst);
+        self
+    }
+
+    /// Limit requests with a different result of `key` separately, e.g. by caller ID, peer
+    /// address or [`route`](AGIRequest::route).
+    #[must_use]
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&AGIRequest) -> String + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Do `action` when the limit is exceeded (default: [`RateLimitAction::Reject`]).
+    #[must_use]
+    pub fn on_exceeded(mut self, action: RateLimitAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Try to take a token for `request`.
+    fn allows(&self, request: &AGIRequest) -> bool {
+        let key = (self.key)(request);
+        self.buckets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take(key, &self.limit)
+    }
+}
+impl core::fmt::Debug for RateLimitLayer {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("RateLimitLayer")
+            .field("limit", &self.limit)
+            .field("action", &self.action)
+            .finish_non_exhaustive()
+    }
+}
+impl Layer for RateLimitLayer {
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(RateLimitHandler {
+            layer: self.clone(),
+            inner: Box::new(handler),
+        })
+    }
+}
+
+/// The handler created by a [`RateLimitLayer`].
+#[derive(Debug)]
+struct RateLimitHandler {
+    layer: RateLimitLayer,
+    inner: Box<dyn AGIHandler>,
+}
+#[async_trait::async_trait]
+impl AGIHandler for RateLimitHandler {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        if self.layer.allows(request) {
+            return self.inner.handle(connection, request).await;
+        };
+        match &self.layer.action {
+            RateLimitAction::Reject => Err(AGIError::ClientSideError(
+                "The rate limit was exceeded".to_owned(),
+            )),
+            RateLimitAction::Handler(handler) => handler.handle(connection, request).await,
+            RateLimitAction::SetVariable(name, value) => {
+                connection
+                    .send_command(SetVariable::new(name.clone(), value.clone()))
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use url::Url;
+
+    use super::*;
+    use crate::{router::Router, AGIStatusGeneric, AGIVariableDump};
+
+    #[derive(Debug, Default)]
+    struct Count(AtomicUsize);
+    #[async_trait::async_trait]
+    impl AGIHandler for Arc<Count> {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    fn request(callerid: &str) -> AGIRequest {
+        AGIRequest {
+            variables: AGIVariableDump::builder(Url::parse("agi://host/").unwrap())
+                .callerid(callerid)
+                .build(),
+            captures: HashMap::new(),
+            wildcards: None,
+            host: None,
+            query: HashMap::new(),
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        }
+    }
+
+    /// Run `handler` for `request` on a connection nobody listens on.
+    async fn run(handler: &dyn AGIHandler, request: &AGIRequest) -> Result<(), AGIError> {
+        let (_, server_side) = tokio::io::duplex(64);
+        handler
+            .handle(&mut Connection::new(server_side), request)
+            .await
+    }
+
+    #[test]
+    fn buckets_refill() {
+        let limit = Limit {
+            burst: 2.0,
+            rate: 1.0,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 2.0,
+            updated: start,
+        };
+        assert!(bucket.take(start, &limit));
+        assert!(bucket.take(start, &limit));
+        assert!(!bucket.take(start, &limit));
+        assert!(!bucket.take(start + Duration::from_millis(500), &limit));
+        assert!(bucket.take(start + Duration::from_millis(1000), &limit));
+        assert!(!bucket.is_full(start + Duration::from_millis(2000), &limit));
+        assert!(bucket.is_full(start + Duration::from_millis(3000), &limit));
+        // a long break does not allow more than the burst
+        assert!(bucket.take(start + Duration::from_secs(100), &limit));
+        assert!(bucket.take(start + Duration::from_secs(100), &limit));
+        assert!(!bucket.take(start + Duration::from_secs(100), &limit));
+    }
+
+    #[tokio::test]
+    async fn keys_are_limited_separately() {
+        let count = Arc::new(Count::default());
+        let handler = RateLimitLayer::new(2, Duration::from_secs(3600))
+            .key(|request| request.variables.callerid.clone())
+            .layer(count.clone());
+        assert!(run(handler.as_ref(), &request("alice")).await.is_ok());
+        assert!(run(handler.as_ref(), &request("alice")).await.is_ok());
+        assert!(matches!(
+            run(handler.as_ref(), &request("alice")).await,
+            Err(AGIError::ClientSideError(_))
+        ));
+        assert!(run(handler.as_ref(), &request("bob")).await.is_ok());
+        assert_eq!(count.0.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn exceeded_limits_run_the_action() {
+        let count = Arc::new(Count::default());
+        let announcement = Arc::new(Count::default());
+        let layer = RateLimitLayer::new(1, Duration::from_secs(3600))
+            .on_exceeded(RateLimitAction::Handler(Arc::new(announcement.clone())));
+        let first = layer.layer(count.clone());
+        // handlers created by the same layer share the buckets
+        let second = layer.layer(count.clone());
+        assert!(run(first.as_ref(), &request("alice")).await.is_ok());
+        assert!(run(second.as_ref(), &request("bob")).await.is_ok());
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+        assert_eq!(announcement.0.load(Ordering::Relaxed), 1);
+    }
+
+    /// Run a session for `url` against `router`, returning the commands it sent.
+    async fn commands_for(router: &Arc<Router>, url: &str) -> Vec<String> {
+        let router = router.clone();
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { router.handle(server_side).await });
+        let session = crate::client::AGIClientSession::start(
+            client_side,
+            &AGIVariableDump::builder(Url::parse(url).unwrap()).build(),
+        )
+        .await
+        .unwrap();
+        let mut commands = vec![];
+        session
+            .run(|command| {
+                commands.push(command);
+                async { AGIStatusGeneric::Ok("1".to_owned(), None) }
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+        commands
+    }
+
+    fn limited() -> RateLimitLayer {
+        RateLimitLayer::new(1, Duration::from_secs(3600)).on_exceeded(RateLimitAction::SetVariable(
+            "LIMITED".to_owned(),
+            "yes".to_owned(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn exceeded_limits_set_variables() {
+        let router = Arc::new(
+            Router::new()
+                .route("/", Arc::new(Count::default()))
+                .layer(limited()),
+        );
+        assert!(commands_for(&router, "agi://host/").await.is_empty());
+        assert_eq!(
+            commands_for(&router, "agi://host/").await,
+            vec!["SET VARIABLE \"LIMITED\" \"yes\""]
+        );
+    }
+
+    #[tokio::test]
+    async fn routes_are_limited_separately() {
+        let router = Arc::new(
+            Router::new()
+                .route("/user/:id", Arc::new(Count::default()))
+                .route("/other", Arc::new(Count::default()))
+                .layer(limited().key(|request| request.route.clone().unwrap_or_default())),
+        );
+        assert!(commands_for(&router, "agi://host/user/1").await.is_empty());
+        // another path, but the same route
+        assert_eq!(
+            commands_for(&router, "agi://host/user/2").await,
+            vec!["SET VARIABLE \"LIMITED\" \"yes\""]
+        );
+        assert!(commands_for(&router, "agi://host/other").await.is_empty());
+    }
+}
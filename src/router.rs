@@ -1,22 +1,102 @@
 //! The Router is the basic element describing a service you may want to run.
 //! A [`Router`] is made up of [`AGIHandler`]s at some paths, potentially with [`Layer`]s to apply
 //! logic to multiple routes at once.
-use tokio::net::TcpStream;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use percent_encoding::percent_decode_str;
+
 #[cfg(feature = "tracing")]
 use tracing::{error, event, info, trace, warn, Level};
-use url::Url;
 
 use crate::*;
 
 use self::agiparse::{AGIMessage, AGIRequestType};
-use self::{handler::FallbackHandler, layer::Layer};
+use self::host_pattern::HostPattern;
+use self::route_tree::{Predicate, RouteTree};
+use self::{
+    connection::AGIStream,
+    handler::{FallbackHandler, IntoAGIHandler},
+    layer::Layer,
+};
+use crate::ip_filter::IpFilter;
+use crate::proxy_protocol::{self, ProxyProtocol};
+use crate::transcript::TranscriptSink;
+
+pub mod extension_pattern;
+mod host_pattern;
+mod route_tree;
+
+pub use self::extension_pattern::ExtensionPattern;
+
+/// Wraps a handler in a layer.
+type WrapFn = dyn Fn(Box<dyn AGIHandler>) -> Box<dyn AGIHandler> + Send + Sync;
+
+/// A [`Layer`] in the middleware stack of a [`Router`], with its type erased.
+struct StackLayer(Box<WrapFn>);
+impl core::fmt::Debug for StackLayer {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "StackLayer")
+    }
+}
+
+/// The handler chosen for a request, before the middleware stack is applied.
+struct Dispatch {
+    handler: Box<dyn AGIHandler>,
+    captures: HashMap<String, String>,
+    wildcards: Option<String>,
+    /// The pattern of the route `handler` was added for, unless it is a fallback.
+    route: Option<String>,
+    /// Whether `handler` is a fallback.
+    fallback: bool,
+}
+
+/// Runs a handler with the state of the router it was nested from.
+#[derive(Debug)]
+struct WithState {
+    state: AGIState,
+    inner: Arc<dyn AGIHandler>,
+}
+#[async_trait::async_trait]
+impl AGIHandler for WithState {
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        let request = AGIRequest {
+            state: Some(self.state.clone()),
+            ..request.clone()
+        };
+        self.inner.handle(connection, &request).await
+    }
+}
 
 /// A router contains the mapping from request path to handlers
 /// and contains the logic for dispatching requests.
 #[derive(Debug)]
 pub struct Router {
-    routes: Vec<(Vec<String>, Box<dyn AGIHandler>)>,
-    fallback: Box<dyn AGIHandler>,
+    routes: RouteTree<Arc<dyn AGIHandler>>,
+    /// The fallback for requests no route (and no fallback of a nested router) matches.
+    /// `None` means the default [`FallbackHandler`].
+    fallback: Option<Arc<dyn AGIHandler>>,
+    /// The middleware stack applied to each handler when dispatching, innermost first. Empty
+    /// once the router is [`finish`](Self::finish)ed.
+    layers: Vec<StackLayer>,
+    /// Whether `layers` also apply to fallbacks.
+    layer_fallback: bool,
+    /// Routers handling all requests for a host, in the order they were added.
+    hosts: Vec<(HostPattern, Router)>,
+    /// The state passed to all handlers in the [`AGIRequest`].
+    state: Option<AGIState>,
+    recorder: Option<Arc<dyn TranscriptSink>>,
+    /// The addresses allowed to open sessions.
+    ip_filter: Option<IpFilter>,
+    /// Whether connections start with a PROXY protocol header.
+    proxy_protocol: ProxyProtocol,
+    /// How long to wait for the PROXY protocol header.
+    proxy_protocol_timeout: Duration,
 }
 impl Default for Router {
     fn default() -> Self {
@@ -30,26 +110,46 @@ impl Router {
     #[must_use = "Run this router with blazing_agi::serve::serve"]
     pub fn new() -> Self {
         Router {
-            routes: vec![],
-            fallback: Box::new(FallbackHandler {}),
+            routes: RouteTree::default(),
+            fallback: None,
+            layers: vec![],
+            layer_fallback: false,
+            hosts: vec![],
+            state: None,
+            recorder: None,
+            ip_filter: None,
+            proxy_protocol: ProxyProtocol::Disabled,
+            proxy_protocol_timeout: Duration::from_secs(5),
         }
     }
 
     /// Add a route to this router.
     /// This is a mapping path -> handler.
+    /// The handler is either an [`AGIHandler`], an async function taking the connection and the
+    /// request (see [`RequestFn`](crate::handler::RequestFn)) or an async function taking
+    /// extractors (see [`extract`](crate::extract)).
     ///
     /// The location MUST start with `/`.
     /// The location MAY contain any number of `:capture` segments. The value of the matching
     /// request path in this segment will be collectted into the `captures` field of the
     /// [`AGIRequest`] passed to your handler.
+    /// A capture MAY be constrained with `:capture<constraint>`, where the constraint is either an
+    /// integer type like `u32` or `i64`, or a regex the whole segment has to match (like
+    /// `[a-z]+`). A segment not satisfying the constraint does not match the capture, so the
+    /// request may still match another route or the fallback. Use
+    /// [`AGIRequest::capture`] to get the value as a typed value.
+    /// Captures at the end of the location MAY be optional (`:capture?` or `:capture<u32>?`).
+    /// A request leaving them out still matches, but has no value for them.
     /// The location MAY end in a `*wildcard` segment. Anything (even multiple segments, or the
     /// empty segment) matches this wilcard. The value matched will be collected into the
     /// `wildcards` field of the [`AGIRequest`] passed to your handler.
     ///
-    /// location matching happens from the first added route to the last added.
-    /// The first match found will be chosen, even if another would also match with a shorter
-    /// wildcard match.
-    /// There is no logic to ensure that two locations do not overlap.
+    /// When more then one route matches a request, static segments win over constrained captures
+    /// (in the order they were added), those win over unconstrained captures, and captures win
+    /// over wildcards, segment by segment from the start of the path. The order in
+    /// which routes are added does not matter.
+    /// Finding the route takes time proportional to the length of the path, not to the number
+    /// of routes.
     ///
     /// Example:
     /// ```
@@ -78,23 +178,117 @@ impl Router {
     /// This functions panics when inputs are wrong - You are expected to create the Router
     /// immediately on service start.
     /// Panics if a path not starting with '/' is given.
+    /// Panics if the location conflicts with a route added before, i.e. if both would match
+    /// exactly the same requests, or if they use different names for a capture at the same
+    /// position. Leaving out optional captures counts, so `/a` and `/a/:b?` conflict.
+    /// Panics if a wildcard is not the last segment of the location, or an optional capture is
+    /// followed by a segment that is not optional.
+    /// Panics if a constraint is not a valid regex.
     #[must_use = "Run this router with blazing_agi::serve::serve"]
-    pub fn route<H>(mut self, location: &str, handler: H) -> Self
+    pub fn route<H, M>(mut self, location: &str, handler: H) -> Self
     where
-        H: AGIHandler + 'static,
+        H: IntoAGIHandler<M>,
     {
         assert!(!location.is_empty(), "Path must not be empty");
         assert!(location.starts_with('/'), "Path must start with a '/'");
-        self.routes.push((
-            location.split('/').skip(1).map(|s| s.to_owned()).collect(),
-            Box::new(handler),
-        ));
+        self.routes.insert(location, handler.into_handler().into());
         self
     }
 
+    /// Add a conditional route to this router: `handler` handles all requests for which
+    /// `predicate` returns true.
+    ///
+    /// Conditional routes are only considered when no route matches the request path. They are
+    /// tried in the order they were added, before the fallback.
+    /// In a router [`nest`](Self::nest)ed below a prefix, conditional routes only apply to paths
+    /// below that prefix.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{command::{verbose::Verbose, AGICommand}, router::Router, serve, AGIVariableDump};
+    /// # use blazing_agi_macros::create_handler;
+    /// #[create_handler]
+    /// async fn pstn_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    /// #[create_handler]
+    /// async fn internal_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .route_when(|req: &AGIVariableDump| req.context == "from-pstn", pstn_handler)
+    ///     .route_when(
+    ///         |req: &AGIVariableDump| req.custom_args.get(&1).is_some_and(|x| x == "internal"),
+    ///         internal_handler,
+    ///     );
+    /// ```
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn route_when<P, H, M>(mut self, predicate: P, handler: H) -> Self
+    where
+        P: Fn(&AGIVariableDump) -> bool + Send + Sync + 'static,
+        H: IntoAGIHandler<M>,
+    {
+        self.routes.insert_conditional(
+            "",
+            Predicate::new(predicate),
+            handler.into_handler().into(),
+        );
+        self
+    }
+
+    /// Add a conditional route to this router: `handler` handles all requests whose
+    /// `agi_extension` matches `pattern`, an extension pattern in asterisk dialplan syntax (see
+    /// [`ExtensionPattern`]).
+    ///
+    /// This is [`route_when`](Self::route_when) with a predicate matching the extension.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{command::{verbose::Verbose, AGICommand}, router::Router, serve};
+    /// # use blazing_agi_macros::create_handler;
+    /// #[create_handler]
+    /// async fn national_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .route_extension("_NXXNXXXXXX", national_handler);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `pattern` is not a valid extension pattern.
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn route_extension<H, M>(self, pattern: &str, handler: H) -> Self
+    where
+        H: IntoAGIHandler<M>,
+    {
+        let pattern = match pattern.parse::<ExtensionPattern>() {
+            Ok(x) => x,
+            Err(e) => panic!("{e}"),
+        };
+        self.route_when(
+            move |req: &AGIVariableDump| pattern.matches(&req.extension),
+            handler,
+        )
+    }
+
     /// Merge `self` with `other` router to combine routes.
     ///
-    /// The fallback of the first router will be chosen, the fallback of the second ignored.
+    /// If only one of the routers has a [`fallback`](Self::fallback) set, it becomes the fallback
+    /// of the merged router. Fallbacks of routers nested into either router stay in place.
+    /// The same goes for the [state](Self::with_state).
+    /// [`layer`](Self::layer)s of `other` only apply to its routes (and its fallback, if
+    /// [`layer_fallback`](Self::layer_fallback) is set), while layers of `self` also apply to the
+    /// merged routes. So `a.merge(b)` runs the routes of `b` in the layers of `a`, but
+    /// `b.merge(a)` does not run the routes of `a` in the layers of `b`.
+    /// If `self` records transcripts, `other`s recorder is ignored. The same goes for the
+    /// [`ip_filter`](Self::ip_filter) and the [`proxy_protocol`](Self::proxy_protocol).
+    ///
+    /// # Panics
+    /// Panics if a route in `other` conflicts with a route in `self`
+    /// (see [`route`](Self::route)).
+    /// Panics if both routers have a fallback or both have a state set.
     ///
     /// Example:
     /// ```
@@ -116,11 +310,245 @@ impl Router {
     /// let full_router = some_router.merge(api_router);
     /// ```
     #[must_use = "Run this router with blazing_agi::serve::serve"]
-    pub fn merge(mut self, mut other: Router) -> Router {
-        self.routes.append(&mut other.routes);
+    pub fn merge(mut self, other: Router) -> Router {
+        let other = other.bake_layers();
+        self.routes.graft("", other.routes);
+        if let Some(fallback) = other.fallback {
+            assert!(
+                self.fallback.is_none(),
+                "Both merged routers have a fallback. Set it on at most one of them."
+            );
+            self.fallback = Some(fallback);
+        };
+        for (pattern, router) in other.hosts {
+            self.add_host(pattern, router);
+        }
+        self.take_state(other.state);
+        if self.recorder.is_none() {
+            self.recorder = other.recorder;
+        };
+        if self.ip_filter.is_none() {
+            self.ip_filter = other.ip_filter;
+        };
+        if self.proxy_protocol == ProxyProtocol::Disabled {
+            self.proxy_protocol = other.proxy_protocol;
+            self.proxy_protocol_timeout = other.proxy_protocol_timeout;
+        };
+        self
+    }
+
+    /// Mount all routes of `router` below `prefix`.
+    ///
+    /// A route `/menu` in `router` becomes `{prefix}/menu`, and the route `/` becomes `prefix`
+    /// itself. `prefix` may contain `:capture` segments; their values are passed to the handlers
+    /// of `router` along with their own captures.
+    ///
+    /// If `router` has a [`fallback`](Self::fallback) set, it handles all requests below
+    /// `prefix` that no route matches, instead of the fallback of `self`.
+    /// [`layer`](Self::layer)s of `router` only apply to its routes (and its fallbacks, if
+    /// [`layer_fallback`](Self::layer_fallback) is set), while layers of `self` also apply to the
+    /// nested routes.
+    /// If `router` has a [state](Self::with_state), its routes and fallbacks get it instead of
+    /// the state of `self`.
+    /// Transcripts are recorded by the outermost router only; a recorder of `router` is ignored.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{command::{verbose::Verbose, AGICommand}, router::Router, serve};
+    /// # use blazing_agi_macros::create_handler;
+    /// #[create_handler]
+    /// async fn menu_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     // captures from the prefix are present as well
+    ///     let tenant = request.captures.get("tenant_id").expect("Please file an issue if this fails.");
+    ///     Ok(())
+    /// }
+    /// #[create_handler]
+    /// async fn unknown_tenant_route(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    ///
+    /// let tenant_router = Router::new()
+    ///     .route("/menu/:choice", menu_handler)
+    ///     .fallback(unknown_tenant_route);
+    /// let router = Router::new()
+    ///     .nest("/tenant/:tenant_id", tenant_router);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `prefix` does not start with `/`, or contains a wildcard.
+    /// Panics if a nested route conflicts with a route in `self` (see [`route`](Self::route)),
+    /// or if `self` already has a router with a fallback nested at the same prefix.
+    /// Panics if `router` has [`host`](Self::host) routers.
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn nest(mut self, prefix: &str, router: Router) -> Self {
+        assert!(prefix.starts_with('/'), "Prefix must start with a '/'");
+        assert!(
+            router.hosts.is_empty(),
+            "A router with host routers cannot be nested below a prefix"
+        );
+        let prefix = prefix.trim_end_matches('/');
+        let router = router.bake_layers().bake_state();
+        self.routes.graft(prefix, router.routes);
+        if let Some(fallback) = router.fallback {
+            self.routes.insert_fallback(prefix, fallback);
+        };
+        self
+    }
+
+    /// Apply the middleware stack to the routes (and fallbacks) directly, so they keep it when
+    /// combined with another router. Host routers get the stack outside of their own layers.
+    fn bake_layers(mut self) -> Self {
+        if self.layers.is_empty() {
+            return self;
+        };
+        let layers = std::mem::take(&mut self.layers);
+        let layer_fallback = self.layer_fallback;
+        self.hosts = std::mem::take(&mut self.hosts)
+            .into_iter()
+            .map(|(pattern, router)| {
+                let mut router = router.bake_all_layers();
+                // the default fallback of a host router is layered at dispatch as well
+                if layer_fallback && router.fallback.is_none() {
+                    router.fallback = Some(Arc::new(FallbackHandler {}));
+                };
+                (pattern, router.wrap_handlers(&layers, layer_fallback))
+            })
+            .collect();
+        self.wrap_handlers(&layers, layer_fallback)
+    }
+
+    /// Like [`bake_layers`](Self::bake_layers), for a router handling every request it gets on
+    /// its own: its default fallback is layered as well.
+    fn bake_all_layers(mut self) -> Self {
+        if self.layer_fallback && self.fallback.is_none() && !self.layers.is_empty() {
+            self.fallback = Some(Arc::new(FallbackHandler {}));
+        };
+        self.bake_layers()
+    }
+
+    /// Prepare the router for serving: Apply the middleware stack to every handler once, so it
+    /// does not have to be applied for each request.
+    pub(crate) fn finish(mut self) -> Self {
+        self.hosts = std::mem::take(&mut self.hosts)
+            .into_iter()
+            .map(|(pattern, router)| (pattern, router.finish()))
+            .collect();
+        self.bake_all_layers()
+    }
+
+    /// Wrap the routes (and fallbacks, if `layer_fallback` is set) in `layers`.
+    fn wrap_handlers(mut self, layers: &[StackLayer], layer_fallback: bool) -> Self {
+        let apply = |handler: Arc<dyn AGIHandler>| -> Arc<dyn AGIHandler> {
+            layers
+                .iter()
+                .fold(
+                    Box::new(handler) as Box<dyn AGIHandler>,
+                    |handler, layer| (layer.0)(handler),
+                )
+                .into()
+        };
+        self.routes = std::mem::take(&mut self.routes).map_values(apply);
+        if layer_fallback {
+            self.routes = std::mem::take(&mut self.routes).map_fallbacks(apply);
+            self.fallback = self.fallback.map(apply);
+        };
+        self
+    }
+
+    /// Pass the state to the routes and fallbacks directly, so they keep it when nested into
+    /// another router.
+    fn bake_state(mut self) -> Self {
+        let Some(state) = self.state.take() else {
+            return self;
+        };
+        let apply = |handler: Arc<dyn AGIHandler>| -> Arc<dyn AGIHandler> {
+            Arc::new(WithState {
+                state: state.clone(),
+                inner: handler,
+            })
+        };
+        self.routes = std::mem::take(&mut self.routes)
+            .map_values(apply)
+            .map_fallbacks(apply);
+        self.fallback = self.fallback.map(apply);
+        self
+    }
+
+    /// Take `state` as the state of `self`, if there is one.
+    ///
+    /// # Panics
+    /// Panics if both `self` and `state` are set.
+    fn take_state(&mut self, state: Option<AGIState>) {
+        if let Some(state) = state {
+            assert!(
+                self.state.is_none(),
+                "Both combined routers have a state. Set it on at most one of them."
+            );
+            self.state = Some(state);
+        };
+    }
+
+    /// Let `router` handle all requests whose url host matches `host`.
+    ///
+    /// `host` is a domain name like `tenant-a.agi.local` (compared ignoring case) or an IP
+    /// address. Labels of the form `:capture` match any single label and are collected into the
+    /// `captures` field of the [`AGIRequest`], unless a path capture has the same name.
+    /// Hosts without captures are tried first, then hosts with captures in the order they were
+    /// added. Requests for a matching host are handled by `router` alone, including its fallback;
+    /// requests matching no host are handled by the routes of `self`.
+    /// The host of every request is available in the `host` field of the [`AGIRequest`].
+    ///
+    /// [`layer`](Self::layer)s of `self` also apply to the routes of `router`, outside of the
+    /// layers of `router`.
+    /// Handlers of `router` get its [state](Self::with_state), or the state of `self` if
+    /// `router` has none.
+    /// Transcripts are recorded by the outermost router only; a recorder of `router` is ignored.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{command::{verbose::Verbose, AGICommand}, router::Router, serve};
+    /// # use blazing_agi_macros::create_handler;
+    /// #[create_handler]
+    /// async fn tenant_a_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    /// #[create_handler]
+    /// async fn tenant_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     let tenant = request.captures.get("tenant").expect("Please file an issue if this fails.");
+    ///     Ok(())
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .host("tenant-a.agi.local", Router::new().route("/ivr", tenant_a_handler))
+    ///     .host(":tenant.agi.local", Router::new().route("/ivr", tenant_handler));
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if `host` is empty, contains an empty label or an unnamed capture.
+    /// Panics if `self` already has a router for the same host pattern (up to the names of
+    /// captures).
+    /// Panics if `router` itself has host routers.
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn host(mut self, host: &str, router: Router) -> Self {
+        self.add_host(HostPattern::new(host), router);
         self
     }
 
+    /// Add `router` for the hosts matching `pattern`.
+    ///
+    /// # Panics
+    /// See [`host`](Self::host).
+    fn add_host(&mut self, pattern: HostPattern, router: Router) {
+        assert!(
+            router.hosts.is_empty(),
+            "The router for the host {pattern} must not have host routers itself"
+        );
+        if let Some((existing, _)) = self.hosts.iter().find(|(x, _)| x.conflicts_with(&pattern)) {
+            panic!("The host {pattern} conflicts with the host {existing}");
+        };
+        self.hosts.push((pattern, router));
+    }
+
     /// Set the fallback handler.
     /// This will be called if no route matches a request.
     ///
@@ -143,15 +571,146 @@ impl Router {
     ///     .fallback(bar_handler);
     /// ```
     #[must_use = "Run this router with blazing_agi::serve::serve"]
-    pub fn fallback<H>(mut self, handler: H) -> Self
+    pub fn fallback<H, M>(mut self, handler: H) -> Self
+    where
+        H: IntoAGIHandler<M>,
+    {
+        self.fallback = Some(handler.into_handler().into());
+        self
+    }
+
+    /// Record a transcript of every connection handled by this router to `sink`.
+    ///
+    /// Every message asterisk sends (including the initial request) and every command sent by a
+    /// handler is recorded. See [`transcript`](crate::transcript) for how to replay the
+    /// recordings.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{router::Router, transcript::DirectorySink};
+    /// let router = Router::new()
+    ///     .record(DirectorySink::new("/var/log/blazing_agi"));
+    /// ```
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn record<T>(mut self, sink: T) -> Self
+    where
+        T: TranscriptSink + 'static,
+    {
+        self.recorder = Some(Arc::new(sink));
+        self
+    }
+
+    /// Only accept connections from addresses `filter` allows.
+    ///
+    /// Connections from other addresses are closed before anything (except for a
+    /// [PROXY protocol](Self::proxy_protocol) header) is read from them. The filter
+    /// applies to the whole server, so it is ignored on routers that are
+    /// [`nest`](Self::nest)ed or used as a [`host`](Self::host). To filter only some routes, use
+    /// an [`IpFilterLayer`](crate::ip_filter::IpFilterLayer) instead.
+    ///
+    /// Sessions handled in-process have no peer address unless one is given, so a filter with
+    /// [`allow`](IpFilter::allow)ed networks drops them. Pass an address with
+    /// `AGITestClient::peer_addr` or `transcript::replay_from` when testing such a router.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{ip_filter::IpFilter, router::Router};
+    /// let router = Router::new()
+    ///     .ip_filter(IpFilter::new().allow("10.0.0.0/8").allow("::1"));
+    /// ```
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn ip_filter(mut self, filter: IpFilter) -> Self {
+        self.ip_filter = Some(filter);
+        self
+    }
+
+    /// Read a PROXY protocol header at the start of each connection.
+    ///
+    /// The original addresses sent by the proxy replace the addresses of the connection in the
+    /// [`AGIRequest`] and are checked by the [`ip_filter`](Self::ip_filter). See
+    /// [`proxy_protocol`](crate::proxy_protocol) for details. Like the `ip_filter`, this
+    /// applies to the whole server and is ignored on nested routers and host routers.
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{proxy_protocol::ProxyProtocol, router::Router};
+    /// let router = Router::new().proxy_protocol(ProxyProtocol::Required);
+    /// ```
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn proxy_protocol(mut self, mode: ProxyProtocol) -> Self {
+        self.proxy_protocol = mode;
+        self
+    }
+
+    /// Drop connections that do not send their PROXY protocol header within `timeout`
+    /// (default: 5 seconds), so clients sending nothing do not hold on to a connection.
+    ///
+    /// This only has an effect if a [`proxy_protocol`](Self::proxy_protocol) header is read.
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn proxy_protocol_timeout(mut self, timeout: Duration) -> Self {
+        self.proxy_protocol_timeout = timeout;
+        self
+    }
+
+    /// Pass `state` to all handlers of this router.
+    ///
+    /// Handler functions get it with the [`State`](crate::extract::State) extractor, handlers
+    /// created with `#[create_handler(state = S)]` as `state: Arc<S>` and other handlers with
+    /// [`AGIRequest::state_as`].
+    ///
+    /// Example:
+    /// ```
+    /// use blazing_agi::{connection::Connection, extract::State, router::Router, AGIError};
+    ///
+    /// struct Config {
+    ///     greeting: String,
+    /// }
+    ///
+    /// async fn greet(connection: &mut Connection, config: State<Config>) -> Result<(), AGIError> {
+    ///     println!("{}", config.greeting);
+    ///     Ok(())
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .route("/greet", greet)
+    ///     .with_state(Config { greeting: "Hello".to_owned() });
+    /// ```
+    ///
+    /// The same with a macro-generated handler:
+    /// ```
+    /// # use blazing_agi::{command::{verbose::Verbose, AGICommand}, router::Router};
+    /// # use blazing_agi_macros::create_handler;
+    ///
+    /// struct Config {
+    ///     greeting: String,
+    /// }
+    ///
+    /// #[create_handler(state = Config)]
+    /// async fn greet(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     connection.send_command(Verbose::new(state.greeting.clone())).await?;
+    ///     Ok(())
+    /// }
+    ///
+    /// let router = Router::new()
+    ///     .route("/greet", greet)
+    ///     .with_state(Config { greeting: "Hello".to_owned() });
+    /// ```
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn with_state<S>(mut self, state: S) -> Self
     where
-        H: AGIHandler + 'static,
+        S: Send + Sync + 'static,
     {
-        self.fallback = Box::new(handler);
+        self.state = Some(Arc::new(state));
         self
     }
 
-    /// Add a layer(middleware) to each route that currently exists.
+    /// Add a layer(middleware) to the middleware stack of this router.
+    ///
+    /// The stack is applied to the handler of every request this router dispatches, including
+    /// routes added after this call, conditional routes, and the routes of nested and host
+    /// routers. Layers added later on run first (they are the outermost ones). The fallback is only
+    /// covered if [`layer_fallback`](Self::layer_fallback) is set.
+    /// To apply a layer only to some routes, use [`route_layer`](Self::route_layer) instead.
     ///
     /// See `examples/layer-agi-digest.rs` for a real world example.
     /// Example:
@@ -171,93 +730,112 @@ impl Router {
     /// // For both paths, bar_handler is run first, then foo_handler if bar_handler succeeds.
     /// // The fallback is not affected.
     /// let some_router = Router::new()
+    ///     .layer(layer_before!(bar_handler))
     ///     .route("/some/path", foo_handler)
-    ///     .route("/some/other/path", foo_handler)
-    ///     .layer(layer_before!(bar_handler));
+    ///     .route("/some/other/path", foo_handler);
     /// ```
     #[must_use = "Run this router with blazing_agi::serve::serve"]
-    pub fn layer<L: Layer>(self, layer: L) -> Self {
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer + Send + Sync + 'static,
+    {
+        self.layers
+            .push(StackLayer(Box::new(move |handler| layer.layer(handler))));
+        self
+    }
+
+    /// Whether the middleware stack (see [`layer`](Self::layer)) also applies to the fallbacks of
+    /// this router and of routers nested into it. Defaults to `false`.
+    ///
+    /// Set this when a layer must see every request, e.g. for authentication.
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn layer_fallback(mut self, layered: bool) -> Self {
+        self.layer_fallback = layered;
+        self
+    }
+
+    /// Add a layer(middleware) to the routes that currently exist, and only to them.
+    ///
+    /// Routes added later on and fallbacks are never affected. To scope a layer to a few routes,
+    /// declare them in a [`group`](Self::group).
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn route_layer<L: Layer>(self, layer: L) -> Self {
         Router {
             routes: self
                 .routes
+                .map_values(|handler| Arc::from(layer.layer(handler))),
+            fallback: self.fallback,
+            layers: self.layers,
+            layer_fallback: self.layer_fallback,
+            hosts: self
+                .hosts
                 .into_iter()
-                .map(|(loc, handler)| {
-                    (
-                        loc.clone(),
-                        Box::new((layer.clone()).layer(handler)) as Box<dyn AGIHandler>,
-                    )
-                })
+                .map(|(pattern, router)| (pattern, router.route_layer(layer.clone())))
                 .collect(),
-            fallback: self.fallback,
+            state: self.state,
+            recorder: self.recorder,
+            ip_filter: self.ip_filter,
+            proxy_protocol: self.proxy_protocol,
+            proxy_protocol_timeout: self.proxy_protocol_timeout,
         }
     }
 
-    /// Find out, whether path defines a route that should handle url.
-    ///
-    /// path may contain captures and a trailing wildcard segment
-    ///
-    /// This function guarantees, that all defined captures have a value set in the returned
-    /// hashmap
-    #[cfg_attr(feature = "tracing", tracing::instrument(level=tracing::Level::TRACE,ret))]
-    fn path_matches(
-        path: &[String],
-        url: &Url,
-    ) -> Option<(HashMap<String, String>, Option<String>)> {
-        let mut idx_in_path = 0;
-        let mut captures = HashMap::<String, String>::new();
-        let mut wildcards = String::new();
-        let path_segs_opt = url.path_segments();
-        // early return for empty request path
-        if path_segs_opt.is_none() {
-            if path.is_empty() {
-                return Some((captures, None));
-            };
-            return None;
-        };
-        let mut path_segs = path_segs_opt.expect("is_none should have been handled earlier");
-        while let Some(segment_to_match) = path_segs.next() {
-            // capture: store the value
-            if path[idx_in_path].starts_with(':') {
-                let name = path[idx_in_path][1..].to_owned();
-                captures.insert(name.clone(), segment_to_match.to_owned());
-            // wildcard: match the rest of url and early return
-            } else if path[idx_in_path].starts_with('*') {
-                wildcards.push_str(segment_to_match);
-                for rem in path_segs {
-                    wildcards.push('/');
-                    wildcards.push_str(rem);
-                }
-                return Some((captures, Some(wildcards)));
-            // normal segment - simply continue iterating
-            } else if path[idx_in_path] != segment_to_match {
-                return None;
-            };
-            idx_in_path += 1;
-        }
-        // we have iterated through the entire url that got passed to us
-        // return success, if our predefined path is also exhausted
-        if idx_in_path == path.len() {
-            Some((captures, None))
-        } else {
-            None
-        }
+    /// Declare a group of routes in `f`.
+    ///
+    /// `f` gets an empty router and returns it with the routes of the group. Layers applied in
+    /// `f` only apply to the routes of the group. The returned router is then
+    /// [`merge`](Self::merge)d into `self`.
+    ///
+    /// # Panics
+    /// Panics in the same cases as [`merge`](Self::merge).
+    ///
+    /// Example:
+    /// ```
+    /// # use blazing_agi::{command::{verbose::Verbose, AGICommand}, router::Router, serve};
+    /// # use blazing_agi_macros::{create_handler, layer_before};
+    /// #[create_handler]
+    /// async fn public_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    ///
+    /// #[create_handler]
+    /// async fn secure_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    ///
+    /// #[create_handler]
+    /// async fn check_handler(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+    ///     Ok(())
+    /// }
+    ///
+    /// // check_handler only runs before the /secure routes
+    /// let router = Router::new()
+    ///     .route("/public", public_handler)
+    ///     .group(|group| {
+    ///         group
+    ///             .route("/secure/voicemail", secure_handler)
+    ///             .route("/secure/admin/*", secure_handler)
+    ///             .route_layer(layer_before!(check_handler))
+    ///     })
+    ///     .route("/public/other", public_handler);
+    /// ```
+    #[must_use = "Run this router with blazing_agi::serve::serve"]
+    pub fn group<F>(self, f: F) -> Self
+    where
+        F: FnOnce(Router) -> Router,
+    {
+        self.merge(f(Router::new()))
     }
 
-    /// Find the correct handler for a request.
+    /// Find the correct handler for a request, with the middleware stack applied.
+    /// A [`finish`](Self::finish)ed router has no stack left to apply.
     ///
     /// NOTE: it would be nice to remove this panic and bubble an error instead
     /// # Panics
     ///
     /// Panics if a non-FastAGI request is passed.
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self),level=tracing::Level::TRACE))]
-    fn route_request<'borrow>(
-        &'borrow self,
-        request: &AGIVariableDump,
-    ) -> (
-        &'borrow dyn AGIHandler,
-        HashMap<String, String>,
-        Option<String>,
-    ) {
+    fn route_request(&self, request: &AGIVariableDump) -> Dispatch {
         let url = match &request.request {
             agiparse::AGIRequestType::FastAGI(x) => x.clone(),
             agiparse::AGIRequestType::File(_) => {
@@ -271,22 +849,146 @@ impl Router {
                 panic!("Caller must ensure that only FastAGI requests get passed.")
             }
         };
-        for (idx, (path, _)) in self.routes.iter().enumerate() {
-            if let Some((captures, wildcards)) = Router::path_matches(path, &url) {
-                return (&self.routes[idx].1, captures, wildcards);
+        let mut dispatch = self.find_handler(&url, request);
+        if !dispatch.fallback || self.layer_fallback {
+            for layer in &self.layers {
+                dispatch.handler = (layer.0)(dispatch.handler);
             }
+        };
+        dispatch
+    }
+
+    /// Find the correct handler for a request to `url`, with only the middleware stacks of host
+    /// routers applied.
+    fn find_handler(&self, url: &url::Url, request: &AGIVariableDump) -> Dispatch {
+        if let Some((router, host_captures)) = self.host_router(url) {
+            let mut dispatch = router.route_request(request);
+            for (name, value) in host_captures {
+                dispatch.captures.entry(name).or_insert(value);
+            }
+            return dispatch;
+        };
+        // a url without a path has no segments, not a single empty one
+        // segments are decoded after splitting, so an encoded / does not start a new segment
+        let decoded = url
+            .path_segments()
+            .map(|segments| {
+                segments
+                    .map(|x| percent_decode_str(x).decode_utf8_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let segments = decoded.iter().map(String::as_str).collect::<Vec<_>>();
+        if let Some(found) = self.routes.lookup(&segments) {
+            return Dispatch {
+                handler: Box::new(found.value.clone()),
+                captures: found.captures,
+                wildcards: found.wildcards,
+                route: Some(found.pattern.to_owned()),
+                fallback: false,
+            };
+        };
+        // nothing found. return a conditional route or the fallback of the innermost nested router
+        if let Some(found) = self.routes.lookup_fallback(&segments, request) {
+            return Dispatch {
+                handler: Box::new(found.value.clone()),
+                captures: found.captures,
+                wildcards: None,
+                // conditional routes of the root router have the empty prefix
+                route: (!found.fallback).then(|| match found.prefix {
+                    "" => "/".to_owned(),
+                    prefix => prefix.to_owned(),
+                }),
+                fallback: found.fallback,
+            };
+        };
+        // or our own fallback handler
+        Dispatch {
+            handler: match &self.fallback {
+                Some(fallback) => Box::new(fallback.clone()),
+                None => Box::new(FallbackHandler {}),
+            },
+            captures: HashMap::new(),
+            wildcards: None,
+            route: None,
+            fallback: true,
         }
-        // nothing found. return the fallback handler
-        (&self.fallback, HashMap::<String, String>::new(), None)
+    }
+
+    /// Find the router for the host of `url`, with the captured host labels.
+    fn host_router(&self, url: &url::Url) -> Option<(&Router, HashMap<String, String>)> {
+        let host = url.host_str()?;
+        // hosts without captures win
+        let mut hosts = self
+            .hosts
+            .iter()
+            .filter(|(x, _)| !x.has_captures())
+            .chain(self.hosts.iter().filter(|(x, _)| x.has_captures()));
+        hosts.find_map(|(pattern, router)| Some((router, pattern.matches(host)?)))
+    }
+
+    /// Find the state for a request to `url`.
+    fn state_for(&self, url: &url::Url) -> Option<AGIState> {
+        self.host_router(url)
+            .and_then(|(router, _)| router.state.clone())
+            .or_else(|| self.state.clone())
     }
 
     /// Handle a Request.
     /// Note that differently from HTTP, a request really is an incoming stream.
     /// This function removes the protocol start from the stream, extracts some parameters
     /// and then tries to call the correct handler.
+    #[cfg(test)]
     #[cfg_attr(feature = "tracing", tracing::instrument(skip(self),level=tracing::Level::TRACE))]
-    pub(crate) async fn handle(&self, stream: TcpStream) {
-        let mut conn = Connection::new(stream);
+    pub(crate) async fn handle<S: AGIStream + 'static>(&self, stream: S) {
+        self.handle_from(stream, None, None).await;
+    }
+
+    /// Handle a Request from `peer_addr`, received on `local_addr`.
+    ///
+    /// If a [`proxy_protocol`](Self::proxy_protocol) header is expected, it is read first and
+    /// the addresses in it replace `peer_addr` and `local_addr`.
+    /// Connections the [`ip_filter`](Self::ip_filter) does not allow are dropped right away.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self, stream),level=tracing::Level::TRACE))]
+    pub(crate) async fn handle_from<S: AGIStream + 'static>(
+        &self,
+        mut stream: S,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) {
+        let (prefix, peer_addr, local_addr) = if self.proxy_protocol == ProxyProtocol::Disabled {
+            (vec![], peer_addr, local_addr)
+        } else {
+            match proxy_protocol::read_header(
+                &mut stream,
+                self.proxy_protocol,
+                self.proxy_protocol_timeout,
+            )
+            .await
+            {
+                Ok((Some(header), rest)) if header.source.is_some() => {
+                    (rest, header.source, header.destination)
+                }
+                Ok((_, rest)) => (rest, peer_addr, local_addr),
+                #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+                Err(e) => {
+                    #[cfg(feature = "tracing")]
+                    info!("Dropped a connection from {peer_addr:?}: {e}");
+                    return;
+                }
+            }
+        };
+        if let Some(filter) = &self.ip_filter {
+            if !filter.allows_peer(peer_addr) {
+                #[cfg(feature = "tracing")]
+                info!("Dropped a connection from {peer_addr:?}, which is not allowed.");
+                return;
+            };
+        };
+        let mut conn = Connection::new(proxy_protocol::Rewind::new(prefix, stream));
+        if let Some(sink) = &self.recorder {
+            conn.record_to(sink.clone());
+        };
 
         // the first packet has to be agi_network: yes
         match conn.read_one_message().await {
@@ -309,14 +1011,27 @@ impl Router {
         match conn.read_one_message().await {
             Err(_) => {}
             Ok(AGIMessage::VariableDump(request_data)) => {
-                if let AGIRequestType::FastAGI(_) = request_data.request {
+                if let AGIRequestType::FastAGI(url) = &request_data.request {
+                    let state = self.state_for(url);
                     // find the handler responsible
-                    let (handler, captures, wildcards) = self.route_request(&request_data);
+                    let Dispatch {
+                        handler,
+                        captures,
+                        wildcards,
+                        route,
+                        ..
+                    } = self.route_request(&request_data);
                     // create the agirequest item and call the handler
                     let full_request = AGIRequest {
+                        host: request_data.request.host(),
+                        query: request_data.request.query(),
                         variables: *request_data,
                         captures,
                         wildcards,
+                        state,
+                        peer_addr,
+                        local_addr,
+                        route,
                     };
                     let handle_response = handler.handle(&mut conn, &full_request).await;
                     match handle_response {
@@ -357,105 +1072,862 @@ impl Router {
 
 #[cfg(test)]
 mod test {
+    use url::Url;
+
     use super::*;
 
+    #[derive(Debug)]
+    struct Named(&'static str);
+    #[async_trait::async_trait]
+    impl AGIHandler for Named {
+        async fn handle(&self, conn: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            conn.send_command(crate::command::Verbose::new(self.0.to_owned()))
+                .await?;
+            Ok(())
+        }
+    }
+
+    /// The name of the handler `router` chooses for `url`, with its captures and wildcards.
+    fn route(router: &Router, url: &str) -> (String, HashMap<String, String>, Option<String>) {
+        let request = AGIVariableDump::builder(Url::parse(url).unwrap()).build();
+        let dispatch = router.route_request(&request);
+        (
+            format!("{:?}", dispatch.handler),
+            dispatch.captures,
+            dispatch.wildcards,
+        )
+    }
+
+    #[test]
+    fn dispatch_reports_the_route() {
+        let nested = Router::new()
+            .route("/user/:id/:lang?", Named("user"))
+            .route_when(|req: &AGIVariableDump| req.callerid == "42", Named("when"))
+            .fallback(Named("fallback"));
+        let router = Router::new().nest("/api", nested);
+        let route_of = |url: &str| {
+            let request = AGIVariableDump::builder(Url::parse(url).unwrap())
+                .callerid("42")
+                .build();
+            router.route_request(&request).route
+        };
+        assert_eq!(
+            route_of("agi://host/api/user/7").as_deref(),
+            Some("/api/user/:id/:lang?")
+        );
+        assert_eq!(route_of("agi://host/api/other").as_deref(), Some("/api"));
+        let router = Router::new()
+            .route_when(|_: &AGIVariableDump| true, Named("when"))
+            .route("/x", Named("x"));
+        let request = AGIVariableDump::builder(Url::parse("agi://host/y").unwrap()).build();
+        assert_eq!(router.route_request(&request).route.as_deref(), Some("/"));
+        let router = Router::new().fallback(Named("fallback"));
+        assert_eq!(router.route_request(&request).route, None);
+    }
+
+    #[test]
+    fn route_by_priority() {
+        let router = Router::new()
+            .route("/users/*", Named("wildcard"))
+            .route("/users/:id", Named("capture"))
+            .route("/users/admin", Named("static"));
+        assert_eq!(
+            route(&router, "agi://host/users/admin").0,
+            "Named(\"static\")"
+        );
+        let (name, captures, _) = route(&router, "agi://host/users/bob");
+        assert_eq!(name, "Named(\"capture\")");
+        assert_eq!(captures.get("id"), Some(&"bob".to_owned()));
+        let (name, _, wildcards) = route(&router, "agi://host/users/bob/voicemail");
+        assert_eq!(name, "Named(\"wildcard\")");
+        assert_eq!(wildcards, Some("bob/voicemail".to_owned()));
+    }
+
+    #[test]
+    fn route_to_fallback() {
+        let router = Router::new()
+            .route("/some/path", Named("some"))
+            .fallback(Named("fallback"));
+        assert_eq!(route(&router, "agi://host/other").0, "Named(\"fallback\")");
+        // no path at all
+        assert_eq!(route(&router, "agi://host:4573").0, "Named(\"fallback\")");
+    }
+
+    #[test]
+    fn merged_routes_are_found() {
+        let router = Router::new()
+            .route("/a", Named("a"))
+            .merge(Router::new().route("/b/:x", Named("b")));
+        assert_eq!(route(&router, "agi://host/a").0, "Named(\"a\")");
+        assert_eq!(route(&router, "agi://host/b/1").0, "Named(\"b\")");
+    }
+
+    #[test]
+    #[should_panic(expected = "The route /a/:y conflicts with the route /a/:y")]
+    fn conflicting_routes_panic() {
+        let _ = Router::new()
+            .route("/a/:y", Named("first"))
+            .merge(Router::new().route("/a/:y", Named("second")));
+    }
+
+    #[test]
+    fn merge_keeps_the_only_fallback() {
+        let router = Router::new()
+            .route("/a", Named("a"))
+            .merge(Router::new().fallback(Named("fallback")));
+        assert_eq!(route(&router, "agi://host/b").0, "Named(\"fallback\")");
+    }
+
+    #[test]
+    #[should_panic(expected = "Both merged routers have a fallback")]
+    fn merging_two_fallbacks_panics() {
+        let _ = Router::new()
+            .fallback(Named("first"))
+            .merge(Router::new().fallback(Named("second")));
+    }
+
+    #[test]
+    fn nested_routes_and_fallback() {
+        let tenant = Router::new()
+            .route("/", Named("tenant root"))
+            .route("/menu/:choice", Named("menu"))
+            .fallback(Named("tenant fallback"));
+        let router = Router::new()
+            .route("/tenant/admin", Named("admin"))
+            .nest("/tenant/:tenant_id/", tenant)
+            .fallback(Named("fallback"));
+        let (name, captures, _) = route(&router, "agi://host/tenant/7/menu/2");
+        assert_eq!(name, "Named(\"menu\")");
+        assert_eq!(captures.get("tenant_id"), Some(&"7".to_owned()));
+        assert_eq!(captures.get("choice"), Some(&"2".to_owned()));
+        assert_eq!(
+            route(&router, "agi://host/tenant/7").0,
+            "Named(\"tenant root\")"
+        );
+        let (name, captures, _) = route(&router, "agi://host/tenant/7/unknown");
+        assert_eq!(name, "Named(\"tenant fallback\")");
+        assert_eq!(captures.get("tenant_id"), Some(&"7".to_owned()));
+        assert_eq!(
+            route(&router, "agi://host/tenant/admin").0,
+            "Named(\"admin\")"
+        );
+        assert_eq!(route(&router, "agi://host/tenant").0, "Named(\"fallback\")");
+        assert_eq!(route(&router, "agi://host/other").0, "Named(\"fallback\")");
+    }
+
+    #[test]
+    fn conditional_routes() {
+        let router = Router::new()
+            .route("/path", Named("path"))
+            .route_when(
+                |req: &AGIVariableDump| req.context == "from-pstn",
+                Named("pstn"),
+            )
+            .route_extension("_NXXNXXXXXX", Named("national"))
+            .nest(
+                "/tenant",
+                Router::new()
+                    .route_when(|_: &AGIVariableDump| true, Named("tenant"))
+                    .fallback(Named("tenant fallback")),
+            );
+        let mut request = AGIVariableDump::builder(Url::parse("agi://host/path").unwrap()).build();
+        request.context = "from-pstn".to_owned();
+        // the path wins
+        assert_eq!(
+            format!("{:?}", router.route_request(&request).handler),
+            "Named(\"path\")"
+        );
+        request.request = AGIRequestType::FastAGI(Url::parse("agi://host/other").unwrap());
+        assert_eq!(
+            format!("{:?}", router.route_request(&request).handler),
+            "Named(\"pstn\")"
+        );
+        request.context = "default".to_owned();
+        request.extension = "2125551234".to_owned();
+        assert_eq!(
+            format!("{:?}", router.route_request(&request).handler),
+            "Named(\"national\")"
+        );
+        request.extension = "100".to_owned();
+        assert_eq!(
+            format!("{:?}", router.route_request(&request).handler),
+            "FallbackHandler"
+        );
+        // conditional routes of nested routers stay below their prefix
+        request.request = AGIRequestType::FastAGI(Url::parse("agi://host/tenant/x").unwrap());
+        assert_eq!(
+            format!("{:?}", router.route_request(&request).handler),
+            "Named(\"tenant\")"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "contains an unclosed [")]
+    fn invalid_extension_pattern_panics() {
+        let _ = Router::new().route_extension("_[12", Named("x"));
+    }
+
+    #[test]
+    fn captures_and_wildcards_are_decoded() {
+        let router = Router::new()
+            .route("/hello world/:name", Named("static"))
+            .route("/files/*", Named("wildcard"));
+        let (name, captures, _) = route(&router, "agi://host/hello%20world/J%C3%BCrgen%2FB?x=1");
+        assert_eq!(name, "Named(\"static\")");
+        assert_eq!(captures.get("name"), Some(&"Jürgen/B".to_owned()));
+        let (_, _, wildcards) = route(&router, "agi://host/files/a%20b/c");
+        assert_eq!(wildcards, Some("a b/c".to_owned()));
+    }
+
     #[test]
-    fn path_matches_simple() {
-        let input_url = Url::parse("agi://some.host:4573/some/route").unwrap();
-        let known_path = vec!["some".to_owned(), "route".to_owned()];
+    fn typed_captures() {
+        let router = Router::new()
+            .route("/queue/:id<u32>", Named("queue"))
+            .route("/queue/:name<[a-z]+>", Named("named queue"))
+            .route("/menu/:choice<u8>?", Named("menu"));
+        let request = AGIVariableDump::builder(Url::parse("agi://host/queue/42").unwrap()).build();
+        let Dispatch {
+            handler,
+            captures,
+            wildcards,
+            ..
+        } = router.route_request(&request);
+        assert_eq!(format!("{handler:?}"), "Named(\"queue\")");
+        let request = AGIRequest {
+            variables: request,
+            captures,
+            wildcards,
+            host: None,
+            query: HashMap::new(),
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        };
+        assert_eq!(request.capture::<u32>("id"), Some(42));
+        assert_eq!(request.capture::<u32>("other"), None);
+        assert_eq!(
+            route(&router, "agi://host/queue/sales").0,
+            "Named(\"named queue\")"
+        );
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((HashMap::<String, String>::new(), None))
+            route(&router, "agi://host/queue/Sales").0,
+            "FallbackHandler"
         );
+        assert_eq!(route(&router, "agi://host/menu").0, "Named(\"menu\")");
+        assert_eq!(route(&router, "agi://host/menu/3").0, "Named(\"menu\")");
+        assert_eq!(route(&router, "agi://host/menu/300").0, "FallbackHandler");
     }
 
     #[test]
-    fn path_matches_wildcards() {
-        let input_url = Url::parse("agi://some.host:4573/some/route/appended/wildcard").unwrap();
-        let known_path = vec![
-            "some".to_owned(),
-            "route".to_owned(),
-            "*irrelevant".to_owned(),
-        ];
+    fn route_by_host() {
+        let router = Router::new()
+            .route("/ivr", Named("default"))
+            .host(
+                ":tenant.agi.local",
+                Router::new().route("/ivr/:tenant", Named("captured")),
+            )
+            .host(
+                "tenant-a.agi.local",
+                Router::new()
+                    .route("/ivr", Named("tenant a"))
+                    .fallback(Named("tenant a fallback")),
+            );
+        assert_eq!(
+            route(&router, "agi://Tenant-A.agi.local:4573/ivr").0,
+            "Named(\"tenant a\")"
+        );
+        assert_eq!(
+            route(&router, "agi://tenant-a.agi.local/other").0,
+            "Named(\"tenant a fallback\")"
+        );
+        let (name, captures, _) = route(&router, "agi://tenant-b.agi.local/ivr/path");
+        assert_eq!(name, "Named(\"captured\")");
+        // the path capture wins over the host capture
+        assert_eq!(captures.get("tenant"), Some(&"path".to_owned()));
+        let (name, _, _) = route(&router, "agi://tenant-b.agi.local/ivr");
+        assert_eq!(name, "FallbackHandler");
+        assert_eq!(
+            route(&router, "agi://127.0.0.1/ivr").0,
+            "Named(\"default\")"
+        );
+    }
+
+    #[test]
+    fn host_captures_are_collected() {
+        let router = Router::new().host(
+            ":tenant.:cluster.local",
+            Router::new().route("/ivr", Named("ivr")),
+        );
+        let (_, captures, _) = route(&router, "agi://a.b.local/ivr");
+        assert_eq!(captures.get("tenant"), Some(&"a".to_owned()));
+        assert_eq!(captures.get("cluster"), Some(&"b".to_owned()));
+    }
+
+    /// The commands `router` sends when handling a request for `url`, answering each with 200.
+    async fn commands_for(router: impl Into<Arc<Router>>, url: &str) -> Vec<String> {
+        let router = router.into();
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { router.handle(server_side).await });
+        let request = AGIVariableDump::builder(Url::parse(url).unwrap())
+            .callerid("+4930123456")
+            .build();
+        let session = crate::client::AGIClientSession::start(client_side, &request)
+            .await
+            .unwrap();
+        let mut commands = vec![];
+        session
+            .run(|command| {
+                commands.push(command);
+                async { AGIStatusGeneric::Ok("1".to_owned(), None) }
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+        commands
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Queue {
+        id: u32,
+    }
+
+    async fn queue_handler(
+        connection: &mut Connection,
+        crate::extract::Captures(queue): crate::extract::Captures<Queue>,
+        crate::extract::CallerId(caller): crate::extract::CallerId,
+        prefix: crate::extract::State<String>,
+    ) -> Result<(), AGIError> {
+        connection
+            .send_command(crate::command::Verbose::new(format!(
+                "{} {caller} {}",
+                *prefix, queue.id
+            )))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn handler_functions_with_extractors_and_state() {
+        let router = Router::new()
+            .route("/queue/:id", queue_handler)
+            .with_state("queue".to_owned());
+        assert_eq!(
+            commands_for(router, "agi://host/queue/7").await,
+            vec!["VERBOSE \"queue +4930123456 7\""]
+        );
+    }
+
+    #[tokio::test]
+    async fn host_routers_have_their_own_state() {
+        let router = Router::new()
+            .host(
+                "a.local",
+                Router::new()
+                    .route("/queue/:id", queue_handler)
+                    .with_state("a".to_owned()),
+            )
+            .host("b.local", Router::new().route("/queue/:id", queue_handler))
+            .with_state("outer".to_owned());
+        assert_eq!(
+            commands_for(router, "agi://a.local/queue/1").await,
+            vec!["VERBOSE \"a +4930123456 1\""]
+        );
+        let router = Router::new()
+            .host("b.local", Router::new().route("/queue/:id", queue_handler))
+            .with_state("outer".to_owned());
+        assert_eq!(
+            commands_for(router, "agi://b.local/queue/1").await,
+            vec!["VERBOSE \"outer +4930123456 1\""]
+        );
+    }
+
+    #[derive(Debug)]
+    struct StateAsHandler;
+    #[async_trait::async_trait]
+    impl AGIHandler for StateAsHandler {
+        async fn handle(
+            &self,
+            connection: &mut Connection,
+            request: &AGIRequest,
+        ) -> Result<(), AGIError> {
+            assert!(request.state_as::<u8>().is_none());
+            let prefix = request.state_as::<String>().expect("state is a String");
+            connection
+                .send_command(crate::command::Verbose::new(prefix.to_string()))
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_handlers_get_the_state() {
+        let router = Router::new()
+            .route("/", StateAsHandler)
+            .with_state("state".to_owned());
+        assert_eq!(
+            commands_for(router, "agi://host/").await,
+            vec!["VERBOSE \"state\""]
+        );
+    }
+
+    async fn plain_request_fn(
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        connection
+            .send_command(crate::command::Verbose::new(request.captures["id"].clone()))
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_fns_and_closures_are_handlers() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let router = Router::new().route("/plain/:id", plain_request_fn).route(
+            "/closure",
+            crate::handler::handler_fn(move |connection, request| {
+                let calls = counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+                Box::pin(async move {
+                    connection
+                        .send_command(crate::command::Verbose::new(format!(
+                            "{calls} {}",
+                            request.variables.callerid
+                        )))
+                        .await?;
+                    Ok(())
+                })
+            }),
+        );
+        let router = Arc::new(router);
+        assert_eq!(
+            commands_for(router.clone(), "agi://host/plain/7").await,
+            vec!["VERBOSE \"7\""]
+        );
+        assert_eq!(
+            commands_for(router.clone(), "agi://host/closure").await,
+            vec!["VERBOSE \"1 +4930123456\""]
+        );
+        assert_eq!(
+            commands_for(router, "agi://host/closure").await,
+            vec!["VERBOSE \"2 +4930123456\""]
+        );
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn failed_extraction_skips_the_handler() {
+        let router = Router::new()
+            .route("/queue/:id", queue_handler)
+            .with_state("queue".to_owned());
+        assert!(commands_for(router, "agi://host/queue/x").await.is_empty());
+        // no state
+        let router = Router::new().route("/queue/:id", queue_handler);
+        assert!(commands_for(router, "agi://host/queue/1").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejections_are_configurable() {
+        let handler = crate::extract::handler(queue_handler)
+            .on_rejection(|e| AGIError::ClientSideError(format!("custom: {e}")));
+        let (_, server_side) = tokio::io::duplex(4096);
+        let request = AGIRequest {
+            variables: AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+            captures: HashMap::new(),
+            wildcards: None,
+            host: None,
+            query: HashMap::new(),
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        };
+        let result = handler
+            .handle(&mut Connection::new(server_side), &request)
+            .await;
+        assert!(matches!(result, Err(AGIError::ClientSideError(x)) if x.starts_with("custom: ")));
+    }
+
+    #[tokio::test]
+    async fn nested_routers_keep_their_state() {
+        let router = Router::new()
+            .route("/queue/:id", queue_handler)
+            .nest(
+                "/a",
+                Router::new()
+                    .route("/queue/:id", queue_handler)
+                    .with_state("a".to_owned()),
+            )
+            .nest(
+                "/b",
+                Router::new()
+                    .route("/queue/:id", queue_handler)
+                    .fallback(StateAsHandler)
+                    .with_state("b".to_owned()),
+            )
+            .with_state("outer".to_owned());
+        let router = Arc::new(router);
+        assert_eq!(
+            commands_for(router.clone(), "agi://host/a/queue/1").await,
+            vec!["VERBOSE \"a +4930123456 1\""]
+        );
+        assert_eq!(
+            commands_for(router.clone(), "agi://host/b/queue/2").await,
+            vec!["VERBOSE \"b +4930123456 2\""]
+        );
+        assert_eq!(
+            commands_for(router.clone(), "agi://host/b/unknown").await,
+            vec!["VERBOSE \"b\""]
+        );
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((
-                HashMap::<String, String>::new(),
-                Some("appended/wildcard".to_owned())
-            ))
+            commands_for(router, "agi://host/queue/3").await,
+            vec!["VERBOSE \"outer +4930123456 3\""]
         );
     }
 
     #[test]
-    fn path_matches_empty_wildcard() {
-        let input_url = Url::parse("agi://some.host:4573/some/route").unwrap();
-        let known_path = vec!["some".to_owned(), "route".to_owned(), "*".to_owned()];
-        assert_eq!(Router::path_matches(&known_path, &input_url), None);
+    #[should_panic(expected = "Both combined routers have a state")]
+    fn merging_two_states_panics() {
+        let _ = Router::new()
+            .with_state(1_u8)
+            .merge(Router::new().with_state(2_u8));
     }
 
     #[test]
-    fn path_matches_trivial_wildcard() {
-        let input_url = Url::parse("agi://some.host:4573/some/route/").unwrap();
-        let known_path = vec!["some".to_owned(), "route".to_owned(), "*".to_owned()];
+    #[should_panic(expected = "The host :b.local conflicts with the host :a.local")]
+    fn conflicting_hosts_panic() {
+        let _ = Router::new()
+            .host(":a.local", Router::new())
+            .merge(Router::new().host(":b.local", Router::new()));
+    }
+
+    /// Replaces every handler it is applied to with `Named`.
+    #[derive(Clone)]
+    struct Rename(&'static str);
+    impl Layer for Rename {
+        fn layer<H: AGIHandler + 'static>(&self, _handler: H) -> Box<dyn AGIHandler> {
+            Box::new(Named(self.0))
+        }
+    }
+
+    #[test]
+    fn layers_on_nested_routers_stay_scoped() {
+        let router = Router::new().route("/outer", Named("outer")).nest(
+            "/inner",
+            Router::new()
+                .route("/x", Named("x"))
+                .layer(Rename("inner layer")),
+        );
+        assert_eq!(route(&router, "agi://host/outer").0, "Named(\"outer\")");
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((HashMap::<String, String>::new(), Some("".to_owned())))
+            route(&router, "agi://host/inner/x").0,
+            "Named(\"inner layer\")"
+        );
+        let router = router.layer(Rename("outer layer"));
+        assert_eq!(
+            route(&router, "agi://host/inner/x").0,
+            "Named(\"outer layer\")"
         );
     }
 
     #[test]
-    fn path_matches_captures() {
-        let input_url = Url::parse("agi://some.host:4573/scripts/the_script").unwrap();
-        let known_path = vec!["scripts".to_owned(), ":name".to_owned()];
-        let mut expect_captures = HashMap::<String, String>::new();
-        expect_captures.insert("name".to_owned(), "the_script".to_owned());
+    fn group_layers_stay_in_the_group() {
+        let router = Router::new()
+            .route("/public", Named("public"))
+            .group(|group| {
+                group
+                    .route("/secure/:id", Named("secure"))
+                    .route_layer(Rename("checked"))
+            })
+            .route("/later", Named("later"))
+            .fallback(Named("fallback"));
+        assert_eq!(route(&router, "agi://host/public").0, "Named(\"public\")");
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((expect_captures, None))
+            route(&router, "agi://host/secure/1").0,
+            "Named(\"checked\")"
         );
+        assert_eq!(route(&router, "agi://host/later").0, "Named(\"later\")");
+        assert_eq!(route(&router, "agi://host/x").0, "Named(\"fallback\")");
     }
 
     #[test]
-    fn path_matches_captures_and_wildcard() {
-        let input_url = Url::parse("agi://some.host:4573/scripts/the_script/additionals").unwrap();
-        let known_path = vec![":directory".to_owned(), ":name".to_owned(), "*".to_owned()];
-        let mut expect_captures = HashMap::<String, String>::new();
-        expect_captures.insert("directory".to_owned(), "scripts".to_owned());
-        expect_captures.insert("name".to_owned(), "the_script".to_owned());
+    fn route_layers_skip_later_routes_and_fallbacks() {
+        let router = Router::new()
+            .route("/a", Named("a"))
+            .fallback(Named("fallback"))
+            .route_layer(Rename("layered"))
+            .route("/b", Named("b"));
+        assert_eq!(route(&router, "agi://host/a").0, "Named(\"layered\")");
+        assert_eq!(route(&router, "agi://host/b").0, "Named(\"b\")");
+        assert_eq!(route(&router, "agi://host/x").0, "Named(\"fallback\")");
+    }
+
+    #[test]
+    fn layer_stack_covers_later_routes() {
+        let router = Router::new()
+            .layer(Rename("inner"))
+            .route("/a", Named("a"))
+            .layer(Rename("outer"))
+            .route_when(|_| true, Named("conditional"))
+            .host("b.local", Router::new().route("/b", Named("b")))
+            .fallback(Named("fallback"));
+        assert_eq!(route(&router, "agi://host/a").0, "Named(\"outer\")");
+        assert_eq!(route(&router, "agi://host/x").0, "Named(\"outer\")");
+        assert_eq!(route(&router, "agi://b.local/b").0, "Named(\"outer\")");
+        // without the outer layer, the inner one is visible
+        let router = Router::new()
+            .layer(Rename("inner"))
+            .route("/a", Named("a"))
+            .route_layer(Rename("route layer"));
+        assert_eq!(route(&router, "agi://host/a").0, "Named(\"inner\")");
+    }
+
+    #[test]
+    fn layer_stack_covers_fallbacks_on_request() {
+        let router = Router::new()
+            .layer(Rename("layered"))
+            .nest("/menu", Router::new().fallback(Named("menu fallback")));
+        assert_eq!(route(&router, "agi://host/x").0, "FallbackHandler");
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((expect_captures, Some("additionals".to_owned())))
+            route(&router, "agi://host/menu/x").0,
+            "Named(\"menu fallback\")"
         );
+        let router = router.layer_fallback(true);
+        assert_eq!(route(&router, "agi://host/x").0, "Named(\"layered\")");
+        assert_eq!(route(&router, "agi://host/menu/x").0, "Named(\"layered\")");
+    }
+
+    /// Wraps every handler it is applied to, keeping it visible in the `Debug` output.
+    #[derive(Clone)]
+    struct Wrap(&'static str);
+    impl Layer for Wrap {
+        fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+            Box::new(Wrapped(self.0, Box::new(handler)))
+        }
+    }
+    struct Wrapped(&'static str, Box<dyn AGIHandler>);
+    impl core::fmt::Debug for Wrapped {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "{}({:?})", self.0, self.1)
+        }
+    }
+    #[async_trait::async_trait]
+    impl AGIHandler for Wrapped {
+        async fn handle(&self, conn: &mut Connection, req: &AGIRequest) -> Result<(), AGIError> {
+            self.1.handle(conn, req).await
+        }
     }
 
     #[test]
-    fn path_matches_trivial_path_segments() {
-        let input_url = Url::parse("agi://some.host:4573/scripts//").unwrap();
-        let known_path = vec![":directory".to_owned(), ":name".to_owned(), "".to_owned()];
-        let mut expect_captures = HashMap::<String, String>::new();
-        expect_captures.insert("directory".to_owned(), "scripts".to_owned());
-        expect_captures.insert("name".to_owned(), "".to_owned());
+    fn layer_stacks_of_merged_host_routers_are_kept() {
+        let router = Router::new().merge(
+            Router::new()
+                .host(
+                    "a.local",
+                    Router::new().route("/a", Named("a")).layer(Wrap("own")),
+                )
+                .layer(Wrap("auth")),
+        );
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((expect_captures, None))
+            route(&router, "agi://a.local/a").0,
+            "auth(own(Named(\"a\")))"
         );
+        assert_eq!(route(&router, "agi://a.local/x").0, "FallbackHandler");
+
+        let router = Router::new().group(|group| {
+            group
+                .host("a.local", Router::new().route("/a", Named("a")))
+                .layer(Wrap("auth"))
+                .layer_fallback(true)
+        });
+        assert_eq!(route(&router, "agi://a.local/a").0, "auth(Named(\"a\"))");
+        assert_eq!(route(&router, "agi://a.local/x").0, "auth(FallbackHandler)");
+        assert_eq!(route(&router, "agi://other.local/x").0, "FallbackHandler");
     }
 
     #[test]
-    fn path_matches_empty_path() {
-        let input_url = Url::parse("agi://some.host:4573").unwrap();
-        let known_path = vec![];
-        let expect_captures = HashMap::<String, String>::new();
+    fn finished_routers_dispatch_the_same() {
+        let router = || {
+            Router::new()
+                .route("/a", Named("a"))
+                .route_when(|req: &AGIVariableDump| req.callerid == "42", Named("when"))
+                .nest(
+                    "/nested",
+                    Router::new().route("/b", Named("b")).layer(Wrap("nested")),
+                )
+                .host(
+                    "a.local",
+                    Router::new()
+                        .route("/a", Named("a"))
+                        .layer(Wrap("own"))
+                        .layer_fallback(true),
+                )
+                .host("b.local", Router::new().route("/b", Named("b")))
+                .layer(Wrap("outer"))
+                .layer_fallback(true)
+        };
+        let finished = router().finish();
+        assert!(finished.layers.is_empty());
+        assert!(finished
+            .hosts
+            .iter()
+            .all(|(_, host)| host.layers.is_empty()));
+        let router = router();
+        for url in [
+            "agi://host/a",
+            "agi://host/nested/b",
+            "agi://host/x",
+            "agi://a.local/a",
+            "agi://a.local/x",
+            "agi://b.local/b",
+            "agi://b.local/x",
+        ] {
+            let request = AGIVariableDump::builder(Url::parse(url).unwrap())
+                .callerid("42")
+                .build();
+            assert_eq!(
+                format!("{:?}", router.route_request(&request).handler),
+                format!("{:?}", finished.route_request(&request).handler),
+                "{url}"
+            );
+        }
         assert_eq!(
-            Router::path_matches(&known_path, &input_url),
-            Some((expect_captures, None))
+            route(&finished, "agi://a.local/x").0,
+            "outer(own(FallbackHandler))"
         );
     }
 
     #[test]
-    fn path_matches_no_match() {
-        let input_url = Url::parse("agi://some.host:4573/some/path").unwrap();
-        let known_path = vec!["other_path".to_owned()];
-        assert_eq!(Router::path_matches(&known_path, &input_url), None);
+    fn layer_stacks_of_combined_routers_are_kept() {
+        let router = Router::new()
+            .route("/a", Named("a"))
+            .nest(
+                "/nested",
+                Router::new()
+                    .route("/b", Named("b"))
+                    .fallback(Named("nested fallback"))
+                    .layer(Rename("nested"))
+                    .layer_fallback(true),
+            )
+            .merge(
+                Router::new()
+                    .route("/c", Named("c"))
+                    .layer(Rename("merged")),
+            );
+        assert_eq!(route(&router, "agi://host/a").0, "Named(\"a\")");
+        assert_eq!(route(&router, "agi://host/nested/b").0, "Named(\"nested\")");
+        assert_eq!(route(&router, "agi://host/nested/x").0, "Named(\"nested\")");
+        assert_eq!(route(&router, "agi://host/c").0, "Named(\"merged\")");
+        assert_eq!(route(&router, "agi://host/x").0, "FallbackHandler");
+    }
+
+    /// The commands a session from `peer_addr` starting with `header` receives. Dropped sessions
+    /// receive none.
+    async fn commands_from(router: Router, peer_addr: &str, header: &[u8]) -> Vec<String> {
+        let peer_addr = peer_addr.parse().ok();
+        let local_addr = "10.0.0.1:4573".parse().ok();
+        let (mut client_side, server_side) = tokio::io::duplex(4096);
+        tokio::io::AsyncWriteExt::write_all(&mut client_side, header)
+            .await
+            .unwrap();
+        let server = tokio::spawn(async move {
+            router.handle_from(server_side, peer_addr, local_addr).await;
+        });
+        let request = AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build();
+        let mut commands = vec![];
+        let result = match crate::client::AGIClientSession::start(client_side, &request).await {
+            Ok(session) => {
+                session
+                    .run(|command| {
+                        commands.push(command);
+                        async { AGIStatusGeneric::Ok("1".to_owned(), None) }
+                    })
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        server.await.unwrap();
+        result.map(|()| commands).unwrap_or_default()
+    }
+
+    /// A router sending the addresses of each request.
+    fn address_router() -> Router {
+        Router::new().route(
+            "/",
+            crate::handler::handler_fn(|connection, request| {
+                Box::pin(async move {
+                    connection
+                        .send_command(crate::command::Verbose::new(format!(
+                            "{:?} {:?}",
+                            request.peer_addr, request.local_addr
+                        )))
+                        .await?;
+                    Ok(())
+                })
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn ip_filter_drops_connections_before_reading() {
+        let router = || {
+            address_router().ip_filter(IpFilter::new().allow("192.168.0.0/16").deny("192.168.0.1"))
+        };
+        assert_eq!(
+            commands_from(router(), "192.168.10.1:5000", b"").await,
+            vec!["VERBOSE \"Some(192.168.10.1:5000) Some(10.0.0.1:4573)\"".to_owned()]
+        );
+        assert_eq!(
+            commands_from(router(), "192.168.0.1:5000", b"").await,
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            commands_from(router(), "[::1]:5000", b"").await,
+            Vec::<String>::new()
+        );
+    }
+
+    #[tokio::test]
+    async fn proxy_protocol_replaces_addresses() {
+        let header = b"PROXY TCP4 192.168.10.1 192.168.0.2 56324 4573\r\n";
+        let router = || address_router().proxy_protocol(ProxyProtocol::Required);
+        assert_eq!(
+            commands_from(router(), "10.0.0.2:5000", header).await,
+            vec!["VERBOSE \"Some(192.168.10.1:56324) Some(192.168.0.2:4573)\"".to_owned()]
+        );
+        assert!(commands_from(router(), "10.0.0.2:5000", b"")
+            .await
+            .is_empty());
+        // health checks of the proxy keep the address of the connection
+        assert_eq!(
+            commands_from(router(), "10.0.0.2:5000", b"PROXY UNKNOWN\r\n").await,
+            vec!["VERBOSE \"Some(10.0.0.2:5000) Some(10.0.0.1:4573)\"".to_owned()]
+        );
+        let router = || address_router().proxy_protocol(ProxyProtocol::Optional);
+        assert_eq!(
+            commands_from(router(), "10.0.0.2:5000", b"").await,
+            vec!["VERBOSE \"Some(10.0.0.2:5000) Some(10.0.0.1:4573)\"".to_owned()]
+        );
+        // the filter checks the original address
+        let router = router().ip_filter(IpFilter::new().deny("192.168.10.1"));
+        assert!(commands_from(router, "10.0.0.2:5000", header)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn silent_proxy_connections_time_out() {
+        let router = address_router()
+            .proxy_protocol(ProxyProtocol::Optional)
+            .proxy_protocol_timeout(Duration::from_millis(10));
+        // the client keeps the connection open, but never sends anything
+        let (_client_side, server_side) = tokio::io::duplex(4096);
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            router.handle_from(server_side, "10.0.0.2:5000".parse().ok(), None),
+        )
+        .await
+        .expect("the connection was not dropped");
     }
 }
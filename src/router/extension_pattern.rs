@@ -0,0 +1,232 @@
+//! Match extensions against patterns in asterisk dialplan syntax, like `_NXXNXXXXXX`.
+//!
+//! A pattern starting with `_` may contain:
+//! - `X`: any digit from 0 to 9
+//! - `Z`: any digit from 1 to 9
+//! - `N`: any digit from 2 to 9
+//! - `[15-7]`: any of the characters in the brackets, where `a-b` is a range
+//! - `.`: one or more arbitrary characters
+//! - `!`: zero or more arbitrary characters
+//!
+//! All other characters match themselves, except `-`, which is ignored. A pattern not starting
+//! with `_` matches exactly the extension it names.
+use std::str::FromStr;
+
+/// Contains all the ways in which an extension pattern can be malformed.
+#[derive(Debug, PartialEq)]
+pub enum ExtensionPatternError {
+    /// The pattern contains a `[` without a matching `]`.
+    UnclosedBracket(String),
+    /// The pattern contains `[]`, which cannot match anything.
+    EmptyBracket(String),
+}
+impl core::fmt::Display for ExtensionPatternError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::UnclosedBracket(x) => {
+                write!(f, "The extension pattern {x} contains an unclosed [")
+            }
+            Self::EmptyBracket(x) => {
+                write!(f, "The extension pattern {x} contains an empty []")
+            }
+        }
+    }
+}
+impl std::error::Error for ExtensionPatternError {}
+
+/// A single element of a pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    /// Exactly this character.
+    Literal(char),
+    /// Any character in one of these inclusive ranges.
+    OneOf(Vec<(char, char)>),
+    /// One or more arbitrary characters (`.`).
+    OneOrMore,
+    /// Zero or more arbitrary characters (`!`).
+    ZeroOrMore,
+}
+impl Element {
+    /// Whether this element matches the single character `c`.
+    ///
+    /// The repeating elements match any character.
+    fn matches_char(&self, c: char) -> bool {
+        match self {
+            Self::Literal(x) => *x == c,
+            Self::OneOf(ranges) => ranges.iter().any(|(from, to)| (*from..=*to).contains(&c)),
+            Self::OneOrMore | Self::ZeroOrMore => true,
+        }
+    }
+}
+
+/// An extension pattern in asterisk dialplan syntax.
+///
+/// Example:
+/// ```
+/// use blazing_agi::router::ExtensionPattern;
+/// let pattern = "_NXXNXXXXXX".parse::<ExtensionPattern>().unwrap();
+/// assert!(pattern.matches("2125551234"));
+/// assert!(!pattern.matches("0125551234"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionPattern {
+    pattern: String,
+    elements: Vec<Element>,
+}
+impl ExtensionPattern {
+    /// Whether `extension` matches this pattern.
+    pub fn matches(&self, extension: &str) -> bool {
+        let chars = extension.chars().collect::<Vec<_>>();
+        matches_from(&self.elements, &chars)
+    }
+}
+
+/// Whether `chars` matches `elements` completely.
+fn matches_from(elements: &[Element], chars: &[char]) -> bool {
+    let Some((first, rest)) = elements.split_first() else {
+        return chars.is_empty();
+    };
+    match first {
+        Element::OneOrMore => (1..=chars.len()).any(|taken| matches_from(rest, &chars[taken..])),
+        Element::ZeroOrMore => (0..=chars.len()).any(|taken| matches_from(rest, &chars[taken..])),
+        single => match chars.split_first() {
+            Some((c, remaining)) => single.matches_char(*c) && matches_from(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+impl FromStr for ExtensionPattern {
+    type Err = ExtensionPatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(pattern) = s.strip_prefix('_') else {
+            return Ok(Self {
+                pattern: s.to_owned(),
+                elements: s.chars().map(Element::Literal).collect(),
+            });
+        };
+        let mut elements = vec![];
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            let element = match c {
+                'X' | 'x' => Element::OneOf(vec![('0', '9')]),
+                'Z' | 'z' => Element::OneOf(vec![('1', '9')]),
+                'N' | 'n' => Element::OneOf(vec![('2', '9')]),
+                '.' => Element::OneOrMore,
+                '!' => Element::ZeroOrMore,
+                '-' => continue,
+                '[' => {
+                    let mut class = vec![];
+                    loop {
+                        match chars.next() {
+                            None => {
+                                return Err(ExtensionPatternError::UnclosedBracket(s.to_owned()))
+                            }
+                            Some(']') => break,
+                            Some(x) => class.push(x),
+                        };
+                    }
+                    if class.is_empty() {
+                        return Err(ExtensionPatternError::EmptyBracket(s.to_owned()));
+                    };
+                    Element::OneOf(parse_class(&class))
+                }
+                x => Element::Literal(x),
+            };
+            elements.push(element);
+        }
+        Ok(Self {
+            pattern: s.to_owned(),
+            elements,
+        })
+    }
+}
+
+/// Parse the inside of a `[...]` character class into inclusive ranges.
+fn parse_class(class: &[char]) -> Vec<(char, char)> {
+    let mut ranges = vec![];
+    let mut idx = 0;
+    while idx < class.len() {
+        // a - between two characters is a range, anywhere else it is a literal -
+        if idx + 2 < class.len() && class[idx + 1] == '-' {
+            ranges.push((class[idx], class[idx + 2]));
+            idx += 3;
+        } else {
+            ranges.push((class[idx], class[idx]));
+            idx += 1;
+        };
+    }
+    ranges
+}
+
+impl core::fmt::Display for ExtensionPattern {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pattern(s: &str) -> ExtensionPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn literal_extensions() {
+        assert!(pattern("100").matches("100"));
+        assert!(!pattern("100").matches("1000"));
+        assert!(!pattern("100").matches("10"));
+        // without _, X is just an X
+        assert!(pattern("1XX").matches("1XX"));
+        assert!(!pattern("1XX").matches("123"));
+    }
+
+    #[test]
+    fn digit_classes() {
+        let p = pattern("_NXXNXXXXXX");
+        assert!(p.matches("2125551234"));
+        assert!(!p.matches("1125551234"));
+        assert!(!p.matches("2121551234"));
+        assert!(!p.matches("212555123"));
+        assert!(pattern("_Z").matches("1"));
+        assert!(!pattern("_Z").matches("0"));
+        assert!(pattern("_nxx").matches("200"));
+    }
+
+    #[test]
+    fn brackets_and_dashes() {
+        let p = pattern("_1[2-4,7]-X");
+        assert!(p.matches("120"));
+        assert!(p.matches("1,9"));
+        assert!(p.matches("179"));
+        assert!(!p.matches("159"));
+        assert!(pattern("_[-5]").matches("-"));
+        assert_eq!(
+            "_1[23".parse::<ExtensionPattern>(),
+            Err(ExtensionPatternError::UnclosedBracket("_1[23".to_owned()))
+        );
+        assert_eq!(
+            "_1[]".parse::<ExtensionPattern>(),
+            Err(ExtensionPatternError::EmptyBracket("_1[]".to_owned()))
+        );
+    }
+
+    #[test]
+    fn repetitions() {
+        assert!(pattern("_9.").matches("91"));
+        assert!(pattern("_9.").matches("9123456"));
+        assert!(!pattern("_9.").matches("9"));
+        assert!(pattern("_9!").matches("9"));
+        assert!(pattern("_9!").matches("9123"));
+        assert!(pattern("_.5").matches("12345"));
+        assert!(!pattern("_.5").matches("5"));
+    }
+
+    #[test]
+    fn display_is_the_pattern() {
+        assert_eq!(pattern("_NXX-XXXX").to_string(), "_NXX-XXXX");
+    }
+}
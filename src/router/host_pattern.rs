@@ -0,0 +1,132 @@
+//! Patterns for the host of a request url, used by [`Router::host`](super::Router::host).
+use std::collections::HashMap;
+
+/// A single dot-separated label of a host pattern.
+#[derive(Debug, PartialEq)]
+enum Label {
+    /// Matches exactly this label, ignoring case.
+    Static(String),
+    /// Matches any single label and captures its value under this name.
+    Capture(String),
+}
+
+/// A pattern for hosts like `tenant-a.agi.local` or `:tenant.agi.local`.
+#[derive(Debug)]
+pub(crate) struct HostPattern {
+    pattern: String,
+    labels: Vec<Label>,
+}
+impl HostPattern {
+    /// Parse `pattern`.
+    ///
+    /// # Panics
+    /// Panics if `pattern` is empty or contains an empty label or an unnamed capture.
+    pub fn new(pattern: &str) -> Self {
+        assert!(!pattern.is_empty(), "Host must not be empty");
+        let labels = pattern
+            .split('.')
+            .map(|label| {
+                assert!(
+                    !label.is_empty(),
+                    "The host {pattern} contains an empty label"
+                );
+                if let Some(name) = label.strip_prefix(':') {
+                    assert!(
+                        !name.is_empty(),
+                        "Captures must have a name, but the host {pattern} contains an unnamed capture"
+                    );
+                    Label::Capture(name.to_owned())
+                } else {
+                    Label::Static(label.to_ascii_lowercase())
+                }
+            })
+            .collect();
+        Self {
+            pattern: pattern.to_owned(),
+            labels,
+        }
+    }
+
+    /// Whether this pattern contains any captures.
+    pub fn has_captures(&self) -> bool {
+        self.labels.iter().any(|x| matches!(x, Label::Capture(_)))
+    }
+
+    /// Whether this pattern matches exactly the same hosts as `other`.
+    pub fn conflicts_with(&self, other: &HostPattern) -> bool {
+        self.labels.len() == other.labels.len()
+            && self.labels.iter().zip(&other.labels).all(|x| match x {
+                (Label::Static(a), Label::Static(b)) => a == b,
+                (Label::Capture(_), Label::Capture(_)) => true,
+                _ => false,
+            })
+    }
+
+    /// Match `host` against this pattern, returning the captured labels on success.
+    pub fn matches(&self, host: &str) -> Option<HashMap<String, String>> {
+        let labels = host.split('.').collect::<Vec<_>>();
+        if labels.len() != self.labels.len() {
+            return None;
+        };
+        let mut captures = HashMap::new();
+        for (label, pattern) in labels.into_iter().zip(&self.labels) {
+            match pattern {
+                Label::Static(x) => {
+                    if !x.eq_ignore_ascii_case(label) {
+                        return None;
+                    };
+                }
+                Label::Capture(name) => {
+                    captures.insert(name.clone(), label.to_owned());
+                }
+            };
+        }
+        Some(captures)
+    }
+}
+impl core::fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn static_hosts() {
+        let pattern = HostPattern::new("Tenant-A.agi.local");
+        assert_eq!(pattern.matches("tenant-a.AGI.local"), Some(HashMap::new()));
+        assert_eq!(pattern.matches("tenant-b.agi.local"), None);
+        assert_eq!(pattern.matches("x.tenant-a.agi.local"), None);
+        assert!(!pattern.has_captures());
+    }
+
+    #[test]
+    fn captured_hosts() {
+        let pattern = HostPattern::new(":tenant.agi.local");
+        assert_eq!(
+            pattern.matches("tenant-b.agi.local"),
+            Some(HashMap::from([(
+                "tenant".to_owned(),
+                "tenant-b".to_owned()
+            )]))
+        );
+        assert_eq!(pattern.matches("agi.local"), None);
+        assert!(pattern.has_captures());
+    }
+
+    #[test]
+    fn conflicts() {
+        assert!(HostPattern::new(":a.local").conflicts_with(&HostPattern::new(":b.LOCAL")));
+        assert!(!HostPattern::new(":a.local").conflicts_with(&HostPattern::new("a.local")));
+        assert!(!HostPattern::new("a.local").conflicts_with(&HostPattern::new("a.b.local")));
+    }
+
+    #[test]
+    #[should_panic(expected = "contains an empty label")]
+    fn empty_label_panics() {
+        HostPattern::new("a..local");
+    }
+}
@@ -0,0 +1,1041 @@
+//! A prefix tree of routes, used by [`Router`](super::Router) to find the handler for a path.
+//!
+//! Each edge in the tree is a single path segment. When looking up a path, static segments are
+//! preferred over constrained `:captures<...>` (in the order they were added), which are
+//! preferred over unconstrained `:captures`, which are preferred over a `*wildcard`. If a
+//! preferred edge leads to a dead end, the next one is tried, so a path matches whenever any
+//! route matches it.
+//!
+//! A node may also hold conditional routes, which are used for paths below it that no route
+//! matches, if their predicate accepts the request. If none does, the fallback of the node (if
+//! any) is used.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::AGIVariableDump;
+
+/// A condition on a request, deciding whether a conditional route handles it.
+pub(crate) struct Predicate(Box<dyn Fn(&AGIVariableDump) -> bool + Send + Sync>);
+impl Predicate {
+    pub fn new<P>(predicate: P) -> Self
+    where
+        P: Fn(&AGIVariableDump) -> bool + Send + Sync + 'static,
+    {
+        Self(Box::new(predicate))
+    }
+
+    /// Whether the conditional route handles `request`.
+    pub fn accepts(&self, request: &AGIVariableDump) -> bool {
+        (self.0)(request)
+    }
+}
+impl core::fmt::Debug for Predicate {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "Predicate")
+    }
+}
+
+/// Whether `segment` can be parsed as a `T`.
+fn parses<T: FromStr>(segment: &str) -> bool {
+    segment.parse::<T>().is_ok()
+}
+
+/// A constraint on the value of a capture, like `u32` in `:id<u32>`.
+#[derive(Debug)]
+enum Constraint {
+    /// The value must parse as this integer type.
+    Type(fn(&str) -> bool),
+    /// The value must match this (anchored) regex.
+    Regex(Regex),
+}
+impl Constraint {
+    /// Parse the constraint `source` (without the angle brackets).
+    ///
+    /// Integer type names constrain to that type, anything else is a regex the whole value must
+    /// match.
+    ///
+    /// # Panics
+    /// Panics if `source` is neither an integer type nor a valid regex.
+    fn new(pattern: &str, source: &str) -> Self {
+        let check: fn(&str) -> bool = match source {
+            "u8" => parses::<u8>,
+            "u16" => parses::<u16>,
+            "u32" => parses::<u32>,
+            "u64" => parses::<u64>,
+            "u128" => parses::<u128>,
+            "usize" => parses::<usize>,
+            "i8" => parses::<i8>,
+            "i16" => parses::<i16>,
+            "i32" => parses::<i32>,
+            "i64" => parses::<i64>,
+            "i128" => parses::<i128>,
+            "isize" => parses::<isize>,
+            _ => {
+                return match Regex::new(&format!("^(?:{source})$")) {
+                    Ok(x) => Self::Regex(x),
+                    Err(e) => panic!(
+                        "The route {pattern} contains the invalid constraint <{source}>: {e}"
+                    ),
+                }
+            }
+        };
+        Self::Type(check)
+    }
+
+    /// Whether `segment` satisfies this constraint.
+    fn accepts(&self, segment: &str) -> bool {
+        match self {
+            Self::Type(check) => check(segment),
+            Self::Regex(regex) => regex.is_match(segment),
+        }
+    }
+}
+
+/// A single segment of a route pattern.
+#[derive(Debug, PartialEq)]
+enum Segment<'a> {
+    /// Matches exactly this segment.
+    Static(&'a str),
+    /// Matches any single segment satisfying the constraint (if any) and captures its value
+    /// under this name. An optional capture may be left out at the end of the path.
+    Capture {
+        name: &'a str,
+        constraint: Option<&'a str>,
+        optional: bool,
+    },
+    /// Matches one or more segments (which may be empty) at the end of the path.
+    Wildcard,
+}
+
+/// Parse the capture segment `segment` (without the leading `:`) of `pattern`.
+///
+/// # Panics
+/// Panics if the capture has no name or an unclosed constraint.
+fn parse_capture<'a>(pattern: &str, segment: &'a str) -> Segment<'a> {
+    let (segment, optional) = match segment.strip_suffix('?') {
+        Some(x) => (x, true),
+        None => (segment, false),
+    };
+    let (name, constraint) = match segment.split_once('<') {
+        Some((name, constraint)) => {
+            let Some(constraint) = constraint.strip_suffix('>') else {
+                panic!("The route {pattern} contains a constraint without a closing >");
+            };
+            (name, Some(constraint))
+        }
+        None => (segment, None),
+    };
+    assert!(
+        !name.is_empty(),
+        "Captures must have a name, but {pattern} contains an unnamed capture"
+    );
+    Segment::Capture {
+        name,
+        constraint,
+        optional,
+    }
+}
+
+/// Split `pattern` (starting with `/`) into its segments.
+///
+/// # Panics
+/// Panics if a capture is malformed, a wildcard is not the last segment or an optional capture
+/// is followed by a segment that is not optional.
+fn parse_pattern(pattern: &str) -> Vec<Segment<'_>> {
+    let segments = pattern
+        .split('/')
+        .skip(1)
+        .map(|s| {
+            if let Some(capture) = s.strip_prefix(':') {
+                parse_capture(pattern, capture)
+            } else if s.starts_with('*') {
+                Segment::Wildcard
+            } else {
+                Segment::Static(s)
+            }
+        })
+        .collect::<Vec<_>>();
+    if let Some(idx) = segments.iter().position(|s| *s == Segment::Wildcard) {
+        assert!(
+            idx == segments.len() - 1,
+            "A wildcard must be the last segment, but {pattern} continues after it"
+        );
+    };
+    let is_optional = |s: &Segment| matches!(s, Segment::Capture { optional: true, .. });
+    if let Some(idx) = segments.iter().position(is_optional) {
+        assert!(
+            segments[idx..].iter().all(is_optional),
+            "Only optional captures may follow an optional capture, but {pattern} continues with another segment"
+        );
+    };
+    segments
+}
+
+/// An edge matching any single segment satisfying its constraint.
+#[derive(Debug)]
+struct CaptureEdge<T> {
+    /// The name the segment is captured under.
+    name: String,
+    /// The source and parsed constraint of this edge, if any.
+    constraint: Option<(String, Constraint)>,
+    child: Box<Node<T>>,
+}
+impl<T> CaptureEdge<T> {
+    /// Whether this edge matches `segment`.
+    fn accepts(&self, segment: &str) -> bool {
+        match &self.constraint {
+            Some((_, constraint)) => constraint.accepts(segment),
+            None => true,
+        }
+    }
+}
+
+/// A node in the tree. The path to this node is the route pattern up to here.
+#[derive(Debug)]
+struct Node<T> {
+    /// Edges matching a single segment exactly.
+    statics: HashMap<String, Node<T>>,
+    /// Edges matching any single segment satisfying their constraint. Constrained edges come
+    /// first, in the order they were added; there is at most one unconstrained edge, at the end.
+    captures: Vec<CaptureEdge<T>>,
+    /// The pattern of a route whose optional captures may all be left out from here on, and the
+    /// constraints of the capture edges leading to its value.
+    skip: Option<(String, Vec<Option<String>>)>,
+    /// The pattern and value of a route ending in a wildcard right after this node.
+    wildcard: Option<(String, T)>,
+    /// The pattern and value of a route ending exactly at this node.
+    value: Option<(String, T)>,
+    /// The prefix, predicate and value of conditional routes for all paths starting at this
+    /// node, in the order they were added.
+    conditionals: Vec<(String, Predicate, T)>,
+    /// The prefix and value of the fallback for all paths starting at this node.
+    fallback: Option<(String, T)>,
+}
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self {
+            statics: HashMap::new(),
+            captures: vec![],
+            skip: None,
+            wildcard: None,
+            value: None,
+            conditionals: vec![],
+            fallback: None,
+        }
+    }
+}
+impl<T> Node<T> {
+    /// Get the child of this node along `segment`, creating it if necessary.
+    ///
+    /// # Panics
+    /// Panics if `segment` is a capture with a different name then the existing capture edge
+    /// with the same constraint, if its constraint is invalid, or if it is a wildcard.
+    fn child(&mut self, pattern: &str, segment: Segment<'_>) -> &mut Node<T> {
+        match segment {
+            Segment::Static(s) => self.statics.entry(s.to_owned()).or_default(),
+            Segment::Capture {
+                name, constraint, ..
+            } => {
+                let existing = self.captures.iter().position(|edge| {
+                    edge.constraint.as_ref().map(|(source, _)| source.as_str()) == constraint
+                });
+                let idx = match existing {
+                    Some(idx) => {
+                        let existing_name = &self.captures[idx].name;
+                        assert!(
+                            existing_name == name,
+                            "The route {pattern} captures :{name} where another route captures :{existing_name}. Use the same name for both."
+                        );
+                        idx
+                    }
+                    None => {
+                        let edge = CaptureEdge {
+                            name: name.to_owned(),
+                            constraint: constraint
+                                .map(|x| (x.to_owned(), Constraint::new(pattern, x))),
+                            child: Box::default(),
+                        };
+                        // keep the unconstrained edge last
+                        let idx = match self.captures.last() {
+                            Some(last)
+                                if last.constraint.is_none() && edge.constraint.is_some() =>
+                            {
+                                self.captures.len() - 1
+                            }
+                            _ => self.captures.len(),
+                        };
+                        self.captures.insert(idx, edge);
+                        idx
+                    }
+                };
+                &mut self.captures[idx].child
+            }
+            Segment::Wildcard => {
+                panic!("Wildcards end a route and do not have a child node")
+            }
+        }
+    }
+
+    /// Find the value for `segments` below this node.
+    ///
+    /// `captures` holds the captured values on the way to this node. On success, it contains all
+    /// captures of the matching route; on failure, it is left as it was.
+    fn lookup<'tree, 'path>(
+        &'tree self,
+        segments: &[&'path str],
+        captures: &mut Vec<(&'tree str, &'path str)>,
+    ) -> Option<(&'tree str, &'tree T, Option<String>)> {
+        let Some((first, rest)) = segments.split_first() else {
+            if let Some((pattern, value)) = &self.value {
+                return Some((pattern, value, None));
+            };
+            // optional captures may be left out at the end of the path
+            let (_, constraints) = self.skip.as_ref()?;
+            return self
+                .follow(constraints)
+                .map(|(pattern, value)| (pattern, value, None));
+        };
+        if let Some(found) = self
+            .statics
+            .get(*first)
+            .and_then(|child| child.lookup(rest, captures))
+        {
+            return Some(found);
+        };
+        for edge in self.captures.iter().filter(|edge| edge.accepts(first)) {
+            captures.push((&edge.name, first));
+            if let Some(found) = edge.child.lookup(rest, captures) {
+                return Some(found);
+            };
+            captures.pop();
+        }
+        self.wildcard
+            .as_ref()
+            .map(|(pattern, value)| (pattern.as_str(), value, Some(segments.join("/"))))
+    }
+
+    /// Get the value of the route at the end of the capture edges with `constraints`.
+    fn follow(&self, constraints: &[Option<String>]) -> Option<(&str, &T)> {
+        let Some((first, rest)) = constraints.split_first() else {
+            return self
+                .value
+                .as_ref()
+                .map(|(pattern, value)| (pattern.as_str(), value));
+        };
+        self.captures
+            .iter()
+            .find(|edge| edge.constraint.as_ref().map(|(source, _)| source) == first.as_ref())
+            .and_then(|edge| edge.child.follow(rest))
+    }
+
+    /// Panic if a route ends at this node, either exactly or by leaving out optional captures.
+    fn assert_no_route(&self, pattern: &str) {
+        let existing = self.value.as_ref().map(|(x, _)| x);
+        if let Some(existing) = existing.or(self.skip.as_ref().map(|(x, _)| x)) {
+            panic!("The route {pattern} conflicts with the route {existing}");
+        };
+    }
+
+    /// Find the deepest node on the way along `segments` for which `pick` returns a value.
+    ///
+    /// `captures` is handled like in [`lookup`](Self::lookup).
+    fn lookup_deepest<'tree, 'path, F, R>(
+        &'tree self,
+        segments: &[&'path str],
+        captures: &mut Vec<(&'tree str, &'path str)>,
+        pick: &F,
+    ) -> Option<R>
+    where
+        F: Fn(&'tree Node<T>) -> Option<R>,
+    {
+        if let Some((first, rest)) = segments.split_first() {
+            if let Some(found) = self
+                .statics
+                .get(*first)
+                .and_then(|child| child.lookup_deepest(rest, captures, pick))
+            {
+                return Some(found);
+            };
+            for edge in self.captures.iter().filter(|edge| edge.accepts(first)) {
+                captures.push((&edge.name, first));
+                if let Some(found) = edge.child.lookup_deepest(rest, captures, pick) {
+                    return Some(found);
+                };
+                captures.pop();
+            }
+        };
+        pick(self)
+    }
+
+    /// Apply `f` to the value of every (conditional) route and `g` to every fallback in this
+    /// subtree.
+    fn map_values<F: FnMut(T) -> T, G: FnMut(T) -> T>(self, f: &mut F, g: &mut G) -> Self {
+        Node {
+            statics: self
+                .statics
+                .into_iter()
+                .map(|(segment, child)| (segment, child.map_values(f, g)))
+                .collect(),
+            captures: self
+                .captures
+                .into_iter()
+                .map(|edge| CaptureEdge {
+                    name: edge.name,
+                    constraint: edge.constraint,
+                    child: Box::new(edge.child.map_values(f, g)),
+                })
+                .collect(),
+            skip: self.skip,
+            wildcard: self.wildcard.map(|(pattern, value)| (pattern, f(value))),
+            value: self.value.map(|(pattern, value)| (pattern, f(value))),
+            conditionals: self
+                .conditionals
+                .into_iter()
+                .map(|(prefix, predicate, value)| (prefix, predicate, f(value)))
+                .collect(),
+            fallback: self.fallback.map(|(prefix, value)| (prefix, g(value))),
+        }
+    }
+
+    /// Move everything stored in this subtree to `entries`.
+    fn collect(self, entries: &mut Entries<T>) {
+        entries.routes.extend(self.value);
+        entries.routes.extend(self.wildcard);
+        entries.conditionals.extend(self.conditionals);
+        entries.fallbacks.extend(self.fallback);
+        for edge in self.captures {
+            edge.child.collect(entries);
+        }
+        for (_, child) in self.statics {
+            child.collect(entries);
+        }
+    }
+}
+
+/// Everything stored in a tree, taken out of it.
+struct Entries<T> {
+    /// Pattern and value of each route.
+    routes: Vec<(String, T)>,
+    /// Prefix, predicate and value of each conditional route.
+    conditionals: Vec<(String, Predicate, T)>,
+    /// Prefix and value of each fallback.
+    fallbacks: Vec<(String, T)>,
+}
+
+/// The result of a successful lookup.
+#[derive(Debug, PartialEq)]
+pub(crate) struct RouteMatch<'tree, T> {
+    /// The pattern of the matching route.
+    pub pattern: &'tree str,
+    /// The value stored for the matching route.
+    pub value: &'tree T,
+    /// The values of all captures in the matching route.
+    pub captures: HashMap<String, String>,
+    /// The part of the path matched by the wildcard, if the route ends in one.
+    pub wildcards: Option<String>,
+}
+
+/// The result of [`RouteTree::lookup_fallback`].
+#[derive(Debug, PartialEq)]
+pub(crate) struct FallbackMatch<'tree, T> {
+    /// The prefix the conditional route or fallback was added for.
+    pub prefix: &'tree str,
+    /// The value of the conditional route or fallback.
+    pub value: &'tree T,
+    /// The values of all captures in the prefix of the conditional route or fallback.
+    pub captures: HashMap<String, String>,
+    /// Whether `value` is a fallback rather than a conditional route.
+    pub fallback: bool,
+}
+
+/// A set of route patterns with a value for each, optimized for finding the value for a path.
+#[derive(Debug)]
+pub(crate) struct RouteTree<T> {
+    root: Node<T>,
+}
+impl<T> Default for RouteTree<T> {
+    fn default() -> Self {
+        Self {
+            root: Node::default(),
+        }
+    }
+}
+impl<T> RouteTree<T> {
+    /// Add the route `pattern` with `value`.
+    ///
+    /// # Panics
+    /// Panics if `pattern` is malformed, or if it conflicts with a route already in the tree:
+    /// - the same pattern (up to the names of captures and wildcards) was added before
+    /// - a capture at the same position with the same constraint has a different name
+    /// - a route matches the same paths once its optional captures are left out, like `/a` and
+    ///   `/a/:b?`
+    pub fn insert(&mut self, pattern: &str, value: T) {
+        let segments = parse_pattern(pattern);
+        // the constraints of the trailing optional captures, which may be left out
+        let mut optional = segments
+            .iter()
+            .rev()
+            .map_while(|segment| match segment {
+                Segment::Capture {
+                    constraint,
+                    optional: true,
+                    ..
+                } => Some(constraint.map(str::to_owned)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        optional.reverse();
+        let first_optional = segments.len() - optional.len();
+        let mut node = &mut self.root;
+        for (idx, segment) in segments.into_iter().enumerate() {
+            if segment == Segment::Wildcard {
+                if let Some((existing, _)) = &node.wildcard {
+                    panic!("The route {pattern} conflicts with the route {existing}");
+                };
+                node.wildcard = Some((pattern.to_owned(), value));
+                return;
+            };
+            if idx >= first_optional {
+                node.assert_no_route(pattern);
+                node.skip = Some((
+                    pattern.to_owned(),
+                    optional[idx - first_optional..].to_vec(),
+                ));
+            };
+            node = node.child(pattern, segment);
+        }
+        node.assert_no_route(pattern);
+        node.value = Some((pattern.to_owned(), value));
+    }
+
+    /// Add a fallback for all paths starting with `prefix` that no route matches.
+    ///
+    /// # Panics
+    /// Panics if `prefix` is malformed, contains a wildcard or an optional capture, or if there already is a fallback
+    /// for the same prefix.
+    pub fn insert_fallback(&mut self, prefix: &str, value: T) {
+        let node = self.prefix_node(prefix);
+        if let Some((existing, _)) = &node.fallback {
+            panic!("The fallback for {prefix} conflicts with the fallback for {existing}");
+        };
+        node.fallback = Some((prefix.to_owned(), value));
+    }
+
+    /// Add a conditional route for all paths starting with `prefix` that no route matches.
+    ///
+    /// Conditional routes with a longer prefix are tried first; with the same prefix, they are
+    /// tried in the order they were added.
+    ///
+    /// # Panics
+    /// Panics if `prefix` is malformed or contains a wildcard or an optional capture.
+    pub fn insert_conditional(&mut self, prefix: &str, predicate: Predicate, value: T) {
+        self.prefix_node(prefix)
+            .conditionals
+            .push((prefix.to_owned(), predicate, value));
+    }
+
+    /// Get the node at the end of `prefix`, creating it if necessary.
+    ///
+    /// # Panics
+    /// Panics if `prefix` is malformed or contains a wildcard or an optional capture.
+    fn prefix_node(&mut self, prefix: &str) -> &mut Node<T> {
+        let mut node = &mut self.root;
+        for segment in parse_pattern(prefix) {
+            assert!(
+                segment != Segment::Wildcard,
+                "The prefix {prefix} must not contain a wildcard"
+            );
+            assert!(
+                !matches!(segment, Segment::Capture { optional: true, .. }),
+                "The prefix {prefix} must not contain an optional capture"
+            );
+            node = node.child(prefix, segment);
+        }
+        node
+    }
+
+    /// Move all routes and fallbacks of `other` into this tree, below `prefix`.
+    ///
+    /// `prefix` must not end in `/`; use the empty prefix to merge the trees at the root.
+    /// The route `/` of `other` becomes the route `prefix` itself.
+    ///
+    /// # Panics
+    /// Panics if a route or fallback of `other` conflicts with one in this tree.
+    pub fn graft(&mut self, prefix: &str, other: RouteTree<T>) {
+        let mut entries = Entries {
+            routes: vec![],
+            conditionals: vec![],
+            fallbacks: vec![],
+        };
+        other.root.collect(&mut entries);
+        for (pattern, value) in entries.routes {
+            if pattern == "/" && !prefix.is_empty() {
+                self.insert(prefix, value);
+            } else {
+                self.insert(&format!("{prefix}{pattern}"), value);
+            };
+        }
+        for (pattern, predicate, value) in entries.conditionals {
+            self.insert_conditional(&format!("{prefix}{pattern}"), predicate, value);
+        }
+        for (pattern, value) in entries.fallbacks {
+            self.insert_fallback(&format!("{prefix}{pattern}"), value);
+        }
+    }
+
+    /// Find the value for the path made up of `segments`.
+    pub fn lookup<'tree>(&'tree self, segments: &[&str]) -> Option<RouteMatch<'tree, T>> {
+        let mut captures = vec![];
+        let (pattern, value, wildcards) = self.root.lookup(segments, &mut captures)?;
+        Some(RouteMatch {
+            pattern,
+            value,
+            captures: collect_captures(captures),
+            wildcards,
+        })
+    }
+
+    /// Find the value for the path made up of `segments` if no route matches it: The first
+    /// conditional route accepting `request` or else the fallback, of the node with the longest
+    /// prefix matching the path that has either.
+    pub fn lookup_fallback<'tree>(
+        &'tree self,
+        segments: &[&str],
+        request: &AGIVariableDump,
+    ) -> Option<FallbackMatch<'tree, T>> {
+        let mut captures = vec![];
+        let (prefix, value, fallback) =
+            self.root.lookup_deepest(segments, &mut captures, &|node| {
+                node.conditionals
+                    .iter()
+                    .find(|(_, predicate, _)| predicate.accepts(request))
+                    .map(|(prefix, _, value)| (prefix.as_str(), value, false))
+                    .or(node
+                        .fallback
+                        .as_ref()
+                        .map(|(prefix, value)| (prefix.as_str(), value, true)))
+            })?;
+        Some(FallbackMatch {
+            prefix,
+            value,
+            captures: collect_captures(captures),
+            fallback,
+        })
+    }
+
+    /// Apply `f` to the value of every (conditional) route, but not every fallback, in the tree.
+    pub fn map_values<F: FnMut(T) -> T>(self, mut f: F) -> Self {
+        Self {
+            root: self.root.map_values(&mut f, &mut |x| x),
+        }
+    }
+
+    /// Apply `f` to the value of every fallback in the tree.
+    pub fn map_fallbacks<F: FnMut(T) -> T>(self, mut f: F) -> Self {
+        Self {
+            root: self.root.map_values(&mut |x| x, &mut f),
+        }
+    }
+}
+
+/// Convert captures found during a lookup to owned values.
+fn collect_captures(captures: Vec<(&str, &str)>) -> HashMap<String, String> {
+    captures
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build a tree where each route maps to its own pattern.
+    fn tree(patterns: &[&'static str]) -> RouteTree<&'static str> {
+        let mut tree = RouteTree::default();
+        for pattern in patterns {
+            tree.insert(pattern, *pattern);
+        }
+        tree
+    }
+
+    /// Look up `path` (starting with `/`) the way the router splits url paths.
+    fn lookup<'tree>(
+        tree: &'tree RouteTree<&'static str>,
+        path: &str,
+    ) -> Option<RouteMatch<'tree, &'static str>> {
+        let segments = path.split('/').skip(1).collect::<Vec<_>>();
+        tree.lookup(&segments)
+    }
+
+    fn request() -> AGIVariableDump {
+        AGIVariableDump::builder("agi://host/".parse().unwrap()).build()
+    }
+
+    fn captures(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn matches_simple() {
+        let tree = tree(&["/some/route"]);
+        assert_eq!(
+            lookup(&tree, "/some/route"),
+            Some(RouteMatch {
+                pattern: "/some/route",
+                value: &"/some/route",
+                captures: HashMap::new(),
+                wildcards: None
+            })
+        );
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let tree = tree(&["/some/route/*irrelevant"]);
+        assert_eq!(
+            lookup(&tree, "/some/route/appended/wildcard").map(|m| m.wildcards),
+            Some(Some("appended/wildcard".to_owned()))
+        );
+    }
+
+    #[test]
+    fn matches_empty_wildcard() {
+        let tree = tree(&["/some/route/*"]);
+        assert_eq!(lookup(&tree, "/some/route"), None);
+    }
+
+    #[test]
+    fn matches_trivial_wildcard() {
+        let tree = tree(&["/some/route/*"]);
+        assert_eq!(
+            lookup(&tree, "/some/route/").map(|m| m.wildcards),
+            Some(Some("".to_owned()))
+        );
+    }
+
+    #[test]
+    fn matches_captures() {
+        let tree = tree(&["/scripts/:name"]);
+        assert_eq!(
+            lookup(&tree, "/scripts/the_script").map(|m| m.captures),
+            Some(captures(&[("name", "the_script")]))
+        );
+    }
+
+    #[test]
+    fn matches_captures_and_wildcard() {
+        let tree = tree(&["/:directory/:name/*"]);
+        assert_eq!(
+            lookup(&tree, "/scripts/the_script/additionals"),
+            Some(RouteMatch {
+                pattern: "/:directory/:name/*",
+                value: &"/:directory/:name/*",
+                captures: captures(&[("directory", "scripts"), ("name", "the_script")]),
+                wildcards: Some("additionals".to_owned())
+            })
+        );
+    }
+
+    #[test]
+    fn matches_trivial_path_segments() {
+        let tree = tree(&["/:directory/:name/"]);
+        assert_eq!(
+            lookup(&tree, "/scripts//").map(|m| m.captures),
+            Some(captures(&[("directory", "scripts"), ("name", "")]))
+        );
+    }
+
+    #[test]
+    fn matches_empty_path() {
+        let mut tree = RouteTree::default();
+        tree.insert("", "root");
+        assert_eq!(tree.lookup(&[]).map(|m| *m.value), Some("root"));
+        // a path with segments does not match the empty route
+        assert_eq!(lookup(&tree, "/"), None);
+    }
+
+    #[test]
+    fn no_match() {
+        let tree = tree(&["/other_path"]);
+        assert_eq!(lookup(&tree, "/some/path"), None);
+        // longer than any route
+        assert_eq!(lookup(&tree, "/other_path/more"), None);
+    }
+
+    #[test]
+    fn static_before_capture_before_wildcard() {
+        let tree = tree(&["/users/*", "/users/:id", "/users/admin"]);
+        assert_eq!(
+            lookup(&tree, "/users/admin").map(|m| *m.value),
+            Some("/users/admin")
+        );
+        assert_eq!(
+            lookup(&tree, "/users/bob").map(|m| *m.value),
+            Some("/users/:id")
+        );
+        assert_eq!(
+            lookup(&tree, "/users/bob/x").map(|m| *m.value),
+            Some("/users/*")
+        );
+    }
+
+    #[test]
+    fn backtracks_out_of_dead_ends() {
+        let tree = tree(&["/a/static/end", "/a/:capture/other", "/a/*"]);
+        // the static edge matches the segment, but nothing below it matches the rest
+        assert_eq!(
+            lookup(&tree, "/a/static/other"),
+            Some(RouteMatch {
+                pattern: "/a/:capture/other",
+                value: &"/a/:capture/other",
+                captures: captures(&[("capture", "static")]),
+                wildcards: None
+            })
+        );
+        // a failed capture is not reported
+        assert_eq!(
+            lookup(&tree, "/a/static/nothing"),
+            Some(RouteMatch {
+                pattern: "/a/*",
+                value: &"/a/*",
+                captures: HashMap::new(),
+                wildcards: Some("static/nothing".to_owned())
+            })
+        );
+    }
+
+    #[test]
+    fn routes_can_be_grafted() {
+        let mut tree = tree(&["/c"]);
+        let mut nested = RouteTree::default();
+        for pattern in ["/", "/:b", "/*"] {
+            nested.insert(pattern, pattern);
+        }
+        tree.graft(
+            "/a",
+            nested.map_values(|x| if x == "/" { "root" } else { x }),
+        );
+        assert_eq!(lookup(&tree, "/a").map(|x| *x.value), Some("root"));
+        assert_eq!(lookup(&tree, "/a/x").map(|x| *x.value), Some("/:b"));
+        assert_eq!(lookup(&tree, "/a/x/y").map(|x| *x.value), Some("/*"));
+        assert_eq!(lookup(&tree, "/c").map(|x| *x.value), Some("/c"));
+    }
+
+    #[test]
+    fn deepest_fallback_is_found() {
+        let mut tree = tree(&["/tenant/:id/menu"]);
+        tree.insert_fallback("", "root");
+        tree.insert_fallback("/tenant/:id", "tenant");
+        tree.insert_fallback("/tenant/admin/x", "admin");
+        let found = tree
+            .lookup_fallback(&["tenant", "7", "other"], &request())
+            .unwrap();
+        assert_eq!(*found.value, "tenant");
+        assert_eq!(found.captures, captures(&[("id", "7")]));
+        // the static edge leads to no fallback, so the capture is tried
+        let found = tree
+            .lookup_fallback(&["tenant", "admin", "y"], &request())
+            .unwrap();
+        assert_eq!(*found.value, "tenant");
+        assert_eq!(found.captures, captures(&[("id", "admin")]));
+        let found = tree
+            .lookup_fallback(&["tenant", "admin", "x", "y"], &request())
+            .unwrap();
+        assert_eq!(*found.value, "admin");
+        assert_eq!(found.captures, HashMap::new());
+        assert_eq!(
+            *tree.lookup_fallback(&["tenant"], &request()).unwrap().value,
+            "root"
+        );
+        assert_eq!(
+            *tree.lookup_fallback(&[], &request()).unwrap().value,
+            "root"
+        );
+    }
+
+    #[test]
+    fn fallbacks_are_grafted() {
+        let mut nested = RouteTree::default();
+        nested.insert_fallback("", "nested");
+        let mut tree = RouteTree::default();
+        tree.graft("/a/:b", nested);
+        assert_eq!(
+            *tree
+                .lookup_fallback(&["a", "1", "c"], &request())
+                .unwrap()
+                .value,
+            "nested"
+        );
+        assert!(tree.lookup_fallback(&["a"], &request()).is_none());
+    }
+
+    #[test]
+    fn values_and_fallbacks_are_mapped_separately() {
+        let mut tree = tree(&["/a"]);
+        tree.insert_fallback("/b", "b fallback");
+        let tree = tree.map_values(|_| "route").map_fallbacks(|_| "fallback");
+        assert_eq!(*lookup(&tree, "/a").unwrap().value, "route");
+        assert_eq!(
+            *tree.lookup_fallback(&["b", "c"], &request()).unwrap().value,
+            "fallback"
+        );
+    }
+
+    #[test]
+    fn conditionals_by_prefix_then_order() {
+        let mut tree = tree(&["/a/b"]);
+        tree.insert_conditional("", Predicate::new(|req| req.context == "x"), "root x");
+        tree.insert_conditional("/a/:id", Predicate::new(|req| req.context == "x"), "a x");
+        tree.insert_conditional("/a/:id", Predicate::new(|_| true), "a any");
+        tree.insert_conditional("/c", Predicate::new(|req| req.context == "x"), "c x");
+        tree.insert_fallback("/c", "c fallback");
+        let mut request = request();
+        request.context = "x".to_owned();
+        let found = tree.lookup_fallback(&["a", "1", "c"], &request).unwrap();
+        assert_eq!(*found.value, "a x");
+        assert!(!found.fallback);
+        assert_eq!(found.captures, captures(&[("id", "1")]));
+        assert_eq!(
+            *tree.lookup_fallback(&["b"], &request).unwrap().value,
+            "root x"
+        );
+        request.context = "y".to_owned();
+        assert_eq!(
+            *tree.lookup_fallback(&["a", "1"], &request).unwrap().value,
+            "a any"
+        );
+        assert!(tree.lookup_fallback(&["b"], &request).is_none());
+        // no conditional route accepts the request, so the fallback at the same node is used
+        let found = tree.lookup_fallback(&["c"], &request).unwrap();
+        assert_eq!(*found.value, "c fallback");
+        assert!(found.fallback);
+    }
+
+    #[test]
+    #[should_panic(expected = "The fallback for /a conflicts with the fallback for /a")]
+    fn identical_fallbacks_conflict() {
+        let mut tree = RouteTree::default();
+        tree.insert_fallback("/a", 1);
+        tree.insert_fallback("/a", 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "The route /a/b conflicts with the route /a/b")]
+    fn identical_routes_conflict() {
+        tree(&["/a/b", "/a/b"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The route /a/*rest conflicts with the route /a/*")]
+    fn wildcards_conflict() {
+        tree(&["/a/*", "/a/*rest"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "captures :y where another route captures :x")]
+    fn differently_named_captures_conflict() {
+        tree(&["/a/:x/b", "/a/:y/c"]);
+    }
+
+    #[test]
+    fn constrained_captures() {
+        let tree = tree(&[
+            "/q/:name",
+            "/q/:id<u32>",
+            "/q/:ext<[0-9]{3}>/x",
+            "/r/:neg<i8>",
+        ]);
+        assert_eq!(lookup(&tree, "/q/7").map(|x| *x.value), Some("/q/:id<u32>"));
+        assert_eq!(lookup(&tree, "/q/-7").map(|x| *x.value), Some("/q/:name"));
+        // the constrained edges lead to dead ends, so the unconstrained one is tried
+        let found = lookup(&tree, "/q/123/x").unwrap();
+        assert_eq!(*found.value, "/q/:ext<[0-9]{3}>/x");
+        assert_eq!(found.captures, captures(&[("ext", "123")]));
+        assert_eq!(
+            lookup(&tree, "/r/-128").map(|x| *x.value),
+            Some("/r/:neg<i8>")
+        );
+        assert!(lookup(&tree, "/r/128").is_none());
+        // the regex is anchored
+        assert!(lookup(&tree, "/q/1234/x").is_none());
+    }
+
+    #[test]
+    fn optional_captures() {
+        let tree = tree(&["/a/:b?/:c<u8>?", "/d/:e?"]);
+        let found = lookup(&tree, "/a").unwrap();
+        assert_eq!(*found.value, "/a/:b?/:c<u8>?");
+        assert_eq!(found.captures, HashMap::new());
+        let found = lookup(&tree, "/a/x").unwrap();
+        assert_eq!(found.captures, captures(&[("b", "x")]));
+        let found = lookup(&tree, "/a/x/1").unwrap();
+        assert_eq!(found.captures, captures(&[("b", "x"), ("c", "1")]));
+        assert!(lookup(&tree, "/a/x/y").is_none());
+        assert_eq!(lookup(&tree, "/d").map(|x| *x.value), Some("/d/:e?"));
+    }
+
+    #[test]
+    fn optional_captures_belong_to_their_route() {
+        let tree = tree(&["/p/:x?/:y?", "/p/:x<u8>", "/q/:x?", "/q/:x/:y/:z"]);
+        let found = lookup(&tree, "/p").unwrap();
+        assert_eq!(*found.value, "/p/:x?/:y?");
+        assert_eq!(found.captures, HashMap::new());
+        assert_eq!(lookup(&tree, "/p/1").map(|x| *x.value), Some("/p/:x<u8>"));
+        assert_eq!(lookup(&tree, "/p/a").map(|x| *x.value), Some("/p/:x?/:y?"));
+        assert_eq!(lookup(&tree, "/q/1").map(|x| *x.value), Some("/q/:x?"));
+        // the capture of /q/:x? does not make the captures of other routes optional
+        assert!(lookup(&tree, "/q/1/2").is_none());
+        assert_eq!(
+            lookup(&tree, "/q/1/2/3").map(|x| *x.value),
+            Some("/q/:x/:y/:z")
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The route /a/:x? conflicts with the route /a")]
+    fn optional_captures_conflict_with_shorter_routes() {
+        tree(&["/a", "/a/:x?"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The route /a conflicts with the route /a/:x?")]
+    fn shorter_routes_conflict_with_optional_captures() {
+        tree(&["/a/:x?", "/a"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The route /p/:x conflicts with the route /p/:x?/:y?")]
+    fn optional_captures_conflict_on_shared_nodes() {
+        tree(&["/p/:x?/:y?", "/p/:x"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only optional captures may follow an optional capture")]
+    fn optional_captures_must_be_last() {
+        tree(&["/a/:b?/c"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "contains the invalid constraint <[a-z>")]
+    fn invalid_constraint_panics() {
+        tree(&["/a/:b<[a-z>"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "captures :y where another route captures :x")]
+    fn same_constraint_needs_same_name() {
+        tree(&["/a/:x<u32>/b", "/a/:y<u32>/c", "/a/:z"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "A wildcard must be the last segment")]
+    fn wildcard_must_be_last() {
+        tree(&["/a/*/b"]);
+    }
+}
@@ -14,17 +14,20 @@ use crate::{router::Router, AGIError};
 /// # Errors
 /// Returns an Error when we are unable to start a [`TcpListener`].
 pub async fn serve(listener: TcpListener, router: Router) -> Result<(), AGIError> {
-    let router_arc = Arc::new(router);
+    let router_arc = Arc::new(router.finish());
     loop {
         let our_router = router_arc.clone();
-        let (stream, _) = listener
+        let (stream, peer_addr) = listener
             .accept()
             .await
             .map_err(|_| AGIError::CannotSpawnListener)?;
         #[cfg(feature = "tracing")]
         event!(Level::DEBUG, "Got a new incoming connection.");
+        let local_addr = stream.local_addr().ok();
         tokio::spawn(async move {
-            our_router.handle(stream).await;
+            our_router
+                .handle_from(stream, Some(peer_addr), local_addr)
+                .await;
         });
     }
 }
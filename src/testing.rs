@@ -0,0 +1,558 @@
+//! Test [`AGIHandler`]s and [`Router`]s in-process, without opening a network port.
+//!
+//! This module is only available with the `testing` feature. You will usually want to enable it
+//! in your `dev-dependencies` only:
+//! ```toml
+//! [dev-dependencies]
+//! blazing_agi = { version = "*", features = ["testing"] }
+//! ```
+//!
+//! An [`AGITestClient`] plays the part of asterisk. It sends the initial request to a router or
+//! handler over an in-memory stream and then lets the test script what asterisk should expect
+//! and respond:
+//! ```
+//! # use blazing_agi::{command::{Answer, Verbose}, router::Router};
+//! # use blazing_agi::testing::AGITestClient;
+//! # use blazing_agi_macros::create_handler;
+//! #[create_handler]
+//! async fn greet(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+//!     connection.send_command(Answer::new()).await?;
+//!     connection
+//!         .send_command(Verbose::new(format!("Hello {}", request.variables.callerid)))
+//!         .await?;
+//!     Ok(())
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let client = AGITestClient::new(Router::new().route("/greet", greet))
+//!     .url("agi://localhost/greet")
+//!     .variables(|dump| dump.callerid("Obi-Wan"));
+//! let mut session = client.start().await;
+//! session.expect("ANSWER").await;
+//! session.respond("200 result=0").await;
+//! session.expect_matching("VERBOSE \"Hello *\"").await;
+//! session.respond("200 result=1").await;
+//! session.finish().await.unwrap();
+//! # }
+//! ```
+//!
+//! All `expect*` methods panic with a readable description of the session so far when the
+//! handler does not behave as expected, so they can be used like `assert!`.
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+    },
+    task::JoinHandle,
+};
+use url::Url;
+
+use crate::{
+    connection::Connection, handler::AGIHandler, router::Router, AGIError, AGIRequest,
+    AGIVariableDump, AGIVariableDumpBuilder,
+};
+
+pub mod simulator;
+
+/// How long to wait for the handler to send a command, unless configured otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Size of the in-memory buffer between the test client and the handler.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// What a test client runs against.
+#[derive(Debug, Clone)]
+enum Target {
+    Router(Arc<Router>),
+    Handler {
+        handler: Arc<dyn AGIHandler>,
+        captures: HashMap<String, String>,
+        wildcards: Option<String>,
+    },
+}
+
+/// Plays the part of asterisk for a [`Router`] or a single [`AGIHandler`].
+///
+/// Configure the client, then [`start`](Self::start) as many sessions as you like.
+#[derive(Debug, Clone)]
+pub struct AGITestClient {
+    target: Target,
+    variables: AGIVariableDumpBuilder,
+    timeout: Duration,
+    peer_addr: Option<SocketAddr>,
+}
+impl AGITestClient {
+    /// Test `router`. The request will be dispatched to a handler like it would in production.
+    pub fn new(router: Router) -> Self {
+        Self::with_target(Target::Router(Arc::new(router.finish())))
+    }
+
+    /// Test a single `handler`. No routing takes place - the handler is called directly.
+    ///
+    /// Use [`capture`](Self::capture) and [`wildcards`](Self::wildcards) to set the values the
+    /// handler would otherwise get from its route.
+    pub fn handler<H: AGIHandler + 'static>(handler: H) -> Self {
+        Self::with_target(Target::Handler {
+            handler: Arc::new(handler),
+            captures: HashMap::new(),
+            wildcards: None,
+        })
+    }
+
+    fn with_target(target: Target) -> Self {
+        Self {
+            target,
+            variables: AGIVariableDump::builder(
+                Url::parse("agi://localhost/").expect("Static URL should be parsable"),
+            ),
+            timeout: DEFAULT_TIMEOUT,
+            peer_addr: None,
+        }
+    }
+
+    /// Set the requested url (`agi_request`).
+    ///
+    /// # Panics
+    /// Panics if `url` is not a valid url.
+    #[must_use]
+    pub fn url(mut self, url: &str) -> Self {
+        let parsed = Url::parse(url).unwrap_or_else(|e| panic!("Invalid url {url}: {e}"));
+        self.variables = self
+            .variables
+            .network_script(parsed.path().trim_start_matches('/'))
+            .request(crate::AGIRequestType::FastAGI(parsed));
+        self
+    }
+
+    /// Change the variables sent in the initial request.
+    ///
+    /// ```
+    /// # use blazing_agi::{router::Router, testing::AGITestClient};
+    /// let client = AGITestClient::new(Router::new())
+    ///     .variables(|dump| dump.callerid("123").context("from-pstn").arg(1, "de"));
+    /// ```
+    #[must_use]
+    pub fn variables<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(AGIVariableDumpBuilder) -> AGIVariableDumpBuilder,
+    {
+        self.variables = f(self.variables);
+        self
+    }
+
+    /// Set a captured path segment. Only relevant when testing a single handler.
+    ///
+    /// # Panics
+    /// Panics if this client tests a [`Router`].
+    #[must_use]
+    pub fn capture<S: Into<String>>(mut self, name: S, value: S) -> Self {
+        match &mut self.target {
+            Target::Handler { captures, .. } => {
+                captures.insert(name.into(), value.into());
+            }
+            Target::Router(_) => {
+                panic!("Captures are set by the router. Use AGITestClient::handler to set them manually.")
+            }
+        };
+        self
+    }
+
+    /// Set the wildcard match. Only relevant when testing a single handler.
+    ///
+    /// # Panics
+    /// Panics if this client tests a [`Router`].
+    #[must_use]
+    pub fn wildcards<S: Into<String>>(mut self, value: S) -> Self {
+        match &mut self.target {
+            Target::Handler { wildcards, .. } => {
+                *wildcards = Some(value.into());
+            }
+            Target::Router(_) => {
+                panic!("Wildcards are set by the router. Use AGITestClient::handler to set them manually.")
+            }
+        };
+        self
+    }
+
+    /// Set how long to wait for each command the handler sends.
+    #[must_use]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Pretend the sessions come from `peer_addr`.
+    ///
+    /// Without a peer address, an [`ip_filter`](Router::ip_filter) with allowed networks drops
+    /// every session.
+    /// ```
+    /// # use blazing_agi::{ip_filter::IpFilter, router::Router, testing::AGITestClient};
+    /// let router = Router::new().ip_filter(IpFilter::new().allow("10.0.0.0/8"));
+    /// let client = AGITestClient::new(router).peer_addr("10.0.0.2:5000".parse().unwrap());
+    /// ```
+    #[must_use]
+    pub fn peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.peer_addr = Some(peer_addr);
+        self
+    }
+
+    /// Start a new session: Spawn the handler and send the initial request to it.
+    ///
+    /// This has to be called from within a tokio runtime.
+    ///
+    /// # Panics
+    /// Panics if the initial request cannot be sent.
+    pub async fn start(&self) -> AGITestSession {
+        let (client_side, handler_side) = tokio::io::duplex(BUFFER_SIZE);
+        let variables = self.variables.clone().build();
+        let peer_addr = self.peer_addr;
+        let task = match self.target.clone() {
+            Target::Router(router) => tokio::spawn(async move {
+                router.handle_from(handler_side, peer_addr, None).await;
+                Ok(())
+            }),
+            Target::Handler {
+                handler,
+                captures,
+                wildcards,
+            } => {
+                let request = AGIRequest {
+                    host: variables.request.host(),
+                    query: variables.request.query(),
+                    state: None,
+                    peer_addr,
+                    local_addr: None,
+                    route: None,
+                    variables: variables.clone(),
+                    captures,
+                    wildcards,
+                };
+                tokio::spawn(async move {
+                    let mut connection = Connection::new(handler_side);
+                    handler.handle(&mut connection, &request).await
+                })
+            }
+        };
+        let (read_half, write_half) = tokio::io::split(client_side);
+        let mut session = AGITestSession {
+            reader: BufReader::new(read_half),
+            writer: write_half,
+            task,
+            timeout: self.timeout,
+            log: vec![],
+        };
+        // a single handler does not read the initial request - it already has it
+        if let Target::Router(_) = self.target {
+            session.send("agi_network: yes\n").await;
+            session.send(&variables.to_string()).await;
+        };
+        session
+    }
+}
+
+/// Does `text` match `pattern`, where `*` in `pattern` matches any (possibly empty) sequence of
+/// characters?
+/// ```
+/// use blazing_agi::testing::glob_matches;
+/// assert!(glob_matches("STREAM FILE *", "STREAM FILE \"welcome\" \"\""));
+/// assert!(!glob_matches("STREAM FILE", "STREAM FILE \"welcome\" \"\""));
+/// ```
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = parts.collect::<Vec<_>>();
+    // the last part has to match at the very end
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(idx) => {
+                rest = &rest[idx + part.len()..];
+            }
+            None => return false,
+        };
+    }
+    rest.ends_with(last)
+}
+
+/// A single line in the log of a session.
+#[derive(Debug)]
+enum LogLine {
+    Sent(String),
+    Received(String),
+}
+
+/// A running session between an [`AGITestClient`] and the handler under test.
+///
+/// Create this with [`AGITestClient::start`].
+#[derive(Debug)]
+pub struct AGITestSession {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+    task: JoinHandle<Result<(), AGIError>>,
+    timeout: Duration,
+    log: Vec<LogLine>,
+}
+impl AGITestSession {
+    /// Describe everything that happened in this session so far.
+    fn describe(&self) -> String {
+        let mut res = String::from("Session so far:\n");
+        for line in &self.log {
+            match line {
+                LogLine::Sent(x) => {
+                    for l in x.lines() {
+                        res.push_str(&format!("  asterisk > {l}\n"));
+                    }
+                }
+                LogLine::Received(x) => {
+                    res.push_str(&format!("  handler  > {}\n", x.trim_end_matches('\n')));
+                }
+            }
+        }
+        res
+    }
+
+    /// Send `text` to the handler as-is.
+    async fn send(&mut self, text: &str) {
+        self.log.push(LogLine::Sent(text.to_owned()));
+        if let Err(e) = self.writer.write_all(text.as_bytes()).await {
+            panic!(
+                "Unable to send {text:?} to the handler: {e}\n{}",
+                self.describe()
+            );
+        };
+    }
+
+    /// Wait for the next command the handler sends.
+    ///
+    /// Returns `None` if the handler closed the connection instead.
+    ///
+    /// # Panics
+    /// Panics if the handler does not send a command in time.
+    pub async fn next_command(&mut self) -> Option<String> {
+        let mut line = String::new();
+        let read = tokio::time::timeout(self.timeout, self.reader.read_line(&mut line)).await;
+        match read {
+            Err(_) => {
+                panic!(
+                    "The handler did not send a command within {:?}.\n{}",
+                    self.timeout,
+                    self.describe()
+                );
+            }
+            Ok(Err(e)) => {
+                panic!("Unable to read from the handler: {e}\n{}", self.describe());
+            }
+            Ok(Ok(0)) => None,
+            Ok(Ok(_)) => {
+                self.log.push(LogLine::Received(line.clone()));
+                Some(line.trim_end_matches('\n').to_owned())
+            }
+        }
+    }
+
+    /// Wait for the next command and check it with `predicate`.
+    ///
+    /// `description` is used in the panic message when the check fails.
+    /// Returns the command that was sent.
+    ///
+    /// # Panics
+    /// Panics if the handler sends no command or a command not satisfying `predicate`.
+    pub async fn expect_with<F>(&mut self, description: &str, predicate: F) -> String
+    where
+        F: FnOnce(&str) -> bool,
+    {
+        match self.next_command().await {
+            None => {
+                panic!(
+                    "Expected the handler to send {description}, but it closed the connection.\n{}",
+                    self.describe()
+                );
+            }
+            Some(command) => {
+                if !predicate(&command) {
+                    panic!(
+                        "Expected the handler to send {description}, but it sent `{command}`.\n{}",
+                        self.describe()
+                    );
+                };
+                command
+            }
+        }
+    }
+
+    /// Expect the next command to be exactly `command` (without the trailing newline).
+    ///
+    /// # Panics
+    /// Panics if the handler sends something else.
+    pub async fn expect(&mut self, command: &str) -> String {
+        let expected = command.trim_end_matches('\n');
+        self.expect_with(&format!("`{expected}`"), |actual| actual == expected)
+            .await
+    }
+
+    /// Expect the next command to match `pattern`, in which `*` matches any sequence of
+    /// characters.
+    ///
+    /// # Panics
+    /// Panics if the handler sends something else.
+    pub async fn expect_matching(&mut self, pattern: &str) -> String {
+        self.expect_with(&format!("a command matching `{pattern}`"), |actual| {
+            glob_matches(pattern, actual)
+        })
+        .await
+    }
+
+    /// Respond to the last command with `status`, e.g. `200 result=0`.
+    ///
+    /// A trailing newline is added if `status` does not already contain one.
+    pub async fn respond(&mut self, status: &str) {
+        let mut line = status.to_owned();
+        if !line.ends_with('\n') {
+            line.push('\n');
+        };
+        self.send(&line).await;
+    }
+
+    /// Expect `command`, then respond with `status`.
+    ///
+    /// # Panics
+    /// Panics if the handler sends something else.
+    pub async fn expect_and_respond(&mut self, command: &str, status: &str) {
+        self.expect(command).await;
+        self.respond(status).await;
+    }
+
+    /// Hang up and wait for the handler to finish.
+    ///
+    /// Returns what the handler returned. When testing a [`Router`], this is always `Ok(())`,
+    /// because the router handles errors itself.
+    ///
+    /// # Panics
+    /// Panics if the handler sends further commands or does not finish in time.
+    pub async fn finish(mut self) -> Result<(), AGIError> {
+        // asterisk hangs up. a well-behaved handler should not send anything anymore
+        let _ = self.writer.shutdown().await;
+        let mut rest = String::new();
+        match tokio::time::timeout(self.timeout, self.reader.read_to_string(&mut rest)).await {
+            Err(_) => {
+                panic!(
+                    "The handler did not finish within {:?}.\n{}",
+                    self.timeout,
+                    self.describe()
+                );
+            }
+            Ok(_) => {
+                if !rest.is_empty() {
+                    panic!(
+                        "Expected the handler to finish, but it sent `{}`.\n{}",
+                        rest.trim_end_matches('\n'),
+                        self.describe()
+                    );
+                };
+            }
+        };
+        match (&mut self.task).await {
+            Ok(res) => res,
+            Err(e) => {
+                panic!("The handler panicked: {e}\n{}", self.describe());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::command::{Answer, Verbose};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct AnswerAndGreet {}
+    #[async_trait::async_trait]
+    impl AGIHandler for AnswerAndGreet {
+        async fn handle(
+            &self,
+            connection: &mut Connection,
+            request: &AGIRequest,
+        ) -> Result<(), AGIError> {
+            connection.send_command(Answer::new()).await?;
+            connection
+                .send_command(Verbose::new(format!(
+                    "Hello {}",
+                    request.captures.get("name").map_or("stranger", |x| x)
+                )))
+                .await?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("VERBOSE *", "VERBOSE \"hi\""));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("A*B*C", "AxxBxxC"));
+        assert!(glob_matches("ANSWER", "ANSWER"));
+        assert!(!glob_matches("ANSWER", "ANSWERS"));
+        assert!(!glob_matches("A*B", "AxxC"));
+        assert!(!glob_matches("AB*B", "AB"));
+    }
+
+    #[tokio::test]
+    async fn router_session() {
+        let client = AGITestClient::new(Router::new().route("/greet", AnswerAndGreet {}))
+            .url("agi://localhost/greet");
+        let mut session = client.start().await;
+        session.expect_and_respond("ANSWER", "200 result=0").await;
+        session.expect_matching("VERBOSE \"Hello *\"").await;
+        session.respond("200 result=1").await;
+        assert!(session.finish().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn filtered_router_session() {
+        let router = Router::new()
+            .route("/greet", AnswerAndGreet {})
+            .ip_filter(crate::ip_filter::IpFilter::new().allow("10.0.0.0/8"));
+        let client = AGITestClient::new(router)
+            .url("agi://localhost/greet")
+            .peer_addr("10.0.0.2:5000".parse().unwrap());
+        let mut session = client.start().await;
+        session.expect_and_respond("ANSWER", "200 result=0").await;
+        session.expect_matching("VERBOSE \"Hello *\"").await;
+        session.respond("200 result=1").await;
+        assert!(session.finish().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn handler_session() {
+        let client = AGITestClient::handler(AnswerAndGreet {}).capture("name", "There");
+        let mut session = client.start().await;
+        session.expect_and_respond("ANSWER", "200 result=0").await;
+        session.expect("VERBOSE \"Hello There\"").await;
+        session.respond("200 result=1").await;
+        assert!(session.finish().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn handler_error_is_returned() {
+        let client = AGITestClient::handler(AnswerAndGreet {});
+        let mut session = client.start().await;
+        session.expect("ANSWER").await;
+        // asterisk hangs up instead of responding
+        assert!(session.finish().await.is_err());
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "Expected the handler to send `HANGUP`, but it sent `ANSWER`.")]
+    async fn readable_failure() {
+        let client = AGITestClient::handler(AnswerAndGreet {});
+        let mut session = client.start().await;
+        session.expect("HANGUP").await;
+    }
+}
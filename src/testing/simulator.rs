@@ -0,0 +1,575 @@
+//! A simulated asterisk channel that answers commands the way asterisk would.
+//!
+//! Where an [`AGITestSession`](super::AGITestSession) requires you to script every response,
+//! a [`ChannelSimulator`] keeps a model of the channel and computes the responses itself:
+//! - channel variables for `SET VARIABLE`, `GET VARIABLE` and `GET FULL VARIABLE`
+//! - an in-memory AstDB for the `DATABASE` commands
+//! - the channel state, changed by `ANSWER` and `HANGUP`
+//! - a queue of DTMF digits the caller presses, consumed by `GET DATA`, `WAIT FOR DIGIT` and
+//!   `STREAM FILE`
+//!
+//! This makes it possible to test an IVR by stating what the caller does and asserting on the
+//! state of the channel afterwards:
+//! ```
+//! # use blazing_agi::command::{get_data::GetDataResponse, AGIResponse, GetData, SetVariable};
+//! # use blazing_agi::testing::{AGITestClient, simulator::ChannelSimulator};
+//! # use blazing_agi_macros::create_handler;
+//! #[create_handler]
+//! async fn ivr(connection: &mut Connection, request: &AGIRequest) -> Result<(), AGIError> {
+//!     let choice = connection
+//!         .send_command(GetData::new("menu".to_owned()).with_max_digits(4))
+//!         .await?;
+//!     if let AGIResponse::Ok(GetDataResponse::Digits { digits, .. }) = choice {
+//!         connection
+//!             .send_command(SetVariable::new("CHOICE".to_owned(), digits))
+//!             .await?;
+//!     };
+//!     Ok(())
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let mut channel = ChannelSimulator::new(AGITestClient::handler(ivr)).press("12#");
+//! channel.run().await.unwrap();
+//! assert_eq!(channel.variable("CHOICE"), Some("12"));
+//! assert_eq!(channel.played(), ["menu"]);
+//! # }
+//! ```
+use std::collections::{HashMap, VecDeque};
+
+use crate::command::Characters;
+use crate::AGIError;
+
+use super::AGITestClient;
+
+/// Status sent for commands the simulator does not know.
+const INVALID: &str = "510 Invalid or unknown command";
+/// Status sent for commands that need a live channel after the channel was hung up.
+const DEAD_CHANNEL: &str = "511 Command Not Permitted on a dead channel or intercept routine";
+/// The position asterisk reports after playing a file in the simulator.
+const END_POSITION: u64 = 1000;
+
+/// The state of a simulated channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    /// The channel was not answered yet.
+    Ringing,
+    /// The channel was answered.
+    Up,
+    /// The channel was hung up.
+    HungUp,
+}
+
+/// A custom dialplan function for `GET FULL VARIABLE`, called with the arguments inside the
+/// parentheses.
+type DialplanFunction = Box<dyn Fn(&str) -> String + Send + Sync>;
+
+/// A simulated asterisk channel.
+///
+/// Configure the initial state of the channel, then [`run`](Self::run) the handler against it
+/// and inspect the state afterwards.
+pub struct ChannelSimulator {
+    client: AGITestClient,
+    state: ChannelState,
+    variables: HashMap<String, String>,
+    database: HashMap<(String, String), String>,
+    functions: HashMap<String, DialplanFunction>,
+    digits: VecDeque<Characters>,
+    played: Vec<String>,
+    verbose_messages: Vec<String>,
+}
+impl core::fmt::Debug for ChannelSimulator {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("ChannelSimulator")
+            .field("client", &self.client)
+            .field("state", &self.state)
+            .field("variables", &self.variables)
+            .field("database", &self.database)
+            .field("functions", &self.functions.keys().collect::<Vec<_>>())
+            .field("digits", &self.digits)
+            .field("played", &self.played)
+            .field("verbose_messages", &self.verbose_messages)
+            .finish()
+    }
+}
+impl ChannelSimulator {
+    /// Simulate a ringing channel connected to whatever `client` tests.
+    pub fn new(client: AGITestClient) -> Self {
+        Self {
+            client,
+            state: ChannelState::Ringing,
+            variables: HashMap::new(),
+            database: HashMap::new(),
+            functions: HashMap::new(),
+            digits: VecDeque::new(),
+            played: vec![],
+            verbose_messages: vec![],
+        }
+    }
+
+    /// Start with the channel already answered.
+    #[must_use]
+    pub fn answered(mut self) -> Self {
+        self.state = ChannelState::Up;
+        self
+    }
+
+    /// Set a channel variable before the handler runs.
+    #[must_use]
+    pub fn with_variable<S: Into<String>>(mut self, name: S, value: S) -> Self {
+        self.variables.insert(name.into(), value.into());
+        self
+    }
+
+    /// Put a value into the AstDB before the handler runs.
+    #[must_use]
+    pub fn with_database_entry<S: Into<String>>(mut self, family: S, key: S, value: S) -> Self {
+        self.database
+            .insert((family.into(), key.into()), value.into());
+        self
+    }
+
+    /// Make the dialplan function `name` available in `GET FULL VARIABLE`.
+    ///
+    /// `${name(args)}` evaluates to `function(args)`. Unknown functions evaluate to the empty
+    /// string.
+    #[must_use]
+    pub fn function<F>(mut self, name: &str, function: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.functions.insert(name.to_owned(), Box::new(function));
+        self
+    }
+
+    /// Queue DTMF digits the caller presses, e.g. `"12#"`.
+    ///
+    /// The digits are consumed in order by the commands that read DTMF input.
+    ///
+    /// # Panics
+    /// Panics if `digits` contains anything but `0-9`, `*` and `#`.
+    #[must_use]
+    pub fn press(mut self, digits: &str) -> Self {
+        for digit in digits.chars() {
+            let digit =
+                Characters::try_from(digit).unwrap_or_else(|x| panic!("{x:?} is not a DTMF digit"));
+            self.digits.push_back(digit);
+        }
+        self
+    }
+
+    /// Run the handler against this channel until it closes the connection.
+    ///
+    /// Returns what the handler returned (see [`AGITestSession::finish`](super::AGITestSession::finish)).
+    ///
+    /// # Panics
+    /// Panics if the handler stops sending commands without closing the connection.
+    pub async fn run(&mut self) -> Result<(), AGIError> {
+        let mut session = self.client.start().await;
+        while let Some(command) = session.next_command().await {
+            let status = self.respond_to(&command);
+            session.respond(&status).await;
+        }
+        session.finish().await
+    }
+
+    /// The current state of the channel.
+    pub fn state(&self) -> ChannelState {
+        self.state
+    }
+
+    /// The value of a channel variable, if it is set.
+    pub fn variable(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(String::as_str)
+    }
+
+    /// The value stored in the AstDB under `family/key`, if any.
+    pub fn database(&self, family: &str, key: &str) -> Option<&str> {
+        self.database
+            .get(&(family.to_owned(), key.to_owned()))
+            .map(String::as_str)
+    }
+
+    /// The files played to the caller, in order.
+    pub fn played(&self) -> &[String] {
+        &self.played
+    }
+
+    /// The messages sent with `VERBOSE`, in order.
+    pub fn verbose_messages(&self) -> &[String] {
+        &self.verbose_messages
+    }
+
+    /// The digits the caller pressed that were not consumed yet.
+    pub fn remaining_digits(&self) -> String {
+        self.digits.iter().map(char::from).collect()
+    }
+
+    /// Compute the status asterisk would send in response to `command`.
+    fn respond_to(&mut self, command: &str) -> String {
+        let words = split_arguments(command);
+        let words = words.iter().map(String::as_str).collect::<Vec<_>>();
+        // commands that need a live channel
+        let needs_channel = matches!(
+            words.as_slice(),
+            ["ANSWER"]
+                | ["GET", "DATA", ..]
+                | ["STREAM", "FILE", ..]
+                | ["WAIT", "FOR", "DIGIT", ..]
+        );
+        if needs_channel && self.state == ChannelState::HungUp {
+            return DEAD_CHANNEL.to_owned();
+        };
+        match words.as_slice() {
+            ["ANSWER"] => {
+                self.state = ChannelState::Up;
+                "200 result=0".to_owned()
+            }
+            ["HANGUP"] => {
+                self.state = ChannelState::HungUp;
+                "200 result=1".to_owned()
+            }
+            ["HANGUP", channel] => {
+                if *channel == self.own_channel() {
+                    self.state = ChannelState::HungUp;
+                    "200 result=1".to_owned()
+                } else {
+                    "200 result=-1".to_owned()
+                }
+            }
+            ["NOOP", ..] => "200 result=0".to_owned(),
+            ["VERBOSE", message, ..] => {
+                self.verbose_messages.push((*message).to_owned());
+                "200 result=1".to_owned()
+            }
+            ["SET", "VARIABLE", name, value] => {
+                self.variables
+                    .insert((*name).to_owned(), (*value).to_owned());
+                "200 result=1".to_owned()
+            }
+            ["GET", "VARIABLE", name] => match self.variables.get(*name) {
+                Some(value) => format!("200 result=1 ({value})"),
+                None => "200 result=0".to_owned(),
+            },
+            ["GET", "FULL", "VARIABLE", expression] => {
+                format!("200 result=1 ({})", self.evaluate(expression))
+            }
+            ["GET", "FULL", "VARIABLE", expression, channel] => {
+                if *channel == self.own_channel() {
+                    format!("200 result=1 ({})", self.evaluate(expression))
+                } else {
+                    "200 result=0".to_owned()
+                }
+            }
+            ["DATABASE", "GET", family, key] => match self.database(family, key) {
+                Some(value) => format!("200 result=1 ({value})"),
+                None => "200 result=0".to_owned(),
+            },
+            ["DATABASE", "PUT", family, key, value] => {
+                self.database.insert(
+                    ((*family).to_owned(), (*key).to_owned()),
+                    (*value).to_owned(),
+                );
+                "200 result=1".to_owned()
+            }
+            ["DATABASE", "DEL", family, key] => {
+                match self
+                    .database
+                    .remove(&((*family).to_owned(), (*key).to_owned()))
+                {
+                    Some(_) => "200 result=1".to_owned(),
+                    None => "200 result=0".to_owned(),
+                }
+            }
+            ["DATABASE", "DELTREE", family, rest @ ..] if rest.len() <= 1 => {
+                let prefix = rest.first().copied();
+                let before = self.database.len();
+                self.database.retain(|(fam, key), _| {
+                    fam != family || prefix.is_some_and(|prefix| !key.starts_with(prefix))
+                });
+                if self.database.len() < before {
+                    "200 result=1".to_owned()
+                } else {
+                    "200 result=0".to_owned()
+                }
+            }
+            ["WAIT", "FOR", "DIGIT", _] => match self.digits.pop_front() {
+                Some(digit) => format!("200 result={}", u32::from(char::from(digit))),
+                None => "200 result=0".to_owned(),
+            },
+            ["GET", "DATA", file, rest @ ..] if rest.len() <= 2 => {
+                let max_digits = rest.get(1).and_then(|x| x.parse::<usize>().ok());
+                self.played.push((*file).to_owned());
+                self.collect_digits(max_digits)
+            }
+            ["STREAM", "FILE", file, escape_digits, rest @ ..] if rest.len() <= 1 => {
+                self.played.push((*file).to_owned());
+                let interrupted_by = self
+                    .digits
+                    .front()
+                    .map(char::from)
+                    .filter(|digit| escape_digits.contains(*digit));
+                match interrupted_by {
+                    Some(digit) => {
+                        self.digits.pop_front();
+                        format!("200 result={} endpos={END_POSITION}", u32::from(digit))
+                    }
+                    None => format!("200 result=0 endpos={END_POSITION}"),
+                }
+            }
+            _ => INVALID.to_owned(),
+        }
+    }
+
+    /// Collect digits for `GET DATA` until `#`, `max_digits` or the end of the queue.
+    fn collect_digits(&mut self, max_digits: Option<usize>) -> String {
+        let mut digits = String::new();
+        loop {
+            if max_digits.is_some_and(|max| max > 0 && digits.len() >= max) {
+                return format!("200 result={digits}");
+            };
+            match self.digits.pop_front() {
+                Some(Characters::Pound) => return format!("200 result={digits}"),
+                Some(digit) => digits.push(char::from(digit)),
+                // the caller does not press anything anymore
+                None => return format!("200 result={digits} (timeout)"),
+            };
+        }
+    }
+
+    /// The name of the simulated channel.
+    fn own_channel(&self) -> String {
+        self.client.variables.clone().build().channel
+    }
+
+    /// Evaluate `${...}` expressions in `expression` like the dialplan would.
+    fn evaluate(&self, expression: &str) -> String {
+        let mut res = String::new();
+        let mut rest = expression;
+        while let Some(start) = rest.find("${") {
+            res.push_str(&rest[..start]);
+            let inner_start = start + 2;
+            // find the matching closing brace, allowing nested expressions
+            let mut depth = 1;
+            let mut end = None;
+            for (idx, c) in rest[inner_start..].char_indices() {
+                match c {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = Some(inner_start + idx);
+                            break;
+                        };
+                    }
+                    _ => {}
+                };
+            }
+            let Some(end) = end else {
+                // unterminated expression - asterisk keeps it verbatim
+                res.push_str(&rest[start..]);
+                return res;
+            };
+            let inner = self.evaluate(&rest[inner_start..end]);
+            res.push_str(&self.lookup(&inner));
+            rest = &rest[end + 1..];
+        }
+        res.push_str(rest);
+        res
+    }
+
+    /// Look up a single variable or call a single function.
+    fn lookup(&self, name: &str) -> String {
+        match name.split_once('(') {
+            Some((function, args)) if args.ends_with(')') => self
+                .functions
+                .get(function)
+                .map(|f| f(&args[..args.len() - 1]))
+                .unwrap_or_default(),
+            _ => self.variables.get(name).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Split a command into its arguments like asterisk does: Arguments are separated by spaces,
+/// quotes group arguments and backslashes escape the next character.
+fn split_arguments(command: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut quoted = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    current.push(escaped);
+                };
+                in_argument = true;
+            }
+            '"' => {
+                quoted = !quoted;
+                in_argument = true;
+            }
+            ' ' | '\t' if !quoted => {
+                if in_argument {
+                    res.push(core::mem::take(&mut current));
+                    in_argument = false;
+                };
+            }
+            x => {
+                current.push(x);
+                in_argument = true;
+            }
+        };
+    }
+    if in_argument {
+        res.push(current);
+    };
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use crate::command::get_data::GetDataResponse;
+    use crate::command::stream_file::StreamFileResponse;
+    use crate::command::wait_for_digit::WaitForDigitResponse;
+    use crate::command::{
+        AGIResponse, Answer, DatabaseGet, DatabasePut, GetData, GetFullVariable, Hangup,
+        SetVariable, StreamFile, WaitForDigit,
+    };
+    use crate::connection::Connection;
+    use crate::handler::AGIHandler;
+    use crate::AGIRequest;
+
+    use super::*;
+
+    #[test]
+    fn arguments_are_split() {
+        assert_eq!(
+            split_arguments("SET VARIABLE \"A B\" \"say \\\"hi\\\"\" \"\""),
+            vec!["SET", "VARIABLE", "A B", "say \"hi\"", ""]
+        );
+    }
+
+    /// Ask for a PIN, then store it in the database
+    #[derive(Debug)]
+    struct PinIvr {}
+    #[async_trait::async_trait]
+    impl AGIHandler for PinIvr {
+        async fn handle(
+            &self,
+            connection: &mut Connection,
+            _request: &AGIRequest,
+        ) -> Result<(), AGIError> {
+            connection.send_command(Answer::new()).await?;
+            let welcome = connection
+                .send_command(
+                    StreamFile::new("welcome".to_owned())
+                        .with_escape_digits(vec![Characters::Star]),
+                )
+                .await?;
+            if let AGIResponse::Ok(StreamFileResponse::Interrupted { .. }) = welcome {
+                connection
+                    .send_command(SetVariable::new("SKIPPED".to_owned(), "yes".to_owned()))
+                    .await?;
+            };
+            let pin = connection
+                .send_command(GetData::new("enter-pin".to_owned()).with_max_digits(4))
+                .await?;
+            let AGIResponse::Ok(GetDataResponse::Digits { digits, .. }) = pin else {
+                return Ok(());
+            };
+            let user = connection
+                .send_command(GetFullVariable::new("${CALLERID(num)}".to_owned()))
+                .await?;
+            let AGIResponse::Ok(user) = user else {
+                return Ok(());
+            };
+            connection
+                .send_command(DatabasePut::new(
+                    "pin".to_owned(),
+                    user.value.unwrap_or_default(),
+                    digits,
+                ))
+                .await?;
+            let confirm = connection
+                .send_command(WaitForDigit::new(std::time::Duration::from_secs(3)))
+                .await?;
+            if confirm == AGIResponse::Ok(WaitForDigitResponse::Timeout) {
+                connection.send_command(Hangup::new()).await?;
+            };
+            // not allowed on a dead channel anymore
+            let after_hangup = connection.send_command(Answer::new()).await?;
+            if after_hangup == AGIResponse::DeadChannel {
+                connection
+                    .send_command(SetVariable::new("DEAD".to_owned(), "yes".to_owned()))
+                    .await?;
+            };
+            let old = connection
+                .send_command(DatabaseGet::new("pin".to_owned(), "old".to_owned()))
+                .await?;
+            if let AGIResponse::Ok(old) = old {
+                connection
+                    .send_command(SetVariable::new(
+                        "OLD".to_owned(),
+                        old.value.unwrap_or_default(),
+                    ))
+                    .await?;
+            };
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn ivr_flow() {
+        let mut channel = ChannelSimulator::new(AGITestClient::handler(PinIvr {}))
+            .function("CALLERID", |args| {
+                assert_eq!(args, "num");
+                "0123".to_owned()
+            })
+            .with_database_entry("pin", "old", "0000")
+            .press("*12#9");
+        assert!(channel.run().await.is_ok());
+        assert_eq!(channel.state(), ChannelState::Up);
+        assert_eq!(channel.variable("SKIPPED"), Some("yes"));
+        assert_eq!(channel.database("pin", "0123"), Some("12"));
+        assert_eq!(channel.variable("OLD"), Some("0000"));
+        assert_eq!(channel.variable("DEAD"), None);
+        assert_eq!(channel.played(), ["welcome", "enter-pin"]);
+        assert_eq!(channel.remaining_digits(), "");
+    }
+
+    #[tokio::test]
+    async fn hangup_on_timeout() {
+        let mut channel = ChannelSimulator::new(AGITestClient::handler(PinIvr {}))
+            .function("CALLERID", |_| "0123".to_owned())
+            .press("1234");
+        assert!(channel.run().await.is_ok());
+        assert_eq!(channel.state(), ChannelState::HungUp);
+        assert_eq!(channel.variable("SKIPPED"), None);
+        assert_eq!(channel.database("pin", "0123"), Some("1234"));
+        assert_eq!(channel.variable("DEAD"), Some("yes"));
+        assert_eq!(channel.variable("OLD"), Some(""));
+    }
+
+    #[test]
+    fn expressions_are_evaluated() {
+        let channel = ChannelSimulator::new(AGITestClient::handler(PinIvr {}))
+            .with_variable("A", "B")
+            .with_variable("B", "value")
+            .function("LEN", |args| args.len().to_string());
+        assert_eq!(channel.evaluate("x${A}y"), "xBy");
+        assert_eq!(channel.evaluate("${${A}}"), "value");
+        assert_eq!(channel.evaluate("${LEN(${B})}"), "5");
+        assert_eq!(channel.evaluate("${UNSET}"), "");
+        assert_eq!(channel.evaluate("${A"), "${A");
+    }
+
+    #[test]
+    fn unknown_commands_are_invalid() {
+        let mut channel = ChannelSimulator::new(AGITestClient::handler(PinIvr {}));
+        assert_eq!(channel.respond_to("FOO BAR"), INVALID);
+        assert_eq!(channel.respond_to("GET DATA"), INVALID);
+    }
+}
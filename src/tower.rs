@@ -0,0 +1,468 @@
+//! Use [tower](https://docs.rs/tower) middleware with [`AGIHandler`]s.
+//!
+//! This module is only available with the `tower` feature.
+//!
+//! A handler becomes a [`Service`] taking an [`AGISession`], the connection and request of a
+//! single `FastAGI` session, with [`HandlerService`]. Any tower [`Layer`](tower_layer::Layer)
+//! for such services can be used with [`Router::layer`](crate::router::Router::layer) and
+//! [`Router::route_layer`](crate::router::Router::route_layer) by wrapping it in a
+//! [`TowerLayer`]:
+//! ```
+//! use std::time::Duration;
+//!
+//! use blazing_agi::{router::Router, tower::TowerLayer};
+//! use tower::{limit::ConcurrencyLimitLayer, timeout::TimeoutLayer, ServiceBuilder};
+//!
+//! let router = Router::new().layer(TowerLayer::new(
+//!     ServiceBuilder::new()
+//!         .layer(TimeoutLayer::new(Duration::from_secs(30)))
+//!         .layer(ConcurrencyLimitLayer::new(100)),
+//! ));
+//! ```
+//!
+//! The tower service is built once per [`TowerLayer`] and cloned for each session, so
+//! middleware sharing its state between clones (like the semaphore of a concurrency limit) sees
+//! all sessions of all handlers the layer is applied to. Create a separate [`TowerLayer`] for
+//! each route to limit them separately.
+//!
+//! Errors of tower middleware (like the `Elapsed` error of a timeout) are returned as
+//! [`AGIError::InnerError`]; errors of the handler itself are returned unchanged. Either way, the
+//! connection is handed back, so layers outside of the [`TowerLayer`] can still use it, e.g. to
+//! tell asterisk that the handler timed out.
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+};
+
+use tower_service::Service;
+
+use crate::{handler::AGIHandler, layer::Layer, AGIError, AGIRequest, Connection};
+
+/// The error type of tower middleware.
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Where a session started by a [`TowerLayer`] leaves its connection when it is dropped.
+type ConnectionSlot = Arc<Mutex<Option<Connection>>>;
+
+/// The future returned by a [`HandlerService`].
+pub type SessionFuture = Pin<Box<dyn Future<Output = Result<AGISession, AGIError>> + Send>>;
+
+/// A single `FastAGI` session, the request of a tower [`Service`] handling it.
+///
+/// The service returns the session when it is done, so the connection can be used afterwards.
+/// A session started by a [`TowerLayer`] hands its connection back to the layer when it is
+/// dropped, so the connection survives middleware errors as well.
+#[derive(Debug)]
+pub struct AGISession {
+    /// The connection to asterisk.
+    pub connection: Connection,
+    /// The request asterisk sent.
+    pub request: AGIRequest,
+    /// The handler [`Next`] runs, set by [`TowerLayer`].
+    next: Option<Arc<dyn AGIHandler>>,
+    /// Where the connection goes when the session is dropped, set by [`TowerLayer`].
+    slot: Option<ConnectionSlot>,
+}
+impl AGISession {
+    /// Create a session on `connection` for `request`.
+    pub fn new(connection: Connection, request: AGIRequest) -> Self {
+        Self {
+            connection,
+            request,
+            next: None,
+            slot: None,
+        }
+    }
+}
+impl Drop for AGISession {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            let connection = std::mem::replace(&mut self.connection, closed_connection());
+            *slot.lock().unwrap_or_else(PoisonError::into_inner) = Some(connection);
+        };
+    }
+}
+
+/// A connection nobody listens on, left behind where a connection was taken.
+fn closed_connection() -> Connection {
+    Connection::new(tokio::io::duplex(1).0)
+}
+
+/// A tower [`Service`] running an [`AGIHandler`] for each [`AGISession`].
+pub struct HandlerService<H> {
+    handler: Arc<H>,
+}
+impl<H> HandlerService<H> {
+    /// Create a service running `handler`.
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler: Arc::new(handler),
+        }
+    }
+}
+impl<H> Clone for HandlerService<H> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+        }
+    }
+}
+impl<H: AGIHandler> core::fmt::Debug for HandlerService<H> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "HandlerService({:?})", self.handler)
+    }
+}
+impl<H: AGIHandler + 'static> Service<AGISession> for HandlerService<H> {
+    type Response = AGISession;
+    type Error = AGIError;
+    type Future = SessionFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), AGIError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut session: AGISession) -> SessionFuture {
+        let handler = self.handler.clone();
+        Box::pin(async move {
+            handler
+                .handle(&mut session.connection, &session.request)
+                .await?;
+            Ok(session)
+        })
+    }
+}
+
+/// The innermost tower [`Service`] of a [`TowerLayer`], running the handler the layer was
+/// applied to.
+///
+/// This is only created by [`TowerLayer`]. Sessions not passed in by it fail with
+/// [`AGIError::ClientSideError`].
+#[derive(Clone, Debug)]
+pub struct Next {
+    _private: (),
+}
+impl Service<AGISession> for Next {
+    type Response = AGISession;
+    type Error = AGIError;
+    type Future = SessionFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), AGIError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut session: AGISession) -> SessionFuture {
+        Box::pin(async move {
+            let Some(handler) = session.next.clone() else {
+                return Err(AGIError::ClientSideError(
+                    "The session was not created by a TowerLayer".to_owned(),
+                ));
+            };
+            handler
+                .handle(&mut session.connection, &session.request)
+                .await?;
+            Ok(session)
+        })
+    }
+}
+
+/// Use a tower layer as a [`Layer`].
+///
+/// `S` is the service the tower layer builds around [`Next`]. It is built once, in
+/// [`new`](Self::new), and shared by all clones of this layer.
+pub struct TowerLayer<S> {
+    service: Arc<Mutex<S>>,
+}
+impl<S> TowerLayer<S> {
+    /// Wrap the tower layer `layer`.
+    pub fn new<L>(layer: L) -> Self
+    where
+        L: tower_layer::Layer<Next, Service = S>,
+    {
+        Self {
+            service: Arc::new(Mutex::new(layer.layer(Next { _private: () }))),
+        }
+    }
+}
+impl<S> Clone for TowerLayer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+        }
+    }
+}
+impl<S> core::fmt::Debug for TowerLayer<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "TowerLayer({})", std::any::type_name::<S>())
+    }
+}
+impl<S> Layer for TowerLayer<S>
+where
+    S: Service<AGISession, Response = AGISession> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    fn layer<H: AGIHandler + 'static>(&self, handler: H) -> Box<dyn AGIHandler> {
+        Box::new(ServiceHandler {
+            service: self.service.clone(),
+            handler: Arc::new(handler),
+        })
+    }
+}
+
+/// An [`AGIHandler`] calling a tower [`Service`], which runs `handler` in [`Next`].
+struct ServiceHandler<S> {
+    /// The service, cloned for each session.
+    service: Arc<Mutex<S>>,
+    handler: Arc<dyn AGIHandler>,
+}
+impl<S> core::fmt::Debug for ServiceHandler<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "ServiceHandler({}, {:?})",
+            std::any::type_name::<S>(),
+            self.handler
+        )
+    }
+}
+#[async_trait::async_trait]
+impl<S> AGIHandler for ServiceHandler<S>
+where
+    S: Service<AGISession, Response = AGISession> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+{
+    async fn handle(
+        &self,
+        connection: &mut Connection,
+        request: &AGIRequest,
+    ) -> Result<(), AGIError> {
+        let mut service = self
+            .service
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        // the session owns the connection while the service runs and puts it into the slot
+        // when it is dropped: when it is returned, or when the middleware fails or gives up
+        let slot = ConnectionSlot::default();
+        let session = AGISession {
+            connection: std::mem::replace(connection, closed_connection()),
+            request: request.clone(),
+            next: Some(self.handler.clone()),
+            slot: Some(slot.clone()),
+        };
+        let result = async {
+            std::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+            service.call(session).await
+        }
+        .await
+        .map(drop);
+        // a session kept by the middleware (e.g. one spawned into a task) does not come back
+        if let Some(session_connection) = slot.lock().unwrap_or_else(PoisonError::into_inner).take()
+        {
+            *connection = session_connection;
+        };
+        result.map_err(|e| match e.into().downcast::<AGIError>() {
+            Ok(e) => *e,
+            Err(e) => AGIError::InnerError(e),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tower::{limit::ConcurrencyLimitLayer, timeout::TimeoutLayer, ServiceBuilder};
+    use url::Url;
+
+    use super::*;
+    use crate::{command::Verbose, router::Router, AGIStatusGeneric, AGIVariableDump};
+
+    #[derive(Debug)]
+    struct Greet;
+    #[async_trait::async_trait]
+    impl AGIHandler for Greet {
+        async fn handle(&self, conn: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            conn.send_command(Verbose::new("hello".to_owned())).await?;
+            conn.send_command(Verbose::new("again".to_owned())).await?;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Slow;
+    #[async_trait::async_trait]
+    impl AGIHandler for Slow {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Failing;
+    #[async_trait::async_trait]
+    impl AGIHandler for Failing {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            Err(AGIError::ClientSideError("failing".to_owned()))
+        }
+    }
+
+    /// Records how many sessions run at once.
+    #[derive(Debug, Default)]
+    struct Tracked {
+        running: AtomicUsize,
+        max: AtomicUsize,
+    }
+    #[async_trait::async_trait]
+    impl AGIHandler for Arc<Tracked> {
+        async fn handle(&self, _: &mut Connection, _: &AGIRequest) -> Result<(), AGIError> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn request() -> AGIRequest {
+        AGIRequest {
+            variables: AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+            captures: std::collections::HashMap::new(),
+            wildcards: None,
+            host: None,
+            query: std::collections::HashMap::new(),
+            state: None,
+            peer_addr: None,
+            local_addr: None,
+            route: None,
+        }
+    }
+
+    /// Run `handler` on a connection nobody listens on.
+    async fn run(handler: Box<dyn AGIHandler>) -> Result<(), AGIError> {
+        let (_, server_side) = tokio::io::duplex(64);
+        handler
+            .handle(&mut Connection::new(server_side), &request())
+            .await
+    }
+
+    #[tokio::test]
+    async fn sessions_keep_their_connection() {
+        let router = Router::new()
+            .route("/", Greet)
+            .layer(TowerLayer::new(TimeoutLayer::new(Duration::from_secs(10))));
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { router.handle(server_side).await });
+        let session = crate::client::AGIClientSession::start(
+            client_side,
+            &AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+        )
+        .await
+        .unwrap();
+        let mut commands = vec![];
+        session
+            .run(|command| {
+                commands.push(command);
+                async { AGIStatusGeneric::Ok("1".to_owned(), None) }
+            })
+            .await
+            .unwrap();
+        server.await.unwrap();
+        assert_eq!(commands, vec!["VERBOSE \"hello\"", "VERBOSE \"again\""]);
+    }
+
+    #[tokio::test]
+    async fn outer_layers_keep_the_connection_on_errors() {
+        for handler in [Box::new(Slow) as Box<dyn AGIHandler>, Box::new(Failing)] {
+            let router = Router::new()
+                .route("/", handler)
+                .layer(TowerLayer::new(TimeoutLayer::new(Duration::from_millis(
+                    10,
+                ))))
+                .layer(crate::layer::after(|conn, _, result| {
+                    Box::pin(async move {
+                        conn.send_command(Verbose::new("failed".to_owned())).await?;
+                        result
+                    })
+                }));
+            let (client_side, server_side) = tokio::io::duplex(4096);
+            let server = tokio::spawn(async move { router.handle(server_side).await });
+            let session = crate::client::AGIClientSession::start(
+                client_side,
+                &AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+            )
+            .await
+            .unwrap();
+            let mut commands = vec![];
+            session
+                .run(|command| {
+                    commands.push(command);
+                    async { AGIStatusGeneric::Ok("1".to_owned(), None) }
+                })
+                .await
+                .unwrap();
+            server.await.unwrap();
+            assert_eq!(commands, vec!["VERBOSE \"failed\""]);
+        }
+    }
+
+    /// Run a session without commands against `router`.
+    async fn session(router: Arc<Router>) {
+        let (client_side, server_side) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move { router.handle(server_side).await });
+        crate::client::AGIClientSession::start(
+            client_side,
+            &AGIVariableDump::builder(Url::parse("agi://host/").unwrap()).build(),
+        )
+        .await
+        .unwrap()
+        .run(|_| async { AGIStatusGeneric::Ok("1".to_owned(), None) })
+        .await
+        .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn middleware_state_is_shared_between_sessions() {
+        let tracked = Arc::new(Tracked::default());
+        let router = Arc::new(
+            Router::new()
+                .route("/", tracked.clone())
+                .layer(TowerLayer::new(ConcurrencyLimitLayer::new(1))),
+        );
+        let sessions = (0..3)
+            .map(|_| tokio::spawn(session(router.clone())))
+            .collect::<Vec<_>>();
+        for session in sessions {
+            session.await.unwrap();
+        }
+        assert_eq!(tracked.max.load(Ordering::SeqCst), 1);
+        assert_eq!(tracked.running.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn middleware_errors_are_inner_errors() {
+        let layer = TowerLayer::new(
+            ServiceBuilder::new().layer(TimeoutLayer::new(Duration::from_millis(10))),
+        );
+        assert!(matches!(
+            run(layer.layer(Slow)).await,
+            Err(AGIError::InnerError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn handler_errors_are_kept() {
+        let layer = TowerLayer::new(TimeoutLayer::new(Duration::from_secs(10)));
+        assert!(matches!(
+            run(layer.layer(Failing)).await,
+            Err(AGIError::ClientSideError(x)) if x == "failing"
+        ));
+    }
+}
@@ -0,0 +1,824 @@
+//! Record what was exchanged on a [`Connection`](crate::connection::Connection) and replay it
+//! later.
+//!
+//! When a [`Router`] is configured with [`Router::record`], every inbound
+//! line (sent by asterisk) and every outbound command (sent by a handler) is handed to a
+//! [`TranscriptSink`], together with the time it was seen. Inbound lines are recorded before they
+//! are parsed, so data asterisk sent that could not be parsed is part of the transcript as well.
+//!
+//! A recorded [`Transcript`] can then be fed back into a [`Router`] with [`replay`]. We play the
+//! part of asterisk and check that the handlers send exactly the commands that were recorded.
+//! This makes it possible to turn a misbehaving production call into a regression test:
+//! ```ignore
+//! use blazing_agi::transcript::{replay, Transcript};
+//!
+//! #[tokio::test]
+//! async fn incident_1234() {
+//!     let transcript = include_str!("transcripts/1234.transcript")
+//!         .parse::<Transcript>()
+//!         .unwrap();
+//!     replay(&my_router(), &transcript).await.unwrap();
+//! }
+//! ```
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+#[cfg(feature = "tracing")]
+use tracing::warn;
+
+use crate::router::Router;
+
+/// How long [`replay`] waits for the handler to send the next command before giving up.
+const REPLAY_STEP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Size of the in-memory buffer between the replayed asterisk and the router.
+const REPLAY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Who sent a message in a transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent by the client (asterisk) to us.
+    Inbound,
+    /// Sent by us (a handler) to the client.
+    Outbound,
+}
+impl core::fmt::Display for Direction {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Inbound => {
+                write!(f, "in")
+            }
+            Self::Outbound => {
+                write!(f, "out")
+            }
+        }
+    }
+}
+impl FromStr for Direction {
+    type Err = TranscriptParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in" => Ok(Self::Inbound),
+            "out" => Ok(Self::Outbound),
+            x => Err(TranscriptParseError::UnknownDirection(x.to_owned())),
+        }
+    }
+}
+
+/// The ways in which parsing a [`Transcript`] can fail.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TranscriptParseError {
+    /// A line did not contain all of timestamp, direction and content.
+    IncompleteLine(String),
+    /// The timestamp of a line was not parsable.
+    TimestampUnparsable(String),
+    /// The direction of a line was neither `in` nor `out`.
+    UnknownDirection(String),
+    /// The content contained a `\` that did not start a known escape sequence.
+    InvalidEscape(String),
+}
+impl core::fmt::Display for TranscriptParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::IncompleteLine(x) => {
+                write!(f, "The transcript line {x} is incomplete.")
+            }
+            Self::TimestampUnparsable(x) => {
+                write!(f, "The value {x} is not parsable as a timestamp.")
+            }
+            Self::UnknownDirection(x) => {
+                write!(f, "The direction {x} is not known.")
+            }
+            Self::InvalidEscape(x) => {
+                write!(f, "The content {x} contains an invalid escape sequence.")
+            }
+        }
+    }
+}
+impl std::error::Error for TranscriptParseError {}
+
+/// A single message seen on a [`Connection`](crate::connection::Connection).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    /// When the message was seen.
+    pub timestamp: SystemTime,
+    /// Who sent the message.
+    pub direction: Direction,
+    /// The literal text of the message, exactly as it was on the wire.
+    pub content: String,
+}
+impl TranscriptEntry {
+    /// Create an entry for a message seen right now.
+    pub fn now(direction: Direction, content: String) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            direction,
+            content,
+        }
+    }
+}
+/// An entry is written as a single line:
+/// `{seconds since the epoch}.{microseconds} {in|out} {content}`, where newlines and backslashes
+/// in the content are escaped.
+impl core::fmt::Display for TranscriptEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let since_epoch = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        write!(
+            f,
+            "{}.{:06} {} {}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_micros(),
+            self.direction,
+            escape(&self.content)
+        )
+    }
+}
+impl FromStr for TranscriptEntry {
+    type Err = TranscriptParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ' ');
+        let (Some(timestamp), Some(direction), Some(content)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(TranscriptParseError::IncompleteLine(s.to_owned()));
+        };
+        let (secs, micros) =
+            timestamp
+                .split_once('.')
+                .ok_or(TranscriptParseError::TimestampUnparsable(
+                    timestamp.to_owned(),
+                ))?;
+        let secs = secs
+            .parse::<u64>()
+            .map_err(|_| TranscriptParseError::TimestampUnparsable(timestamp.to_owned()))?;
+        let micros = micros
+            .parse::<u64>()
+            .map_err(|_| TranscriptParseError::TimestampUnparsable(timestamp.to_owned()))?;
+        Ok(Self {
+            timestamp: UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_micros(micros),
+            direction: direction.parse()?,
+            content: unescape(content)?,
+        })
+    }
+}
+
+/// Escape a message so that it fits on a single line.
+fn escape(content: &str) -> String {
+    let mut res = String::with_capacity(content.len());
+    for c in content.chars() {
+        match c {
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            x => res.push(x),
+        }
+    }
+    res
+}
+
+/// Undo [`escape`].
+fn unescape(content: &str) -> Result<String, TranscriptParseError> {
+    let mut res = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        };
+        match chars.next() {
+            Some('\\') => res.push('\\'),
+            Some('n') => res.push('\n'),
+            Some('r') => res.push('\r'),
+            _ => return Err(TranscriptParseError::InvalidEscape(content.to_owned())),
+        };
+    }
+    Ok(res)
+}
+
+/// All messages seen on a single [`Connection`](crate::connection::Connection), in order.
+///
+/// The text representation contains one [`TranscriptEntry`] per line. Empty lines and lines
+/// starting with `#` are ignored when parsing, so recorded transcripts can be annotated.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub entries: Vec<TranscriptEntry>,
+}
+impl core::fmt::Display for Transcript {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for Transcript {
+    type Err = TranscriptParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            entries: s
+                .lines()
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Somewhere to record transcripts to.
+///
+/// A sink is shared between all connections of a [`Router`]. Each connection gets a unique
+/// `session` identifier, which is passed along with every entry.
+pub trait TranscriptSink: Send + Sync + core::fmt::Debug {
+    /// Record a single entry for the connection identified by `session`.
+    ///
+    /// This is called while the handler is running, so it should not block for long.
+    fn record(&self, session: &str, entry: TranscriptEntry);
+}
+
+/// Write one file per connection into a directory.
+///
+/// The file for a connection is called `{session}.transcript` and can be parsed as a
+/// [`Transcript`].
+///
+/// The files are written by a background task, started when the first entry is recorded, so
+/// handlers never wait for the disk.
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    directory: PathBuf,
+    /// Sends entries to the writing task.
+    writer: Arc<OnceLock<tokio::sync::mpsc::UnboundedSender<(String, TranscriptEntry)>>>,
+}
+impl DirectorySink {
+    /// Record transcripts into `directory`, which must already exist.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            writer: Arc::default(),
+        }
+    }
+}
+impl TranscriptSink for DirectorySink {
+    fn record(&self, session: &str, entry: TranscriptEntry) {
+        let writer = self.writer.get_or_init(|| {
+            let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+            tokio::spawn(write_entries(self.directory.clone(), receiver));
+            sender
+        });
+        // the task only stops once all senders are gone
+        let _ = writer.send((session.to_owned(), entry));
+    }
+}
+
+/// Append each entry from `receiver` to the file of its session in `directory`.
+async fn write_entries(
+    directory: PathBuf,
+    mut receiver: tokio::sync::mpsc::UnboundedReceiver<(String, TranscriptEntry)>,
+) {
+    while let Some((session, entry)) = receiver.recv().await {
+        let path = directory.join(format!("{session}.transcript"));
+        let written = async {
+            tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?
+                .write_all(format!("{entry}\n").as_bytes())
+                .await
+        }
+        .await;
+        #[cfg(feature = "tracing")]
+        if let Err(e) = written {
+            warn!("Unable to write transcript entry to {path:?}: {e}");
+        };
+        #[cfg(not(feature = "tracing"))]
+        let _ = written;
+    }
+}
+
+/// Send every entry into a [`tokio::sync::mpsc`] channel, tagged with its session.
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    sender: tokio::sync::mpsc::UnboundedSender<(String, TranscriptEntry)>,
+}
+impl ChannelSink {
+    /// Send entries into `sender`.
+    pub fn new(sender: tokio::sync::mpsc::UnboundedSender<(String, TranscriptEntry)>) -> Self {
+        Self { sender }
+    }
+}
+impl TranscriptSink for ChannelSink {
+    fn record(&self, session: &str, entry: TranscriptEntry) {
+        // a closed receiver simply means that nobody is interested in transcripts anymore
+        let _ = self.sender.send((session.to_owned(), entry));
+    }
+}
+
+/// Counts connections, so that session identifiers are unique within a process.
+static SESSION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The per-connection state required for recording.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    sink: Arc<dyn TranscriptSink>,
+    session: String,
+    /// Inbound data after the last complete line.
+    partial_line: String,
+}
+impl Recorder {
+    pub(crate) fn new(sink: Arc<dyn TranscriptSink>) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_millis();
+        let count = SESSION_COUNTER.fetch_add(1, Ordering::Relaxed);
+        Self {
+            sink,
+            session: format!("{started}-{count}"),
+            partial_line: String::new(),
+        }
+    }
+
+    /// Record `data` read from the client as one entry per complete line, before it is parsed.
+    pub(crate) fn inbound(&mut self, data: &str) {
+        self.partial_line.push_str(data);
+        while let Some(end) = self.partial_line.find('\n') {
+            let line = self.partial_line.drain(..=end).collect();
+            self.sink.record(
+                &self.session,
+                TranscriptEntry::now(Direction::Inbound, line),
+            );
+        }
+    }
+
+    /// Record inbound data that did not end in a newline, e.g. before the connection fails.
+    pub(crate) fn flush_inbound(&mut self) {
+        if !self.partial_line.is_empty() {
+            let rest = core::mem::take(&mut self.partial_line);
+            self.sink.record(
+                &self.session,
+                TranscriptEntry::now(Direction::Inbound, rest),
+            );
+        };
+    }
+
+    pub(crate) fn outbound(&self, content: &str) {
+        self.sink.record(
+            &self.session,
+            TranscriptEntry::now(Direction::Outbound, content.to_owned()),
+        );
+    }
+}
+
+/// The ways in which a [`replay`] can fail.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The handler sent (param `actual`) where the transcript contains (param `expected`).
+    Mismatch {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+    /// The handler closed the connection, but the transcript contains another command.
+    MissingCommand { index: usize, expected: String },
+    /// The handler sent more commands than the transcript contains.
+    UnexpectedCommands(String),
+    /// The handler did not send the command at this index in time.
+    Timeout { index: usize },
+    /// Reading from or writing to the in-memory stream failed.
+    Io(std::io::Error),
+}
+impl core::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Mismatch {
+                index,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "Entry {index}: expected the handler to send {expected:?}, but it sent {actual:?}"
+                )
+            }
+            Self::MissingCommand { index, expected } => {
+                write!(
+                    f,
+                    "Entry {index}: expected the handler to send {expected:?}, but it closed the connection"
+                )
+            }
+            Self::UnexpectedCommands(x) => {
+                write!(
+                    f,
+                    "The handler sent {x:?} after the transcript was finished"
+                )
+            }
+            Self::Timeout { index } => {
+                write!(
+                    f,
+                    "Entry {index}: the handler did not send a command in time"
+                )
+            }
+            Self::Io(x) => {
+                write!(f, "Unable to communicate with the router: {x}")
+            }
+        }
+    }
+}
+impl std::error::Error for ReplayError {}
+
+/// Feed a recorded [`Transcript`] into `router` and check that it sends the same commands.
+///
+/// We play the client side (asterisk): every inbound entry is sent to the router as-is, and for
+/// every outbound entry, we check that the router sends exactly that text.
+/// Timestamps are ignored.
+/// After the last entry, we close the connection like asterisk would when hanging up. Any
+/// command the router sends after that is reported as
+/// [`UnexpectedCommands`](ReplayError::UnexpectedCommands).
+///
+/// # Errors
+/// Returns an Error describing the first difference between the transcript and the behaviour
+/// of `router`.
+pub async fn replay(router: &Router, transcript: &Transcript) -> Result<(), ReplayError> {
+    replay_with_peer(router, transcript, None).await
+}
+
+/// Like [`replay`], but the session comes from `peer_addr`.
+///
+/// Use this to replay sessions against a router with an [`ip_filter`](Router::ip_filter) that
+/// allows only some networks. [`replay`] does not have a peer address, so such a router would
+/// drop the session.
+///
+/// # Errors
+/// Returns an Error describing the first difference between the transcript and the behaviour
+/// of `router`.
+pub async fn replay_from(
+    router: &Router,
+    transcript: &Transcript,
+    peer_addr: SocketAddr,
+) -> Result<(), ReplayError> {
+    replay_with_peer(router, transcript, Some(peer_addr)).await
+}
+
+async fn replay_with_peer(
+    router: &Router,
+    transcript: &Transcript,
+    peer_addr: Option<SocketAddr>,
+) -> Result<(), ReplayError> {
+    let (asterisk_side, router_side) = tokio::io::duplex(REPLAY_BUFFER_SIZE);
+    let ((), res) = tokio::join!(
+        router.handle_from(router_side, peer_addr, None),
+        play_client_side(asterisk_side, transcript)
+    );
+    res
+}
+
+/// Act like asterisk did in `transcript` on `stream`.
+async fn play_client_side(
+    stream: tokio::io::DuplexStream,
+    transcript: &Transcript,
+) -> Result<(), ReplayError> {
+    let (read_half, mut write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    for (index, entry) in transcript.entries.iter().enumerate() {
+        match entry.direction {
+            Direction::Inbound => {
+                write_half
+                    .write_all(entry.content.as_bytes())
+                    .await
+                    .map_err(ReplayError::Io)?;
+            }
+            Direction::Outbound => {
+                // commands are line based - read as many lines as the recorded command had
+                let mut actual = String::new();
+                for _ in 0..entry.content.matches('\n').count().max(1) {
+                    let bytes_read =
+                        tokio::time::timeout(REPLAY_STEP_TIMEOUT, reader.read_line(&mut actual))
+                            .await
+                            .map_err(|_| ReplayError::Timeout { index })?
+                            .map_err(ReplayError::Io)?;
+                    if bytes_read == 0 {
+                        break;
+                    };
+                }
+                if actual.is_empty() {
+                    return Err(ReplayError::MissingCommand {
+                        index,
+                        expected: entry.content.clone(),
+                    });
+                };
+                if actual != entry.content {
+                    return Err(ReplayError::Mismatch {
+                        index,
+                        expected: entry.content.clone(),
+                        actual,
+                    });
+                };
+            }
+        }
+    }
+    // hang up and make sure that nothing else is sent
+    write_half.shutdown().await.map_err(ReplayError::Io)?;
+    let mut rest = String::new();
+    tokio::time::timeout(REPLAY_STEP_TIMEOUT, reader.read_to_string(&mut rest))
+        .await
+        .map_err(|_| ReplayError::Timeout {
+            index: transcript.entries.len(),
+        })?
+        .map_err(ReplayError::Io)?;
+    if rest.is_empty() {
+        Ok(())
+    } else {
+        Err(ReplayError::UnexpectedCommands(rest))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        command::{Answer, Verbose},
+        connection::Connection,
+        handler::AGIHandler,
+        AGIError, AGIRequest,
+    };
+
+    use super::*;
+
+    const NETWORK_START: &str = "agi_network: yes\n";
+    const VARIABLE_DUMP: &str = "agi_network_script: script\n\
+        agi_request: agi://some.host/script\n\
+        agi_channel: SIP/marcelog-e00d2760\n\
+        agi_language: en\n\
+        agi_type: SIP\n\
+        agi_uniqueid: 1297542965.8\n\
+        agi_version: 1.6.0.9\n\
+        agi_callerid: marcelog\n\
+        agi_calleridname: marcelog@mg\n\
+        agi_callingpres: 0\n\
+        agi_callingani2: 0\n\
+        agi_callington: 0\n\
+        agi_callingtns: 0\n\
+        agi_dnid: 667\n\
+        agi_rdnis: unknown\n\
+        agi_context: default\n\
+        agi_extension: 667\n\
+        agi_priority: 2\n\
+        agi_enhanced: 0.0\n\
+        agi_accountcode: \n\
+        agi_threadid: 1104922960\n\n";
+
+    #[derive(Debug)]
+    struct AnswerAndGreet {}
+    #[async_trait::async_trait]
+    impl AGIHandler for AnswerAndGreet {
+        async fn handle(
+            &self,
+            connection: &mut Connection,
+            _: &AGIRequest,
+        ) -> Result<(), AGIError> {
+            connection.send_command(Answer::new()).await?;
+            connection
+                .send_command(Verbose::new("Hello There".to_owned()))
+                .await?;
+            Ok(())
+        }
+    }
+
+    fn entry(direction: Direction, content: &str) -> TranscriptEntry {
+        TranscriptEntry::now(direction, content.to_owned())
+    }
+
+    /// The text sent in each direction, with consecutive entries in the same direction joined.
+    fn joined<I: IntoIterator<Item = (Direction, String)>>(entries: I) -> Vec<(Direction, String)> {
+        let mut res = Vec::<(Direction, String)>::new();
+        for (direction, content) in entries {
+            match res.last_mut() {
+                Some(last) if last.0 == direction => last.1.push_str(&content),
+                _ => res.push((direction, content)),
+            };
+        }
+        res
+    }
+
+    /// Replay `transcript` into `router`, recording it, and return what was recorded.
+    async fn record(router: Router, transcript: &Transcript) -> Vec<(Direction, String)> {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let router = router.record(ChannelSink::new(sender));
+        let _ = replay(&router, transcript).await;
+        drop(router);
+        let mut recorded = vec![];
+        while let Some((_, entry)) = receiver.recv().await {
+            recorded.push((entry.direction, entry.content));
+        }
+        recorded
+    }
+
+    #[test]
+    fn entry_roundtrip() {
+        let original = TranscriptEntry {
+            timestamp: UNIX_EPOCH + Duration::from_micros(1_729_250_000_123_456),
+            direction: Direction::Inbound,
+            content: "agi_network: yes\nback\\slash\n".to_owned(),
+        };
+        let as_string = original.to_string();
+        assert_eq!(
+            as_string,
+            "1729250000.123456 in agi_network: yes\\nback\\\\slash\\n"
+        );
+        assert_eq!(as_string.parse::<TranscriptEntry>(), Ok(original));
+    }
+
+    #[test]
+    fn entry_invalid_escape() {
+        assert_eq!(
+            "1.000000 out VERBOSE \\x".parse::<TranscriptEntry>(),
+            Err(TranscriptParseError::InvalidEscape(
+                "VERBOSE \\x".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn entry_unknown_direction() {
+        assert_eq!(
+            "1.000000 sideways ANSWER\\n".parse::<TranscriptEntry>(),
+            Err(TranscriptParseError::UnknownDirection(
+                "sideways".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn transcript_skips_comments() {
+        let transcript = "# the caller hung up here\n\n1.000001 out ANSWER\\n\n"
+            .parse::<Transcript>()
+            .unwrap();
+        assert_eq!(transcript.entries.len(), 1);
+        assert_eq!(transcript.entries[0].content, "ANSWER\n");
+    }
+
+    #[tokio::test]
+    async fn replay_from_peer() {
+        let router = Router::new()
+            .route("/script", AnswerAndGreet {})
+            .ip_filter(crate::ip_filter::IpFilter::new().allow("10.0.0.0/8"));
+        let transcript = Transcript {
+            entries: vec![
+                entry(Direction::Inbound, NETWORK_START),
+                entry(Direction::Inbound, VARIABLE_DUMP),
+                entry(Direction::Outbound, "ANSWER\n"),
+                entry(Direction::Inbound, "200 result=0\n"),
+                entry(Direction::Outbound, "VERBOSE \"Hello There\"\n"),
+                entry(Direction::Inbound, "200 result=1\n"),
+            ],
+        };
+        replay_from(&router, &transcript, "10.0.0.2:5000".parse().unwrap())
+            .await
+            .unwrap();
+        assert!(replay(&router, &transcript).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn record_session() {
+        let router = Router::new().route("/script", AnswerAndGreet {});
+        let transcript = Transcript {
+            entries: vec![
+                entry(Direction::Inbound, NETWORK_START),
+                entry(Direction::Inbound, VARIABLE_DUMP),
+                entry(Direction::Outbound, "ANSWER\n"),
+                entry(Direction::Inbound, "200 result=0\n"),
+                entry(Direction::Outbound, "VERBOSE \"Hello There\"\n"),
+                entry(Direction::Inbound, "200 result=1\n"),
+            ],
+        };
+        replay(&router, &transcript).await.unwrap();
+        let recorded = record(router, &transcript).await;
+        // inbound data is recorded line by line
+        assert_eq!(
+            recorded.len(),
+            transcript.entries.len() + VARIABLE_DUMP.matches('\n').count() - 1
+        );
+        assert_eq!(
+            joined(recorded),
+            joined(
+                transcript
+                    .entries
+                    .into_iter()
+                    .map(|e| (e.direction, e.content))
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn record_malformed_data() {
+        let router = Router::new().route("/script", AnswerAndGreet {});
+        let transcript = Transcript {
+            entries: vec![
+                entry(Direction::Inbound, NETWORK_START),
+                entry(Direction::Inbound, "agi_request\n\ntrailing"),
+            ],
+        };
+        assert_eq!(
+            record(router, &transcript).await,
+            vec![
+                (Direction::Inbound, NETWORK_START.to_owned()),
+                (Direction::Inbound, "agi_request\n".to_owned()),
+                (Direction::Inbound, "\n".to_owned()),
+                (Direction::Inbound, "trailing".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn directory_sink_writes_files() {
+        let directory = std::env::temp_dir().join(format!(
+            "blazing_agi_transcripts_{}",
+            SESSION_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        let sink = DirectorySink::new(&directory);
+        sink.record("session", entry(Direction::Outbound, "ANSWER\n"));
+        sink.record("session", entry(Direction::Inbound, "200 result=0\n"));
+        let path = directory.join("session.transcript");
+        let mut written = Transcript::default();
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            written = tokio::fs::read_to_string(&path)
+                .await
+                .unwrap_or_default()
+                .parse()
+                .unwrap();
+            if written.entries.len() == 2 {
+                break;
+            };
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            written
+                .entries
+                .into_iter()
+                .map(|e| (e.direction, e.content))
+                .collect::<Vec<_>>(),
+            vec![
+                (Direction::Outbound, "ANSWER\n".to_owned()),
+                (Direction::Inbound, "200 result=0\n".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_detects_mismatch() {
+        let router = Router::new().route("/script", AnswerAndGreet {});
+        let transcript = Transcript {
+            entries: vec![
+                entry(Direction::Inbound, NETWORK_START),
+                entry(Direction::Inbound, VARIABLE_DUMP),
+                entry(Direction::Outbound, "ANSWER\n"),
+                entry(Direction::Inbound, "200 result=0\n"),
+                entry(Direction::Outbound, "VERBOSE \"General Kenobi\"\n"),
+            ],
+        };
+        match replay(&router, &transcript).await {
+            Err(ReplayError::Mismatch {
+                index,
+                expected,
+                actual,
+            }) => {
+                assert_eq!(index, 4);
+                assert_eq!(expected, "VERBOSE \"General Kenobi\"\n");
+                assert_eq!(actual, "VERBOSE \"Hello There\"\n");
+            }
+            x => panic!("Expected a mismatch, got {x:?}"),
+        };
+    }
+
+    #[tokio::test]
+    async fn replay_detects_unexpected_commands() {
+        let router = Router::new().route("/script", AnswerAndGreet {});
+        let transcript = Transcript {
+            entries: vec![
+                entry(Direction::Inbound, NETWORK_START),
+                entry(Direction::Inbound, VARIABLE_DUMP),
+                entry(Direction::Outbound, "ANSWER\n"),
+                entry(Direction::Inbound, "200 result=0\n"),
+            ],
+        };
+        // after the transcript ends, the handler still tries to send VERBOSE
+        match replay(&router, &transcript).await {
+            Err(ReplayError::UnexpectedCommands(x)) => {
+                assert_eq!(x, "VERBOSE \"Hello There\"\n");
+            }
+            x => panic!("Expected unexpected commands, got {x:?}"),
+        };
+    }
+}